@@ -1,8 +1,45 @@
 #version 460
 
 layout (location = 0) in vec4 out_color;
+layout (location = 1) in vec3 out_normal;
+layout (set = 0, binding = 0) uniform FrameUniforms {
+    mat4 view;
+    mat4 proj;
+    vec4 light_direction;
+} frame;
 layout (location = 0) out vec4 frag_color;
+// sampled once vertices carry texture coordinates, sRGB textures are decoded to linear
+layout (set = 0, binding = 1) uniform sampler2D texture_sampler;
+// graphics_pipeline_components::FragmentPushConstants
+layout (push_constant) uniform FragmentPushConstants {
+    vec4 tint;
+} push;
+
+// shaders::LIGHTING_ENABLED_CONSTANT_ID, false outputs the unlit color
+layout (constant_id = 0) const bool LIGHTING_ENABLED = true;
+
+const float AMBIENT = 0.1;
+
+// shaders::ENCODE_SRGB_CONSTANT_ID, colors are linear and only encoded here when the color
+// attachment format doesn't encode them on write
+layout (constant_id = 2) const bool ENCODE_SRGB = false;
+
+vec4 encode_output(vec4 linear) {
+    if (!ENCODE_SRGB) {
+        return linear;
+    }
+    bvec3 low = lessThanEqual(linear.rgb, vec3(0.0031308));
+    vec3 encoded = mix(1.055 * pow(linear.rgb, vec3(1.0 / 2.4)) - 0.055, linear.rgb * 12.92, low);
+    return vec4(encoded, linear.a);
+}
 
 void main() {
-    frag_color = out_color;
+    vec4 color = out_color * push.tint;
+    if (!LIGHTING_ENABLED) {
+        frag_color = encode_output(color);
+        return;
+    }
+    // lambert
+    float diffuse = max(dot(normalize(out_normal), -frame.light_direction.xyz), 0.0);
+    frag_color = encode_output(vec4(color.rgb * (AMBIENT + (1.0 - AMBIENT) * diffuse), color.a));
 }
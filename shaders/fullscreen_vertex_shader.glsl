@@ -0,0 +1,18 @@
+#version 460
+
+layout (set = 0, binding = 0) uniform FrameUniforms {
+    mat4 view;
+    mat4 proj;
+    vec4 light_direction;
+} frame;
+
+// Procedural geometry, no vertex buffer bound. Vertices 0..3 form a triangle covering the whole framebuffer.
+layout (location = 0) out vec4 out_color;
+layout (location = 1) out vec3 out_normal;
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    out_color = vec4(uv * 0.5, 0.5, 1);
+    // facing the light so the fragment shader leaves the color unchanged
+    out_normal = -frame.light_direction.xyz;
+    gl_Position = vec4(uv * 2 - 1, 0, 1);
+}
@@ -0,0 +1,85 @@
+#version 460
+
+// Post effect smoothing edges like FXAA 3.11, but blending a fixed half texel across each edge
+// instead of searching for its ends. Tuned at runtime with Renderer::set_fxaa_params.
+layout (set = 0, binding = 0) uniform sampler2D scene;
+layout (set = 0, binding = 1) uniform PostEffectParams {
+    // edge_threshold, edge_threshold_min, subpixel, unused
+    vec4 values;
+} params;
+layout (location = 0) in vec2 in_uv;
+layout (location = 0) out vec4 out_color;
+
+// shaders::ENCODE_SRGB_CONSTANT_ID, the scene is linear and only encoded here when the swapchain
+// format doesn't encode it on write
+layout (constant_id = 2) const bool ENCODE_SRGB = false;
+
+vec4 encode_output(vec4 linear) {
+    if (!ENCODE_SRGB) {
+        return linear;
+    }
+    bvec3 low = lessThanEqual(linear.rgb, vec3(0.0031308));
+    vec3 encoded = mix(1.055 * pow(linear.rgb, vec3(1.0 / 2.4)) - 0.055, linear.rgb * 12.92, low);
+    return vec4(encoded, linear.a);
+}
+
+// the thresholds are tuned for perceptual luma, sqrt approximates the sRGB curve
+float luma_of(vec3 rgb) {
+    return sqrt(dot(rgb, vec3(0.299, 0.587, 0.114)));
+}
+
+float luma(vec2 uv) {
+    return luma_of(texture(scene, uv).rgb);
+}
+
+void main() {
+    float edge_threshold = params.values.x;
+    float edge_threshold_min = params.values.y;
+    float subpixel = params.values.z;
+    vec2 texel = 1.0 / vec2(textureSize(scene, 0));
+
+    vec4 center = texture(scene, in_uv);
+    float luma_center = luma_of(center.rgb);
+    float luma_up = luma(in_uv + vec2(0, -texel.y));
+    float luma_down = luma(in_uv + vec2(0, texel.y));
+    float luma_left = luma(in_uv + vec2(-texel.x, 0));
+    float luma_right = luma(in_uv + vec2(texel.x, 0));
+    float luma_min = min(luma_center, min(min(luma_up, luma_down), min(luma_left, luma_right)));
+    float luma_max = max(luma_center, max(max(luma_up, luma_down), max(luma_left, luma_right)));
+    float contrast = luma_max - luma_min;
+    // too little contrast to be a visible edge
+    if (contrast < max(edge_threshold_min, luma_max * edge_threshold)) {
+        out_color = encode_output(center);
+        return;
+    }
+
+    float luma_up_left = luma(in_uv + vec2(-texel.x, -texel.y));
+    float luma_up_right = luma(in_uv + vec2(texel.x, -texel.y));
+    float luma_down_left = luma(in_uv + vec2(-texel.x, texel.y));
+    float luma_down_right = luma(in_uv + vec2(texel.x, texel.y));
+
+    // single pixel details differ from their whole neighbourhood rather than along a line
+    float neighbourhood = (2.0 * (luma_up + luma_down + luma_left + luma_right) + luma_up_left
+        + luma_up_right + luma_down_left + luma_down_right) / 12.0;
+    float subpixel_blend =
+        smoothstep(0.0, 1.0, clamp(abs(neighbourhood - luma_center) / contrast, 0.0, 1.0));
+    subpixel_blend = subpixel_blend * subpixel_blend * subpixel;
+
+    float horizontal = 2.0 * abs(luma_up + luma_down - 2.0 * luma_center)
+        + abs(luma_up_left + luma_down_left - 2.0 * luma_left)
+        + abs(luma_up_right + luma_down_right - 2.0 * luma_right);
+    float vertical = 2.0 * abs(luma_left + luma_right - 2.0 * luma_center)
+        + abs(luma_up_left + luma_up_right - 2.0 * luma_up)
+        + abs(luma_down_left + luma_down_right - 2.0 * luma_down);
+    bool is_horizontal = horizontal >= vertical;
+
+    // steps across the edge towards the side with the larger luma difference
+    vec2 step_across = is_horizontal ? vec2(0, texel.y) : vec2(texel.x, 0);
+    float luma_negative = is_horizontal ? luma_up : luma_left;
+    float luma_positive = is_horizontal ? luma_down : luma_right;
+    if (abs(luma_negative - luma_center) > abs(luma_positive - luma_center)) {
+        step_across = -step_across;
+    }
+    float blend = max(0.5, subpixel_blend);
+    out_color = encode_output(vec4(texture(scene, in_uv + step_across * blend).rgb, center.a));
+}
@@ -0,0 +1,18 @@
+#version 460
+
+layout (location = 0) in vec3 position;
+layout (location = 1) in vec4 color;
+layout (set = 0, binding = 0) uniform FrameUniforms {
+    mat4 view;
+    mat4 proj;
+    vec4 light_direction;
+} frame;
+
+// Grid and axis lines already in world space, drawn unlit with the main fragment shader
+layout (location = 0) out vec4 out_color;
+layout (location = 1) out vec3 out_normal;
+void main() {
+    out_color = color;
+    out_normal = vec3(0);
+    gl_Position = frame.proj * frame.view * vec4(position, 1);
+}
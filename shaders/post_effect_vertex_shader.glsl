@@ -0,0 +1,19 @@
+#version 460
+
+// Procedural fullscreen triangle like fullscreen_vertex_shader.glsl for the post effect pass.
+// Post effect fragment shaders sample the rendered scene, in linear color, at the interpolated
+// uv:
+//   layout (set = 0, binding = 0) uniform sampler2D scene;
+//   layout (location = 0) in vec2 in_uv;
+//   layout (location = 0) out vec4 out_color;
+// and can read the values set with Renderer::set_post_effect_params:
+//   layout (set = 0, binding = 1) uniform PostEffectParams { vec4 values; } params;
+// Their output is linear too. When the swapchain format doesn't encode it on write the shader
+// has to sRGB encode it itself, which the pass signals with shaders::ENCODE_SRGB_CONSTANT_ID:
+//   layout (constant_id = 2) const bool ENCODE_SRGB = false;
+layout (location = 0) out vec2 out_uv;
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    out_uv = uv;
+    gl_Position = vec4(uv * 2 - 1, 0, 1);
+}
@@ -0,0 +1,23 @@
+#version 460
+
+layout (location = 0) in vec3 out_direction;
+// written by Renderer::set_skybox, the skybox pipeline is only used once it is
+layout (set = 0, binding = 2) uniform samplerCube skybox_sampler;
+layout (location = 0) out vec4 frag_color;
+
+// shaders::ENCODE_SRGB_CONSTANT_ID, colors are linear and only encoded here when the color
+// attachment format doesn't encode them on write
+layout (constant_id = 2) const bool ENCODE_SRGB = false;
+
+vec4 encode_output(vec4 linear) {
+    if (!ENCODE_SRGB) {
+        return linear;
+    }
+    bvec3 low = lessThanEqual(linear.rgb, vec3(0.0031308));
+    vec3 encoded = mix(1.055 * pow(linear.rgb, vec3(1.0 / 2.4)) - 0.055, linear.rgb * 12.92, low);
+    return vec4(encoded, linear.a);
+}
+
+void main() {
+    frag_color = encode_output(texture(skybox_sampler, out_direction));
+}
@@ -0,0 +1,21 @@
+#version 460
+
+layout (set = 0, binding = 0) uniform FrameUniforms {
+    mat4 view;
+    mat4 proj;
+    vec4 light_direction;
+} frame;
+
+// shaders::SKYBOX_DEPTH_CONSTANT_ID, the depth clear value so only pixels without geometry pass
+layout (constant_id = 1) const float SKYBOX_DEPTH = 1.0;
+
+// Procedural fullscreen triangle like fullscreen_vertex_shader.glsl, carrying the world space
+// direction the camera looks along through each vertex
+layout (location = 0) out vec3 out_direction;
+void main() {
+    vec2 position = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2 - 1;
+    vec4 view_position = inverse(frame.proj) * vec4(position, 1, 1);
+    // rotation only, the skybox stays put as the camera moves
+    out_direction = inverse(mat3(frame.view)) * view_position.xyz;
+    gl_Position = vec4(position, SKYBOX_DEPTH, 1);
+}
@@ -2,14 +2,26 @@
 
 layout (location = 0) in vec3 position;
 layout (location = 1) in vec4 color;
-layout (set = 0, binding = 0) uniform UniformBufferObject {
-    mat4 model;
+layout (location = 2) in vec3 normal;
+// per instance, one location per column so they take up locations 3 through 9
+layout (location = 3) in mat4 instance_model;
+layout (location = 7) in mat3 instance_normal_matrix;
+layout (set = 0, binding = 0) uniform FrameUniforms {
     mat4 view;
     mat4 proj;
-} ubo;
+    vec4 light_direction;
+} frame;
+layout (set = 1, binding = 0) uniform ObjectUniforms {
+    mat4 model;
+    mat4 normal_matrix;
+} object;
 
 layout (location = 0) out vec4 out_color;
+layout (location = 1) out vec3 out_normal;
 void main() {
     out_color = color;
-    gl_Position =  ubo.proj * ubo.view * ubo.model * vec4(position, 1);
+    out_normal = mat3(object.normal_matrix) * instance_normal_matrix * normal;
+    gl_Position =  frame.proj * frame.view * object.model * instance_model * vec4(position, 1);
+    // only read for the POINT_LIST topology, where it has to be written
+    gl_PointSize = 1.0;
 }
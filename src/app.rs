@@ -1,82 +1,257 @@
-use winit::event::{DeviceEvent, WindowEvent};
+use std::time::{Duration, Instant};
 
-use crate::renderer::{self, camera::{self, CameraController}, Renderer};
+use nalgebra::Matrix4;
+use winit::{
+    event::{DeviceEvent, ElementState, MouseButton, MouseScrollDelta, WindowEvent},
+    event_loop::ControlFlow,
+    keyboard::KeyCode,
+    window::Fullscreen,
+};
+
+#[cfg(feature = "gamepad")]
+use crate::gamepad::Gamepad;
+use crate::renderer::{
+    self,
+    camera::{self, CameraController, KeyBindings},
+    Renderer,
+};
+
+// toggles borderless fullscreen on the current monitor
+const FULLSCREEN_KEY: KeyCode = KeyCode::F11;
+// releases the cursor grabbed by clicking into the window
+const RELEASE_CURSOR_KEY: KeyCode = KeyCode::Escape;
+// touchpads report pixels, roughly how many make up one scroll line
+const PIXELS_PER_SCROLL_LINE: f32 = 20.0;
+// longest step a camera update takes, e.g. the first one after an on demand renderer sat idle
+const MAX_CAMERA_DELTA_TIME: Duration = Duration::from_millis(100);
+// gamepads aren't winit events, so an on demand event loop still wakes up this often to poll them
+#[cfg(feature = "gamepad")]
+const GAMEPAD_POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderMode {
+    // a new frame as soon as the last one is done
+    #[default]
+    Continuous,
+    // only redraws once something changed, the event loop waits for events in between
+    OnDemand,
+}
+
+impl RenderMode {
+    pub fn control_flow(self) -> ControlFlow {
+        match self {
+            RenderMode::Continuous => ControlFlow::Poll,
+            RenderMode::OnDemand => ControlFlow::Wait,
+        }
+    }
+}
 
 pub struct App {
     pub renderer: Option<Renderer>,
     pub camera: Option<camera::Camera>,
     pub camera_controller: Option<CameraController>,
+    pub key_bindings: KeyBindings,
+    #[cfg(feature = "gamepad")]
+    pub gamepad: Option<Gamepad>,
     pub renderer_user_settings: renderer::UserSettings,
+    pub render_mode: RenderMode,
+    // of the window's display, mouse deltas are divided by it so sensitivity is in logical pixels
+    pub scale_factor: f64,
+    // look mode, the cursor is hidden and held in the window while mouse motion turns the camera
+    pub cursor_grabbed: bool,
+    // something changed since the last frame, only read in RenderMode::OnDemand
+    pub dirty: bool,
+    // the camera's delta time is measured from it
+    pub last_camera_update: Instant,
+}
+
+impl App {
+    fn set_cursor_grab(&mut self, grab: bool) {
+        self.cursor_grabbed = self.renderer.as_ref().unwrap().set_cursor_grab(grab);
+    }
+    // Call after changing anything drawn, e.g. the meshes, so an on demand renderer redraws
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+        if let Some(renderer) = &self.renderer {
+            renderer.request_redraw();
+        }
+    }
 }
 
 impl winit::application::ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        self.renderer = Some(Renderer::new(&event_loop, &self.renderer_user_settings));
+        self.renderer = Some(Renderer::new(event_loop, &self.renderer_user_settings));
+        self.scale_factor = self.renderer.as_ref().unwrap().scale_factor();
         self.camera = Some(camera::Camera::new());
-        self.camera_controller = Some(CameraController::new(0.01, 0.01));
-        self.renderer.as_ref().unwrap().request_redraw();
+        // a loaded model can be anywhere, so start out looking at all of it
+        if self.renderer_user_settings.model_path.is_some() {
+            let renderer = self.renderer.as_ref().unwrap();
+            if let Some(bounds) = renderer.mesh_bounds(renderer::DEFAULT_MESH_ID) {
+                self.camera.as_mut().unwrap().frame_bounds(
+                    bounds.min,
+                    bounds.max,
+                    renderer.aspect_ratio(),
+                );
+            }
+        }
+        self.camera_controller = Some(CameraController::new(0.6, 0.01));
+        #[cfg(feature = "gamepad")]
+        {
+            self.gamepad = Gamepad::new();
+        }
+        self.mark_dirty();
     }
 
     fn device_event(
         &mut self,
-        event_loop: &winit::event_loop::ActiveEventLoop,
-        device_id: winit::event::DeviceId,
+        _event_loop: &winit::event_loop::ActiveEventLoop,
+        _device_id: winit::event::DeviceId,
         event: winit::event::DeviceEvent,
     ) {
         match event {
-            DeviceEvent::MouseMotion { delta } => {
+            // raw motion arrives wherever the pointer is, so it only turns the camera in look mode
+            DeviceEvent::MouseMotion { delta } if self.cursor_grabbed => {
                 let camera_controller = self.camera_controller.as_mut().unwrap();
-                camera_controller.mouse_delta_x += delta.0 as f32;
-                camera_controller.mouse_delta_y += delta.1 as f32;
+                // winit reports physical pixels
+                camera_controller.mouse_delta_x += (delta.0 / self.scale_factor) as f32;
+                camera_controller.mouse_delta_y += (delta.1 / self.scale_factor) as f32;
+                self.mark_dirty();
             }
             _ => (),
         }
     }
 
+    #[cfg(feature = "gamepad")]
+    fn about_to_wait(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        if let (Some(gamepad), Some(camera_controller)) =
+            (self.gamepad.as_mut(), self.camera_controller.as_mut())
+        {
+            gamepad.update_camera_controller(camera_controller);
+            if self.render_mode == RenderMode::OnDemand {
+                event_loop.set_control_flow(ControlFlow::wait_duration(GAMEPAD_POLL_INTERVAL));
+            }
+            if camera_controller.is_active() && !self.dirty {
+                self.mark_dirty();
+            }
+        }
+    }
+
     fn window_event(
         &mut self,
         event_loop: &winit::event_loop::ActiveEventLoop,
-        window_id: winit::window::WindowId,
+        _window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
         match event {
             WindowEvent::CloseRequested => {
                 event_loop.exit();
             }
-            WindowEvent::Resized(_) => {
+            WindowEvent::Resized(size) => {
                 self.renderer
                     .as_mut()
                     .unwrap()
-                    .resize_dependent_component_rebuild_needed = true;
+                    .window_resized(size.width, size.height);
+                self.mark_dirty();
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.scale_factor = scale_factor;
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if !self.cursor_grabbed => {
+                self.set_cursor_grab(true);
+            }
+            // the grab would otherwise stay on while another window has focus
+            WindowEvent::Focused(false) if self.cursor_grabbed => {
+                self.set_cursor_grab(false);
+            }
+            WindowEvent::CursorMoved {
+                device_id: _,
+                position,
+            } => {
+                self.renderer
+                    .as_mut()
+                    .unwrap()
+                    .set_cursor_position(position.x as f32, position.y as f32);
+            }
+            WindowEvent::Occluded(false) => {
+                self.mark_dirty();
             }
             WindowEvent::KeyboardInput {
                 device_id: _,
                 event,
                 is_synthetic: _,
             } => {
-                use winit::keyboard::{KeyCode, PhysicalKey};
-                let is_pressed = event.state.is_pressed();
-                let camera_controller = self.camera_controller.as_mut().unwrap();
-                match event.physical_key {
-                    PhysicalKey::Code(KeyCode::KeyA) | PhysicalKey::Code(KeyCode::ArrowLeft) => {
-                        camera_controller.left_pressed = is_pressed;
-                    }
-                    PhysicalKey::Code(KeyCode::KeyD) | PhysicalKey::Code(KeyCode::ArrowRight) => {
-                        camera_controller.right_pressed = is_pressed;
+                use winit::keyboard::PhysicalKey;
+                if event.physical_key == PhysicalKey::Code(FULLSCREEN_KEY)
+                    && event.state.is_pressed()
+                    && !event.repeat
+                {
+                    let renderer = self.renderer.as_mut().unwrap();
+                    let fullscreen = match renderer.fullscreen() {
+                        Some(_) => None,
+                        None => Some(Fullscreen::Borderless(None)),
+                    };
+                    renderer.set_fullscreen(fullscreen);
+                    self.mark_dirty();
+                } else if event.physical_key == PhysicalKey::Code(RELEASE_CURSOR_KEY)
+                    && event.state.is_pressed()
+                    && self.cursor_grabbed
+                {
+                    self.set_cursor_grab(false);
+                } else if let PhysicalKey::Code(key_code) = event.physical_key {
+                    if let Some(action) = self.key_bindings.action(key_code) {
+                        self.camera_controller
+                            .as_mut()
+                            .unwrap()
+                            .set_pressed(action, event.state.is_pressed());
+                        self.mark_dirty();
                     }
-                    PhysicalKey::Code(KeyCode::KeyS) | PhysicalKey::Code(KeyCode::ArrowDown) => {
-                        camera_controller.backward_pressed = is_pressed;
-                    }
-                    PhysicalKey::Code(KeyCode::KeyW) | PhysicalKey::Code(KeyCode::ArrowUp) => {
-                        camera_controller.forward_pressed = is_pressed;
-                    }
-                    _ => (),
                 }
             }
+            WindowEvent::MouseWheel {
+                device_id: _,
+                delta,
+                phase: _,
+            } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => lines,
+                    MouseScrollDelta::PixelDelta(position) => {
+                        position.y as f32 / PIXELS_PER_SCROLL_LINE
+                    }
+                };
+                self.camera_controller.as_mut().unwrap().scroll_delta += lines;
+                self.mark_dirty();
+            }
             WindowEvent::RedrawRequested => {
-                self.camera_controller.as_mut().unwrap().update_camera(self.camera.as_mut().unwrap());
-                self.renderer.as_mut().unwrap().draw_frame(self.camera.as_ref().unwrap());
-                self.renderer.as_ref().unwrap().request_redraw();
+                let now = Instant::now();
+                let delta_time = now
+                    .duration_since(self.last_camera_update)
+                    .min(MAX_CAMERA_DELTA_TIME)
+                    .as_secs_f32();
+                self.last_camera_update = now;
+                self.camera_controller
+                    .as_mut()
+                    .unwrap()
+                    .update_camera(self.camera.as_mut().unwrap(), delta_time);
+                let renderer = self.renderer.as_mut().unwrap();
+                if let Err(error) =
+                    renderer.draw_frame(self.camera.as_ref().unwrap(), &[Matrix4::identity()])
+                {
+                    eprintln!("{error}, recreating the device");
+                    if let Err(error) = renderer.recreate_device(&self.renderer_user_settings) {
+                        eprintln!("Failed to recreate the device: {error}");
+                        event_loop.exit();
+                        return;
+                    }
+                }
+                // held keys and sticks keep moving the camera, and with it the next frame
+                self.dirty = self.camera_controller.as_ref().unwrap().is_active();
+                if self.render_mode == RenderMode::Continuous || self.dirty {
+                    self.renderer.as_ref().unwrap().request_redraw();
+                }
             }
             _ => (),
         }
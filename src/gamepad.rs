@@ -0,0 +1,40 @@
+use gilrs::{Axis, Gilrs};
+
+use crate::renderer::camera::CameraController;
+
+// Left stick moves and right stick looks, using the first connected gamepad
+pub struct Gamepad {
+    gilrs: Gilrs,
+}
+
+impl Gamepad {
+    // None when the platform has no gamepad support
+    pub fn new() -> Option<Self> {
+        match Gilrs::new() {
+            Ok(gilrs) => Some(Self { gilrs }),
+            Err(error) => {
+                eprintln!("Gamepad input is unavailable: {error}");
+                None
+            }
+        }
+    }
+    // Drains pending events so the stick values are current, then writes them to the controller
+    pub fn update_camera_controller(&mut self, camera_controller: &mut CameraController) {
+        while self.gilrs.next_event().is_some() {}
+        // gilrs' default deadzone filter already zeroes resting sticks
+        let (forward, right, yaw, pitch) = match self.gilrs.gamepads().next() {
+            Some((_, gamepad)) => (
+                gamepad.value(Axis::LeftStickY),
+                gamepad.value(Axis::LeftStickX),
+                gamepad.value(Axis::RightStickX),
+                // stick up is positive, which should look up
+                -gamepad.value(Axis::RightStickY),
+            ),
+            None => (0.0, 0.0, 0.0, 0.0),
+        };
+        camera_controller.analog_forward = forward;
+        camera_controller.analog_right = right;
+        camera_controller.analog_yaw = yaw;
+        camera_controller.analog_pitch = pitch;
+    }
+}
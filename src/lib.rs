@@ -0,0 +1,7 @@
+pub mod app;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
+mod model_loader;
+pub mod renderer;
+#[cfg(test)]
+mod test;
@@ -1,21 +1,37 @@
-use std::env;
+use std::{env, time::Instant};
 
-use winit::event_loop::{ControlFlow, EventLoop};
-
-mod app;
-mod renderer;
-mod model_loader;
+use ash_renderer::{app, renderer};
+use winit::event_loop::EventLoop;
 
 fn main() {
     env::set_var("RUST_BACKTRACE", "full");
 
+    let mut renderer_user_settings = renderer::UserSettings {
+        model_path: env::args().nth(1),
+        #[cfg(feature = "shaderc")]
+        shader_hot_reload: cfg!(debug_assertions),
+        ..Default::default()
+    };
+    // e.g. for a binary run away from the checkout it was built in
+    if let Some(shader_directory) = env::var_os("ASH_RENDERER_SHADER_DIRECTORY") {
+        renderer_user_settings.shader_directory = shader_directory.into();
+    }
+
     let mut app = app::App {
         renderer: None,
         camera: None,
         camera_controller: None,
-        renderer_user_settings: Default::default()
+        key_bindings: renderer::camera::KeyBindings::default(),
+        #[cfg(feature = "gamepad")]
+        gamepad: None,
+        renderer_user_settings,
+        render_mode: app::RenderMode::default(),
+        scale_factor: 1.0,
+        cursor_grabbed: false,
+        dirty: true,
+        last_camera_update: Instant::now(),
     };
     let event_loop = EventLoop::new().expect("Failed to create event loop");
-    event_loop.set_control_flow(ControlFlow::Poll);
+    event_loop.set_control_flow(app.render_mode.control_flow());
     _ = event_loop.run_app(&mut app);
 }
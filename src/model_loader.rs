@@ -0,0 +1,103 @@
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+use nalgebra::Vector3;
+
+use crate::renderer::{Index, Vertex};
+
+const DEFAULT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+// Reads positions and faces, everything else in the file is ignored. Faces with more than three
+// vertices are triangulated as fans. Vertex colors written after the position (v x y z r g b) are
+// used when present, otherwise vertices are white. Normals are computed by averaging the normals
+// of the faces sharing each vertex.
+pub fn load_obj(path: &str) -> Result<(Vec<Vertex>, Vec<Index>)> {
+    let source = fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?;
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let values = tokens
+                    .map(str::parse::<f32>)
+                    .collect::<Result<Vec<f32>, _>>()
+                    .with_context(|| format!("{path}:{line_number}: invalid vertex"))?;
+                if values.len() < 3 {
+                    return Err(anyhow!(
+                        "{path}:{line_number}: vertex has fewer than 3 components"
+                    ));
+                }
+                let color = match values.len() {
+                    6.. => [values[3], values[4], values[5], 1.0],
+                    _ => DEFAULT_COLOR,
+                };
+                vertices.push(Vertex {
+                    position: [values[0], values[1], values[2]],
+                    color,
+                    normal: [0.0; 3],
+                });
+            }
+            Some("f") => {
+                let face = tokens
+                    .map(|token| parse_face_index(token, vertices.len()))
+                    .collect::<Option<Vec<Index>>>()
+                    .ok_or_else(|| anyhow!("{path}:{line_number}: invalid face"))?;
+                if face.len() < 3 {
+                    return Err(anyhow!(
+                        "{path}:{line_number}: face has fewer than 3 vertices"
+                    ));
+                }
+                for i in 1..face.len() - 1 {
+                    indices.extend([face[0], face[i], face[i + 1]]);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if indices.is_empty() {
+        return Err(anyhow!("{path}: no faces"));
+    }
+    let vertex_count = vertices.len() as Index;
+    if let Some(&index) = indices.iter().find(|&&index| index >= vertex_count) {
+        return Err(anyhow!(
+            "{path}: face references missing vertex {}",
+            index + 1
+        ));
+    }
+    compute_vertex_normals(&mut vertices, &indices);
+    Ok((vertices, indices))
+}
+
+// Area weighted average of the adjacent face normals, counterclockwise faces are front facing
+fn compute_vertex_normals(vertices: &mut [Vertex], indices: &[Index]) {
+    let mut normals = vec![Vector3::<f32>::zeros(); vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(vertices[triangle[i] as usize].position));
+        let face_normal = (b - a).cross(&(c - a));
+        for &index in triangle {
+            normals[index as usize] += face_normal;
+        }
+    }
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        vertex.normal = normal
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_default()
+            .into();
+    }
+}
+
+// Position index of a face vertex written as v, v/vt, v//vn or v/vt/vn.
+// OBJ indices start at 1, negative indices count back from the last vertex read so far.
+fn parse_face_index(token: &str, vertex_count: usize) -> Option<Index> {
+    let index: i64 = token.split('/').next()?.parse().ok()?;
+    let index = match index {
+        1.. => index - 1,
+        ..=-1 => vertex_count as i64 + index,
+        0 => return None,
+    };
+    Index::try_from(index).ok()
+}
@@ -1,44 +1,158 @@
-use std::ffi::{c_char, CStr};
+use std::{ffi::c_char, fmt, path::PathBuf, time::Duration};
 
 use ash::{
     khr,
     vk::{self, ClearValue, ImageSubresourceRange},
 };
-use command_buffer_components::{record_submit_commandbuffer, CommandBufferComponents};
-use descriptor_components::{DescriptorComponents, UniformBuffers};
-use graphics_pipeline_components::GraphicsPipelineComponents;
-use index_buffer_components::{IndexBufferComponents, INDICES};
-use resize_dependent_components::ResizeDependentComponents;
+use buffer::Buffer;
+use command_buffer_components::{
+    try_record_submit_commandbuffer, CommandBufferComponents, SubmitSemaphores,
+    MAX_FRAMES_IN_FLIGHT,
+};
+use descriptor_components::{DescriptorComponents, FrameUniforms, ObjectUniforms};
+use frame_timer::FrameTimer;
+use graphics_pipeline_components::{
+    record_depth_bias, FragmentPushConstants, GraphicsPipelineComponents, PipelineAttachments,
+    GRID_PIPELINE_INDEX, PROCEDURAL_PIPELINE_INDEX, SKYBOX_PIPELINE_INDEX, SPLIT_PIPELINE_INDEX,
+};
+use grid_components::GridComponents;
+use index_buffer_components::INDICES;
+use memory_allocator::MemoryAllocator;
+use mesh::Mesh;
+use nalgebra::{Matrix4, Point3, Vector3};
+use pipeline_cache_components::PipelineCacheComponents;
+use post_effect_components::PostEffectComponents;
+use resize_dependent_components::{
+    ResizeDependentComponents, ResizeDependentError, ResizeDependentOptions, SwapchainSettings,
+};
 use semaphore_components::SemaphoreComponents;
-use vertex_buffer_components::{VertexBufferComponents, VERTICES};
+#[cfg(feature = "shaderc")]
+use shader_watcher::ShaderWatcher;
+use split_geometry_components::SplitGeometryComponents;
+use textures::Texture;
+use timestamp_query_components::TimestampQueryComponents;
+use vertex_buffer_components::{InstanceVertex, VertexBufferComponents, VERTICES};
 use winit::{
+    dpi::PhysicalSize,
     event_loop::ActiveEventLoop,
     raw_window_handle::{HasDisplayHandle, HasWindowHandle},
-    window::WindowAttributes,
+    window::{CursorGrabMode, Fullscreen, WindowAttributes},
 };
 
+use crate::model_loader;
+
 mod buffer;
 pub mod camera;
 mod command_buffer_components;
 mod debug_components;
 mod descriptor_components;
+mod frame_capture;
+mod frame_timer;
 mod graphics_pipeline_components;
+mod grid_components;
 mod index_buffer_components;
+mod memory_allocator;
+mod mesh;
+mod pipeline_cache_components;
+mod post_effect_components;
 mod resize_dependent_components;
 mod select_physical_device;
 mod semaphore_components;
+#[cfg(feature = "shaderc")]
+mod shader_watcher;
 mod shaders;
+mod split_geometry_components;
 mod textures;
+mod timestamp_query_components;
 mod vertex_buffer_components;
+#[cfg(feature = "serde")]
+mod vk_serde;
 
+pub use debug_components::DebugConfig;
+pub use descriptor_components::MAX_OBJECTS;
+pub use graphics_pipeline_components::{
+    BlendMode, DepthBias, DepthBounds, LineMode, LineStipple, PipelineConfig, PipelineInfo,
+};
+pub use index_buffer_components::Index;
+pub use mesh::{Aabb, MeshId, DEFAULT_MESH_ID};
+pub use post_effect_components::{FxaaParams, PostEffectParams};
+pub use resize_dependent_components::PresentModePreference;
+pub use select_physical_device::{enumerate_devices, DeviceInfo};
+#[cfg(feature = "shaderc")]
+pub use shaders::FXAA_POST_EFFECT_SOURCE;
+pub use shaders::{ShaderEntryPoints, SpecializationConstants};
+pub use textures::SamplerConfig;
+pub use vertex_buffer_components::{InstanceData, Vertex, VertexAttribs};
+
+// Fields missing from a settings file keep their defaults
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct UserSettings {
     pub preferred_physical_device_id: Option<u32>,
+    // OBJ file to render instead of the built in mesh
+    pub model_path: Option<String>,
+    pub debug_config: DebugConfig,
+    pub pipeline_config: PipelineConfig,
+    pub present_mode: PresentModePreference,
+    // clamped to what the surface supports, None requests one more than its minimum
+    pub desired_swapchain_image_count: Option<u32>,
+    // TYPE_1 disables multisampling, clamped to what the device supports
+    #[cfg_attr(feature = "serde", serde(with = "vk_serde::sample_count_flags"))]
+    pub msaa_samples: vk::SampleCountFlags,
+    // anisotropic filtering for texture samplers, clamped to maxSamplerAnisotropy. Textures are
+    // sampled without it when None or when the device lacks samplerAnisotropy.
+    pub max_anisotropy: Option<f32>,
+    // filtering and addressing of the texture, the skybox always clamps to its edges
+    pub sampler_config: SamplerConfig,
+    pub window_config: WindowConfig,
+    // GLSL read by shader_hot_reload and reload_shaders, and the compiled <shader name>.spv files
+    // the shaders are loaded from without the shaderc feature. The default only exists on the
+    // machine the crate was built on.
+    pub shader_directory: PathBuf,
+    // read shaders from shader_directory and reload them when they change on disk
+    #[cfg(feature = "shaderc")]
+    pub shader_hot_reload: bool,
+    pub shader_entry_points: ShaderEntryPoints,
+    // ids 1 and 2, SKYBOX_DEPTH_CONSTANT_ID and ENCODE_SRGB_CONSTANT_ID, are reserved for the
+    // renderer and rejected
+    pub specialization_constants: SpecializationConstants,
 }
 
 impl Default for UserSettings {
     fn default() -> Self {
         Self {
             preferred_physical_device_id: None,
+            model_path: None,
+            debug_config: DebugConfig::default(),
+            pipeline_config: PipelineConfig::default(),
+            present_mode: PresentModePreference::default(),
+            desired_swapchain_image_count: None,
+            msaa_samples: vk::SampleCountFlags::TYPE_1,
+            max_anisotropy: None,
+            sampler_config: SamplerConfig::default(),
+            window_config: WindowConfig::default(),
+            shader_directory: PathBuf::from(shaders::SHADER_DIRECTORY),
+            #[cfg(feature = "shaderc")]
+            shader_hot_reload: false,
+            shader_entry_points: ShaderEntryPoints::default(),
+            specialization_constants: SpecializationConstants::default(),
+        }
+    }
+}
+
+// Only read when the window is created, ignored when rendering headless
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WindowConfig {
+    pub title: String,
+    // physical pixels, None lets the platform pick
+    pub size: Option<(u32, u32)>,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "ash_renderer".to_string(),
+            size: None,
         }
     }
 }
@@ -48,25 +162,112 @@ impl Default for UserSettings {
 pub struct Renderer {
     sic: SettingsIndependentComponents,
     sdc: SettingsDependentComponents,
-    pub resize_dependent_component_rebuild_needed: bool,
+    resize_dependent_component_rebuild_needed: bool,
+    // latest size reported by window_resized, applied at the start of the next frame
+    pending_window_extent: Option<vk::Extent2D>,
+    // frames are skipped while minimized, the swapchain is rebuilt once restored
+    minimized: bool,
+    resize_dependent_options: ResizeDependentOptions,
+    cursor_position: Option<(f32, f32)>,
+    light_direction: Vector3<f32>,
+    clear_color: [f32; 4],
+    tint: [f32; 4],
+    // kept across set_post_effect calls and device rebuilds, like the tint
+    post_effect_params: PostEffectParams,
+    // recorded for the grid draw instead of the pipeline config's depth bias
+    grid_depth_bias: Option<DepthBias>,
+    current_frame: usize,
+    // swapchain image of the last successful present, None until then and after swapchain rebuilds
+    last_present_index: Option<usize>,
+    // draw commands reuse fence of the frame that last rendered to each swapchain image, null
+    // until one has. Emptied whenever the swapchain or the device is rebuilt.
+    images_in_flight: Vec<vk::Fence>,
+    frame_timer: FrameTimer,
+    overlay_callback: Option<OverlayCallback>,
+    // set once a frame hit ERROR_DEVICE_LOST, cleared by recreate_device
+    device_lost: bool,
+    // set when rebuilding the device failed after the old one was destroyed, only dropping the
+    // renderer is left
+    sdc_destroyed: bool,
+    #[cfg(feature = "shaderc")]
+    shader_watcher: Option<ShaderWatcher>,
 }
 
 impl Renderer {
     pub fn new(event_loop: &ActiveEventLoop, user_settings: &UserSettings) -> Self {
-        let sic = SettingsIndependentComponents::new(event_loop);
-        let sdc = SettingsDependentComponents::new(&sic, user_settings);
+        let sic = SettingsIndependentComponents::new(
+            Some(event_loop),
+            &user_settings.debug_config,
+            &user_settings.window_config,
+        );
+        Self::from_settings_independent_components(
+            sic,
+            user_settings,
+            ResizeDependentOptions::default(),
+        )
+    }
+    // Renders into an owned width x height color image instead of a window, read the result
+    // back with set_frame_capture_enabled and capture_frame. Frames are finished before
+    // draw_frame returns.
+    pub fn new_headless(width: u32, height: u32, user_settings: &UserSettings) -> Self {
+        let sic = SettingsIndependentComponents::new(
+            None,
+            &user_settings.debug_config,
+            &user_settings.window_config,
+        );
+        let resize_dependent_options = ResizeDependentOptions {
+            surface_extent_override: Some(vk::Extent2D { width, height }),
+            ..Default::default()
+        };
+        Self::from_settings_independent_components(sic, user_settings, resize_dependent_options)
+    }
+    fn from_settings_independent_components(
+        sic: SettingsIndependentComponents,
+        user_settings: &UserSettings,
+        resize_dependent_options: ResizeDependentOptions,
+    ) -> Self {
+        let sdc = SettingsDependentComponents::new(&sic, user_settings, &resize_dependent_options)
+            .unwrap_or_else(|error| panic!("Failed to create the renderer: {error}"));
 
         Self {
             sdc,
             sic,
             resize_dependent_component_rebuild_needed: false,
+            pending_window_extent: None,
+            minimized: false,
+            resize_dependent_options,
+            cursor_position: None,
+            light_direction: Vector3::new(0.3, -1.0, 1.0).normalize(),
+            clear_color: [0.1, 0.1, 0.1, 1.0],
+            tint: [1.0; 4],
+            post_effect_params: FxaaParams::default().into(),
+            grid_depth_bias: None,
+            current_frame: 0,
+            last_present_index: None,
+            images_in_flight: Vec::new(),
+            frame_timer: FrameTimer::new(),
+            overlay_callback: None,
+            device_lost: false,
+            sdc_destroyed: false,
+            #[cfg(feature = "shaderc")]
+            shader_watcher: user_settings
+                .shader_hot_reload
+                .then(|| ShaderWatcher::new(&user_settings.shader_directory)),
         }
     }
 }
 
 impl Drop for Renderer {
     fn drop(&mut self) {
-        self.sdc.cleanup();
+        // a lost device returns no cache data, which would overwrite the saved cache
+        if !self.device_lost {
+            pipeline_cache_components::save_pipeline_cache_data(
+                &self.sdc.pipeline_cache_components.data(&self.sdc.device),
+            );
+        }
+        if !self.sdc_destroyed {
+            self.sdc.cleanup();
+        }
         self.sic.cleanup();
     }
 }
@@ -75,51 +276,96 @@ impl Drop for Renderer {
 struct SettingsIndependentComponents {
     entry: ash::Entry,
     instance: ash::Instance,
-    #[cfg(debug_assertions)]
-    debug_components: debug_components::DebugComponents,
-    window: winit::window::Window,
-    surface: vk::SurfaceKHR,
+    debug_components: Option<debug_components::DebugComponents>,
+    // both None when rendering headless
+    window: Option<winit::window::Window>,
+    surface: Option<vk::SurfaceKHR>,
     surface_loader: khr::surface::Instance,
+    // pipeline caches belong to a device, so only the data survives settings rebuilds
+    pipeline_cache_data: Vec<u8>,
 }
 impl SettingsIndependentComponents {
-    pub fn new(event_loop: &ActiveEventLoop) -> SettingsIndependentComponents {
-        let window = event_loop
-            .create_window(WindowAttributes::default())
-            .expect("Failed to create winit window");
+    pub fn new(
+        event_loop: Option<&ActiveEventLoop>,
+        debug_config: &DebugConfig,
+        window_config: &WindowConfig,
+    ) -> SettingsIndependentComponents {
+        let window = event_loop.map(|event_loop| {
+            let mut window_attributes =
+                WindowAttributes::default().with_title(window_config.title.as_str());
+            if let Some((width, height)) = window_config.size {
+                window_attributes =
+                    window_attributes.with_inner_size(PhysicalSize::new(width, height));
+            }
+            event_loop
+                .create_window(window_attributes)
+                .expect("Failed to create winit window")
+        });
+
+        let entry = unsafe { ash::Entry::load().unwrap() };
 
-        let validation_layer_names =
-            [CStr::from_bytes_with_nul(b"VK_LAYER_KHRONOS_validation\0").unwrap()];
+        let validation_layer_name = c"VK_LAYER_KHRONOS_validation";
 
-        let validation_layer_names_raw: Vec<*const c_char> = if cfg!(debug_assertions) {
-            validation_layer_names
+        let validation_layer_present = unsafe {
+            entry
+                .enumerate_instance_layer_properties()
+                .unwrap()
                 .iter()
-                .map(|name| name.as_ptr())
-                .collect()
+                .any(|layer| layer.layer_name_as_c_str() == Ok(validation_layer_name))
+        };
+
+        let validation_enabled = debug_config.enable_validation && validation_layer_present;
+        if debug_config.enable_validation && !validation_enabled {
+            eprintln!("Validation requested but VK_LAYER_KHRONOS_validation is not installed");
+        }
+
+        let validation_layer_names_raw: Vec<*const c_char> = if validation_enabled {
+            vec![validation_layer_name.as_ptr()]
         } else {
             vec![]
         };
 
-        let mut extension_names =
-            ash_window::enumerate_required_extensions(window.display_handle().unwrap().as_raw())
-                .unwrap()
-                .to_vec();
-        extension_names.push(ash::ext::debug_utils::NAME.as_ptr());
+        let mut extension_names = match &window {
+            Some(window) => {
+                ash_window::enumerate_required_extensions(window.display_handle().unwrap().as_raw())
+                    .unwrap()
+                    .to_vec()
+            }
+            None => vec![],
+        };
+        if validation_enabled {
+            extension_names.push(ash::ext::debug_utils::NAME.as_ptr());
+        }
 
-        let entry = unsafe { ash::Entry::load().unwrap() };
+        // best practices is a feature of the validation layer, so only request it when the layer will be loaded
+        let best_practices_enabled = debug_config.best_practices_validation && validation_enabled;
+        if debug_config.best_practices_validation && !best_practices_enabled {
+            eprintln!("Best practices validation requested but validation layers are unavailable");
+        }
+        if best_practices_enabled {
+            extension_names.push(ash::ext::validation_features::NAME.as_ptr());
+        }
+
+        let enabled_validation_features = [vk::ValidationFeatureEnableEXT::BEST_PRACTICES];
+        let mut validation_features = vk::ValidationFeaturesEXT::default()
+            .enabled_validation_features(&enabled_validation_features);
 
         let application_info = vk::ApplicationInfo::default().api_version(vk::API_VERSION_1_3);
 
-        let instance_create_info = vk::InstanceCreateInfo::default()
+        let mut instance_create_info = vk::InstanceCreateInfo::default()
             .application_info(&application_info)
             .enabled_layer_names(&validation_layer_names_raw)
             .enabled_extension_names(&extension_names);
+        if best_practices_enabled {
+            instance_create_info = instance_create_info.push_next(&mut validation_features);
+        }
 
         let instance = unsafe { entry.create_instance(&instance_create_info, None).unwrap() };
 
-        #[cfg(debug_assertions)]
-        let debug_components = debug_components::DebugComponents::new(&entry, &instance);
+        let debug_components =
+            validation_enabled.then(|| debug_components::DebugComponents::new(&entry, &instance));
 
-        let surface = unsafe {
+        let surface = window.as_ref().map(|window| unsafe {
             ash_window::create_surface(
                 &entry,
                 &instance,
@@ -128,7 +374,7 @@ impl SettingsIndependentComponents {
                 None,
             )
             .unwrap()
-        };
+        });
 
         let surface_loader = khr::surface::Instance::new(&entry, &instance);
 
@@ -136,17 +382,20 @@ impl SettingsIndependentComponents {
             window,
             entry,
             instance,
-            #[cfg(debug_assertions)]
             debug_components,
             surface,
             surface_loader,
+            pipeline_cache_data: pipeline_cache_components::load_pipeline_cache_data(),
         }
     }
     pub fn cleanup(&mut self) {
         unsafe {
-            self.surface_loader.destroy_surface(self.surface, None);
-            #[cfg(debug_assertions)]
-            self.debug_components.cleanup();
+            if let Some(surface) = self.surface {
+                self.surface_loader.destroy_surface(surface, None);
+            }
+            if let Some(debug_components) = &self.debug_components {
+                debug_components.cleanup();
+            }
             self.instance.destroy_instance(None);
         }
     }
@@ -157,73 +406,192 @@ struct SettingsDependentComponents {
     physical_device: vk::PhysicalDevice,
     device: ash::Device,
     graphics_queue: vk::Queue,
+    graphics_queue_family_index: u32,
+    // the same queue as graphics_queue unless the graphics family can't present to the surface
+    present_queue: vk::Queue,
+    present_queue_family_index: u32,
     transfer_queue: Option<vk::Queue>,
     swapchain_loader: khr::swapchain::Device,
     physical_device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    memory_allocator: MemoryAllocator,
+    enabled_features: vk::PhysicalDeviceFeatures,
+    enabled_line_rasterization_features: vk::PhysicalDeviceLineRasterizationFeaturesEXT<'static>,
+    present_mode_preference: PresentModePreference,
+    desired_swapchain_image_count: Option<u32>,
+    depth_format: vk::Format,
+    msaa_samples: vk::SampleCountFlags,
+    // applied to every texture sampler, None when anisotropic filtering is off
+    sampler_anisotropy: Option<f32>,
+    sampler_config: SamplerConfig,
     semaphore_components: SemaphoreComponents,
+    // None when the graphics queue can't write timestamps
+    timestamp_query_components: Option<TimestampQueryComponents>,
     command_buffer_components: CommandBufferComponents,
-    vertex_buffer_components: VertexBufferComponents,
-    index_buffer_components: IndexBufferComponents,
+    // drawn in order, each with every model matrix and instance
+    meshes: Vec<(MeshId, Mesh)>,
+    next_mesh_id: u64,
+    split_geometry_components: Option<SplitGeometryComponents>,
+    // every mesh draw covers all instances, a single identity instance by default
+    instance_buffer_components: VertexBufferComponents<InstanceVertex>,
+    instance_count: u32,
+    // whether set_instances, set_storage_data and set_texture replaced the defaults
+    instances_set: bool,
+    storage_data_set: bool,
+    texture_set: bool,
+    // once set, each object's draws come from the first indirect_draw_count commands instead of
+    // a cmd_draw_indexed
+    indirect_command_buffer: Option<Buffer<vk::DrawIndexedIndirectCommand>>,
+    indirect_draw_count: u32,
+    texture: Texture,
+    // cubemap drawn behind mesh frames once set_skybox has been called
+    skybox: Option<Texture>,
+    // ground grid and axes drawn with mesh frames while set_show_grid is on
+    grid: Option<GridComponents>,
+    // fullscreen pass from the render target to the swapchain image while set_post_effect is on
+    post_effect: Option<PostEffectComponents>,
     shaders: shaders::Shaders,
+    // UserSettings::shader_directory, for reload_shaders
+    shader_directory: PathBuf,
+    specialization_constants: SpecializationConstants,
+    pipeline_cache_components: PipelineCacheComponents,
     rdc: ResizeDependentComponents,
     descriptor_components: DescriptorComponents,
     graphics_pipeline_components: GraphicsPipelineComponents,
 }
 impl SettingsDependentComponents {
+    // Losing the device part way leaks what was created on it so far, the caller can only give
+    // up on rendering then
     fn new(
         settings_independent_components: &SettingsIndependentComponents,
         user_settings: &UserSettings,
-    ) -> SettingsDependentComponents {
+        resize_dependent_options: &ResizeDependentOptions,
+    ) -> Result<SettingsDependentComponents, DeviceLost> {
         let physical_device_selection = select_physical_device(
             &settings_independent_components.instance,
+            &settings_independent_components.surface_loader,
+            settings_independent_components.surface,
             user_settings.preferred_physical_device_id,
         );
         let graphics_queue_family_index =
             physical_device_selection.graphics_queue_family_index as u32;
+        let present_queue_family_index =
+            physical_device_selection.present_queue_family_index as u32;
         let transfer_queue_family_index = physical_device_selection.transfer_queue_family_index;
         let physical_device = physical_device_selection.physical_device;
 
-        let device_extension_names_raw = [khr::swapchain::NAME.as_ptr()];
+        let mut device_extension_names_raw = Vec::new();
+        if settings_independent_components.surface.is_some() {
+            device_extension_names_raw.push(khr::swapchain::NAME.as_ptr());
+        }
+
+        let line_rasterization_supported = unsafe {
+            settings_independent_components
+                .instance
+                .enumerate_device_extension_properties(physical_device)
+                .unwrap()
+        }
+        .iter()
+        .any(|extension| {
+            extension.extension_name_as_c_str() == Ok(ash::ext::line_rasterization::NAME)
+        });
+
+        // every supported line mode is enabled so it can be switched at runtime without
+        // recreating the device
+        let mut line_rasterization_features =
+            vk::PhysicalDeviceLineRasterizationFeaturesEXT::default();
+        if line_rasterization_supported {
+            device_extension_names_raw.push(ash::ext::line_rasterization::NAME.as_ptr());
+            let mut supported_features2 =
+                vk::PhysicalDeviceFeatures2::default().push_next(&mut line_rasterization_features);
+            unsafe {
+                settings_independent_components
+                    .instance
+                    .get_physical_device_features2(physical_device, &mut supported_features2)
+            };
+        }
+        // kept without the p_next chain it is linked into below
+        let enabled_line_rasterization_features = line_rasterization_features;
+
+        let supported_features = unsafe {
+            settings_independent_components
+                .instance
+                .get_physical_device_features(physical_device)
+        };
+
+        let depth_bias_clamp_requested = user_settings.pipeline_config.requires_depth_bias_clamp();
+        if depth_bias_clamp_requested && supported_features.depth_bias_clamp == vk::FALSE {
+            eprintln!("Device does not support depthBiasClamp");
+        }
+        let depth_bounds_requested = user_settings.pipeline_config.requires_depth_bounds();
+        if depth_bounds_requested && supported_features.depth_bounds == vk::FALSE {
+            eprintln!("Device does not support depthBounds");
+        }
+        let sampler_anisotropy_requested = user_settings.max_anisotropy.is_some();
+        if sampler_anisotropy_requested && supported_features.sampler_anisotropy == vk::FALSE {
+            eprintln!("Device does not support samplerAnisotropy, textures are sampled without it");
+        }
 
-        let features = vk::PhysicalDeviceFeatures::default().shader_clip_distance(true);
+        // enabled whenever supported so the polygon mode can be switched at runtime
+        let features = vk::PhysicalDeviceFeatures::default()
+            .shader_clip_distance(true)
+            .fill_mode_non_solid(supported_features.fill_mode_non_solid == vk::TRUE)
+            .depth_bias_clamp(
+                depth_bias_clamp_requested && supported_features.depth_bias_clamp == vk::TRUE,
+            )
+            .depth_bounds(depth_bounds_requested && supported_features.depth_bounds == vk::TRUE)
+            // indirect draws fall back to one call per command without multiDrawIndirect
+            .multi_draw_indirect(supported_features.multi_draw_indirect == vk::TRUE)
+            .draw_indirect_first_instance(
+                supported_features.draw_indirect_first_instance == vk::TRUE,
+            )
+            .sampler_anisotropy(
+                sampler_anisotropy_requested && supported_features.sampler_anisotropy == vk::TRUE,
+            );
 
         let mut dynamic_rendering_features =
             vk::PhysicalDeviceDynamicRenderingFeatures::default().dynamic_rendering(true);
 
         let priorities = [1.0];
 
-        let graphics_queue_create_info = vk::DeviceQueueCreateInfo::default()
-            .queue_family_index(graphics_queue_family_index)
-            .queue_priorities(&priorities);
-        let queue_infos = match transfer_queue_family_index {
-            Some(i) => {
-                let transfer_queue_create_info = vk::DeviceQueueCreateInfo::default()
-                    .queue_family_index(i as u32)
-                    .queue_priorities(&priorities);
-                vec![graphics_queue_create_info, transfer_queue_create_info]
+        // one queue per distinct family, families may only be listed once
+        let mut queue_family_indices = vec![graphics_queue_family_index];
+        let other_queue_family_indices = std::iter::once(present_queue_family_index)
+            .chain(transfer_queue_family_index.map(|i| i as u32));
+        for queue_family_index in other_queue_family_indices {
+            if !queue_family_indices.contains(&queue_family_index) {
+                queue_family_indices.push(queue_family_index);
             }
-            None => vec![graphics_queue_create_info],
-        };
+        }
+        let queue_infos: Vec<vk::DeviceQueueCreateInfo> = queue_family_indices
+            .iter()
+            .map(|&queue_family_index| {
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(queue_family_index)
+                    .queue_priorities(&priorities)
+            })
+            .collect();
 
-        let device_create_info = vk::DeviceCreateInfo::default()
+        let mut device_create_info = vk::DeviceCreateInfo::default()
             .queue_create_infos(&queue_infos)
             .enabled_extension_names(&device_extension_names_raw)
             .push_next(&mut dynamic_rendering_features)
             .enabled_features(&features);
+        if line_rasterization_supported {
+            device_create_info = device_create_info.push_next(&mut line_rasterization_features);
+        }
 
         let device = unsafe {
             settings_independent_components
                 .instance
                 .create_device(physical_device, &device_create_info, None)
-                .unwrap()
+                .map_err(|e| device_lost_or_panic(e, "Failed to create device"))?
         };
 
         let graphics_queue = unsafe { device.get_device_queue(graphics_queue_family_index, 0) };
+        let present_queue = unsafe { device.get_device_queue(present_queue_family_index, 0) };
 
-        let transfer_queue = match transfer_queue_family_index {
-            Some(i) => Some(unsafe { device.get_device_queue(i as u32, 0) }),
-            None => None,
-        };
+        let transfer_queue =
+            transfer_queue_family_index.map(|i| unsafe { device.get_device_queue(i as u32, 0) });
 
         let swapchain_loader =
             khr::swapchain::Device::new(&settings_independent_components.instance, &device);
@@ -234,90 +602,253 @@ impl SettingsDependentComponents {
                 .get_physical_device_memory_properties(physical_device)
         };
 
-        let semaphore_components = SemaphoreComponents::new(&device);
+        let physical_device_properties = unsafe {
+            settings_independent_components
+                .instance
+                .get_physical_device_properties(physical_device)
+        };
+        let msaa_samples = select_msaa_samples(
+            user_settings.msaa_samples,
+            &physical_device_properties.limits,
+        );
+
+        let mut memory_allocator = MemoryAllocator::new(
+            physical_device_memory_properties,
+            &physical_device_properties.limits,
+        );
+
+        let pipeline_cache_components = PipelineCacheComponents::new(
+            &device,
+            &physical_device_properties,
+            &settings_independent_components.pipeline_cache_data,
+        );
+
+        let depth_format = resize_dependent_components::select_depth_format(
+            &settings_independent_components.instance,
+            physical_device,
+        );
+        if user_settings.pipeline_config.reverse_z {
+            warn_if_not_float_depth(depth_format);
+        }
 
-        let command_buffer_components =
-            CommandBufferComponents::new(graphics_queue_family_index, &device);
+        let semaphore_components = SemaphoreComponents::new(&device);
 
-        let mut index_buffer_components =
-            IndexBufferComponents::new_unintiailized(&device, &physical_device_memory_properties);
-        index_buffer_components.update_indices(
+        let graphics_queue_family_properties = unsafe {
+            settings_independent_components
+                .instance
+                .get_physical_device_queue_family_properties(physical_device)
+        }[graphics_queue_family_index as usize];
+        let timestamp_query_components = TimestampQueryComponents::new(
             &device,
-            &INDICES,
-            command_buffer_components.setup_command_buffer,
-            command_buffer_components.setup_commands_reuse_fence,
-            graphics_queue,
+            &physical_device_properties.limits,
+            &graphics_queue_family_properties,
         );
 
-        let mut vertex_buffer_components =
-            VertexBufferComponents::new_unintialized(&device, &physical_device_memory_properties);
-        vertex_buffer_components.update_vertices(
+        let command_buffer_components = CommandBufferComponents::new(
+            graphics_queue_family_index,
+            transfer_queue_family_index
+                .map(|i| i as u32)
+                .zip(transfer_queue),
             &device,
-            &VERTICES,
-            command_buffer_components.setup_command_buffer,
-            command_buffer_components.setup_commands_reuse_fence,
-            graphics_queue,
         );
+        let upload_commands = command_buffer_components.upload_commands(graphics_queue);
 
-        let shaders = shaders::Shaders::new(&device);
+        let (vertices, indices) = match &user_settings.model_path {
+            Some(model_path) => model_loader::load_obj(model_path).unwrap_or_else(|error| {
+                eprintln!("{error:#}, using the built in mesh");
+                (VERTICES.to_vec(), INDICES.to_vec())
+            }),
+            None => (VERTICES.to_vec(), INDICES.to_vec()),
+        };
 
-        let rdc = resize_dependent_components::ResizeDependentComponents::new(
+        let default_mesh = Mesh::new(
             &device,
-            &settings_independent_components.window,
-            settings_independent_components.surface,
-            &settings_independent_components.surface_loader,
-            &swapchain_loader,
-            physical_device,
-            command_buffer_components.setup_command_buffer,
-            command_buffer_components.setup_commands_reuse_fence,
-            &physical_device_memory_properties,
-            graphics_queue,
+            &mut memory_allocator,
+            &vertices,
+            &indices,
+            &upload_commands,
+        )?;
+
+        let mut instance_buffer_components =
+            VertexBufferComponents::new_unintialized(&device, &mut memory_allocator, 1);
+        instance_buffer_components.update_vertices(
+            &device,
+            &[InstanceVertex::from(&InstanceData::default())],
+            &upload_commands,
+        )?;
+
+        // Display keeps the line breaks of shaderc's diagnostics, expect would escape them
+        #[cfg(feature = "shaderc")]
+        let shaders = shaders::Shaders::new(&device)
+            .unwrap_or_else(|error| panic!("Failed to compile shaders:\n{error}"));
+        #[cfg(not(feature = "shaderc"))]
+        let shaders =
+            shaders::Shaders::from_spirv_directory(&device, &user_settings.shader_directory)
+                .unwrap_or_else(|error| panic!("Failed to load shaders:\n{error}"));
+        let shaders = shaders.with_entry_points(user_settings.shader_entry_points.clone());
+
+        let rdc = ResizeDependentComponents::new(
+            &mut command_buffer_components.setup_context(
+                &device,
+                &mut memory_allocator,
+                graphics_queue,
+            ),
+            &SwapchainSettings {
+                window_surface: settings_independent_components
+                    .window
+                    .as_ref()
+                    .zip(settings_independent_components.surface),
+                surface_loader: &settings_independent_components.surface_loader,
+                swapchain_loader: &swapchain_loader,
+                physical_device,
+                physical_device_memory_properties: &physical_device_memory_properties,
+                graphics_queue_family_index,
+                present_queue_family_index,
+                present_mode_preference: user_settings.present_mode,
+                desired_image_count: user_settings.desired_swapchain_image_count,
+            },
+            depth_format,
+            msaa_samples,
+            resize_dependent_options,
+            vk::SwapchainKHR::null(),
+        )
+        .map_err(swapchain_device_lost_or_panic)?;
+
+        let sampler_anisotropy = textures::clamped_anisotropy(
+            user_settings.max_anisotropy,
+            features.sampler_anisotropy == vk::TRUE,
+            physical_device_properties.limits.max_sampler_anisotropy,
         );
+        let sampler_config = user_settings.sampler_config.validated();
+
+        let texture = Texture::new_from_path(
+            &mut command_buffer_components.setup_context(
+                &device,
+                &mut memory_allocator,
+                graphics_queue,
+            ),
+            textures::DEFAULT_TEXTURE_PATH,
+            &sampler_config,
+            sampler_anisotropy,
+        )
+        .map_err(|error| match error.downcast::<DeviceLost>() {
+            Ok(device_lost) => device_lost,
+            Err(error) => panic!("{error:#}"),
+        })?;
 
         let descriptor_components = DescriptorComponents::new(
             &device,
-            &physical_device_memory_properties,
-            rdc.swapchain_components.present_images.len() as u32,
+            &mut memory_allocator,
+            &texture,
+            physical_device_properties
+                .limits
+                .min_uniform_buffer_offset_alignment,
         );
 
         let graphics_pipeline_components = GraphicsPipelineComponents::new(
             &device,
-            &rdc.swapchain_components.surface_format,
-            &shaders.shader_stage_infos(),
-            &[descriptor_components.uniform_buffer_descriptor_set_layout],
-            &rdc.scissors,
-            &rdc.viewports,
+            pipeline_cache_components.pipeline_cache,
+            &shaders.pipeline_shader_stages(),
+            &user_settings.specialization_constants,
+            &descriptor_components.descriptor_set_layouts(),
+            &PipelineAttachments {
+                color_format: rdc.scene_color_format(),
+                depth_format,
+                sample_count: msaa_samples,
+            },
+            &user_settings
+                .pipeline_config
+                .validated(&features, &enabled_line_rasterization_features),
         );
 
-        SettingsDependentComponents {
+        Ok(SettingsDependentComponents {
             physical_device,
             device,
             graphics_queue,
+            graphics_queue_family_index,
+            present_queue,
+            present_queue_family_index,
             transfer_queue,
             swapchain_loader,
             physical_device_memory_properties,
+            memory_allocator,
+            enabled_features: features,
+            enabled_line_rasterization_features,
+            present_mode_preference: user_settings.present_mode,
+            desired_swapchain_image_count: user_settings.desired_swapchain_image_count,
+            depth_format,
+            msaa_samples,
+            sampler_anisotropy,
+            sampler_config,
             shaders,
+            shader_directory: user_settings.shader_directory.clone(),
+            specialization_constants: user_settings.specialization_constants.clone(),
+            pipeline_cache_components,
             rdc,
             command_buffer_components,
             semaphore_components,
-            index_buffer_components,
-            vertex_buffer_components,
+            timestamp_query_components,
+            meshes: vec![(DEFAULT_MESH_ID, default_mesh)],
+            next_mesh_id: DEFAULT_MESH_ID.0 + 1,
+            split_geometry_components: None,
+            instance_buffer_components,
+            instance_count: 1,
+            instances_set: false,
+            storage_data_set: false,
+            texture_set: false,
+            indirect_command_buffer: None,
+            indirect_draw_count: 0,
+            texture,
+            skybox: None,
+            grid: None,
+            post_effect: None,
             descriptor_components,
             graphics_pipeline_components,
-        }
+        })
     }
 
     pub fn cleanup(&mut self) {
         unsafe {
-            self.device.device_wait_idle().unwrap();
+            // fails once the device is lost, its objects still have to be destroyed
+            let _ = self.device.device_wait_idle();
             self.graphics_pipeline_components.cleanup(&self.device);
+            self.pipeline_cache_components.cleanup(&self.device);
             self.shaders.cleanup(&self.device);
-            self.index_buffer_components.cleanup(&self.device);
-            self.vertex_buffer_components.cleanup(&self.device);
-            self.descriptor_components.cleanup(&self.device);
+            for (_, mesh) in &self.meshes {
+                mesh.cleanup(&self.device, &mut self.memory_allocator);
+            }
+            self.instance_buffer_components
+                .cleanup(&self.device, &mut self.memory_allocator);
+            if let Some(indirect_command_buffer) = &self.indirect_command_buffer {
+                indirect_command_buffer.cleanup(&self.device, &mut self.memory_allocator);
+            }
+            if let Some(split_geometry_components) = &self.split_geometry_components {
+                split_geometry_components.cleanup(&self.device, &mut self.memory_allocator);
+            }
+            self.descriptor_components
+                .cleanup(&self.device, &mut self.memory_allocator);
+            self.texture
+                .cleanup(&self.device, &mut self.memory_allocator);
+            if let Some(skybox) = &self.skybox {
+                skybox.cleanup(&self.device, &mut self.memory_allocator);
+            }
+            if let Some(grid) = &self.grid {
+                grid.cleanup(&self.device, &mut self.memory_allocator);
+            }
+            if let Some(post_effect) = &self.post_effect {
+                post_effect.cleanup(&self.device, &mut self.memory_allocator);
+            }
             self.semaphore_components.cleanup(&self.device);
+            if let Some(timestamp_query_components) = &self.timestamp_query_components {
+                timestamp_query_components.cleanup(&self.device);
+            }
             self.command_buffer_components.cleanup(&self.device);
-            self.rdc.cleanup(&self.device, &self.swapchain_loader);
+            self.rdc.cleanup(
+                &self.device,
+                &self.swapchain_loader,
+                &mut self.memory_allocator,
+            );
+            self.memory_allocator.cleanup(&self.device);
             self.device.destroy_device(None);
         }
     }
@@ -326,37 +857,61 @@ impl SettingsDependentComponents {
 #[derive(Clone, Copy)]
 struct PhysicalDeviceSelection {
     pub graphics_queue_family_index: usize,
+    pub present_queue_family_index: usize,
     pub transfer_queue_family_index: Option<usize>,
     pub physical_device: vk::PhysicalDevice,
 }
 fn select_physical_device(
     instance: &ash::Instance,
+    surface_loader: &khr::surface::Instance,
+    surface: Option<vk::SurfaceKHR>,
     preferred_physical_device_id: Option<u32>,
 ) -> PhysicalDeviceSelection {
     let physical_devices = unsafe { instance.enumerate_physical_devices().unwrap() };
     let mut qualified_devices = Vec::new();
+    let mut devices_without_dynamic_rendering = Vec::new();
     for physical_device in physical_devices.iter() {
+        if !select_physical_device::supports_dynamic_rendering(instance, *physical_device) {
+            let properties = unsafe { instance.get_physical_device_properties(*physical_device) };
+            devices_without_dynamic_rendering.push(
+                properties
+                    .device_name_as_c_str()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+            continue;
+        }
         let properties =
             unsafe { instance.get_physical_device_queue_family_properties(*physical_device) };
-        let mut graphics_queue_family_index = None;
-        let mut transfer_queue_family_index = None;
-        for i in 0..properties.len() {
-            let property = properties[i];
-            if property.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-                graphics_queue_family_index = Some(i);
-            } else if property.queue_flags.contains(vk::QueueFlags::TRANSFER) {
-                transfer_queue_family_index = Some(i);
-            }
-        }
-        if graphics_queue_family_index.is_some() {
+        // every family can present when headless, which keeps a single queue
+        let (graphics_queue_family_index, present_queue_family_index, transfer_queue_family_index) =
+            select_physical_device::select_queue_families(&properties, |i| {
+                surface.is_none_or(|surface| unsafe {
+                    surface_loader
+                        .get_physical_device_surface_support(*physical_device, i as u32, surface)
+                        .unwrap_or(false)
+                })
+            });
+        if let (Some(graphics_queue_family_index), Some(present_queue_family_index)) =
+            (graphics_queue_family_index, present_queue_family_index)
+        {
             qualified_devices.push(PhysicalDeviceSelection {
-                graphics_queue_family_index: graphics_queue_family_index.unwrap(),
+                graphics_queue_family_index,
+                present_queue_family_index,
                 transfer_queue_family_index,
                 physical_device: *physical_device,
             })
         }
     }
     if qualified_devices.is_empty() {
+        if !devices_without_dynamic_rendering.is_empty() {
+            panic!(
+                "No supported physical device found. Dynamic rendering from Vulkan 1.3 is \
+                 required, which is missing on: {}. Updating the graphics driver may add it.",
+                devices_without_dynamic_rendering.join(", ")
+            );
+        }
         panic!("No supported physical device found");
     }
     let mut selection_index = 0;
@@ -385,31 +940,184 @@ fn select_physical_device(
     }
     qualified_devices[selection_index]
 }
+// Highest sample count not above the requested one that both color and depth attachments support
+fn select_msaa_samples(
+    requested_samples: vk::SampleCountFlags,
+    limits: &vk::PhysicalDeviceLimits,
+) -> vk::SampleCountFlags {
+    let supported_samples =
+        limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+    let msaa_samples = [
+        vk::SampleCountFlags::TYPE_64,
+        vk::SampleCountFlags::TYPE_32,
+        vk::SampleCountFlags::TYPE_16,
+        vk::SampleCountFlags::TYPE_8,
+        vk::SampleCountFlags::TYPE_4,
+        vk::SampleCountFlags::TYPE_2,
+    ]
+    .into_iter()
+    .find(|&samples| {
+        samples.as_raw() <= requested_samples.as_raw() && supported_samples.contains(samples)
+    })
+    .unwrap_or(vk::SampleCountFlags::TYPE_1);
+    if msaa_samples != requested_samples {
+        eprintln!("{requested_samples:?} MSAA is not supported, using {msaa_samples:?}");
+    }
+    msaa_samples
+}
+// select_depth_format already prefers D32_SFLOAT, this only fires when the device lacks it
+fn warn_if_not_float_depth(depth_format: vk::Format) {
+    if !matches!(
+        depth_format,
+        vk::Format::D32_SFLOAT | vk::Format::D32_SFLOAT_S8_UINT
+    ) {
+        eprintln!(
+            "Reverse z with {depth_format:?} gains little precision without a float depth format"
+        );
+    }
+}
+// What an overlay callback needs to record into the scene's rendering, e.g. to create pipelines
+// matching its attachments
+#[derive(Debug, Clone, Copy)]
+pub struct OverlayContext {
+    pub present_index: usize,
+    pub extent: vk::Extent2D,
+    pub color_attachment_format: vk::Format,
+    pub depth_attachment_format: vk::Format,
+    pub sample_count: vk::SampleCountFlags,
+}
+
+// Records extra commands after the scene, e.g. a UI, before the scene's dynamic rendering ends.
+// Pipelines it binds have to be created with PipelineRenderingCreateInfo for the context's
+// formats and sample count. The viewport and scissor are left covering the whole extent.
+pub type OverlayCallback = Box<dyn FnMut(&ash::Device, vk::CommandBuffer, &OverlayContext)>;
+
+#[derive(Clone, Copy)]
+enum FrameGeometry<'a> {
+    // every mesh is drawn once per model matrix
+    Mesh { model_matrices: &'a [Matrix4<f32>] },
+    // no vertex buffer is bound, the vertex shader generates vertex_count vertices from gl_VertexIndex
+    Procedural { vertex_count: u32 },
+}
+
+// What record_draw_commands draws and for which frame
+#[derive(Clone, Copy)]
+struct FrameDrawInfo<'a> {
+    // frame in flight, selects the per frame descriptor sets
+    current_frame: usize,
+    // swapchain image rendered to, passed on to the overlay
+    present_index: usize,
+    geometry: FrameGeometry<'a>,
+}
+
 impl Renderer {
-    pub fn draw_frame(&mut self, camera: &camera::Camera) {
+    // Draws every mesh once for each model matrix, applied before the scene wide camera::MODEL_MATRIX.
+    // Only the first MAX_OBJECTS model matrices are drawn.
+    pub fn draw_frame(
+        &mut self,
+        camera: &camera::Camera,
+        model_matrices: &[Matrix4<f32>],
+    ) -> Result<(), DeviceLost> {
+        let model_matrices = &model_matrices[..model_matrices.len().min(MAX_OBJECTS)];
+        self.render_frame(camera, FrameGeometry::Mesh { model_matrices })
+    }
+    // Draws a frame with vertex_count procedurally generated vertices, 3 covers the framebuffer
+    // with a fullscreen triangle.
+    pub fn draw_procedural(
+        &mut self,
+        camera: &camera::Camera,
+        vertex_count: u32,
+    ) -> Result<(), DeviceLost> {
+        self.render_frame(camera, FrameGeometry::Procedural { vertex_count })
+    }
+    fn render_frame(
+        &mut self,
+        camera: &camera::Camera,
+        frame_geometry: FrameGeometry,
+    ) -> Result<(), DeviceLost> {
+        // nothing can be drawn until recreate_device has been called
+        if self.device_lost {
+            return Err(DeviceLost);
+        }
+        let result = self.try_render_frame(camera, frame_geometry);
+        self.device_lost = result.is_err();
+        result
+    }
+    fn try_render_frame(
+        &mut self,
+        camera: &camera::Camera,
+        frame_geometry: FrameGeometry,
+    ) -> Result<(), DeviceLost> {
+        // a minimized window has a zero extent, which no swapchain can be created with
+        if self.window_is_minimized() {
+            self.minimized = true;
+            return Ok(());
+        }
+        if self.minimized {
+            // the swapchain may be out of date without a Resized event having arrived
+            self.minimized = false;
+            self.resize_dependent_component_rebuild_needed = true;
+        }
+        self.frame_timer.start_frame();
+        #[cfg(feature = "shaderc")]
+        if self
+            .shader_watcher
+            .as_mut()
+            .is_some_and(ShaderWatcher::poll)
+        {
+            // the previous shaders stay in use until the sources compile again
+            if let Err(error) = self.reload_shaders() {
+                if error.is::<DeviceLost>() {
+                    return Err(DeviceLost);
+                }
+                eprintln!("Failed to reload shaders: {error}");
+            }
+        }
+        // any number of resizes between frames cost at most one rebuild
+        if let Some(pending_window_extent) = self.pending_window_extent.take() {
+            if self
+                .resize_dependent_options
+                .surface_extent_override
+                .is_none()
+                && pending_window_extent != self.sdc.rdc.swapchain_components.surface_resolution
+            {
+                self.resize_dependent_component_rebuild_needed = true;
+            }
+        }
         if self.resize_dependent_component_rebuild_needed {
-            self.handle_window_resize();
+            self.handle_window_resize()?;
             self.resize_dependent_component_rebuild_needed = false;
         }
 
+        let current_frame = self.current_frame;
+        let draw_commands_reuse_fence = self
+            .sdc
+            .command_buffer_components
+            .draw_commands_reuse_fences[current_frame];
+
         unsafe {
             self.sdc
                 .device
-                .wait_for_fences(
-                    &[self.sdc.command_buffer_components.draw_commands_reuse_fence],
-                    true,
-                    u64::MAX,
-                )
-                .unwrap()
+                .wait_for_fences(&[draw_commands_reuse_fence], true, u64::MAX)
+                .map_err(|e| device_lost_or_panic(e, "Failed to wait for frame fence"))?
         };
+        if let Some(timestamp_query_components) = self.sdc.timestamp_query_components.as_mut() {
+            timestamp_query_components.read_results(&self.sdc.device, current_frame);
+        }
 
-        let next_image_result = unsafe {
-            self.sdc.swapchain_loader.acquire_next_image(
-                self.sdc.rdc.swapchain_components.swapchain,
-                u64::MAX,
-                self.sdc.semaphore_components.present_complete_semaphore,
-                vk::Fence::null(),
-            )
+        let headless = self.sic.surface.is_none();
+
+        // the offscreen image stands in for the swapchain when headless
+        let next_image_result = match headless {
+            true => Ok((0, false)),
+            false => unsafe {
+                self.sdc.swapchain_loader.acquire_next_image(
+                    self.sdc.rdc.swapchain_components.swapchain,
+                    u64::MAX,
+                    self.sdc.semaphore_components.present_complete_semaphores[current_frame],
+                    vk::Fence::null(),
+                )
+            },
         };
 
         let present_index = match next_image_result {
@@ -422,34 +1130,133 @@ impl Renderer {
             Err(e) => {
                 if e == vk::Result::ERROR_OUT_OF_DATE_KHR {
                     self.resize_dependent_component_rebuild_needed = true;
-                    return;
+                    return Ok(());
                 }
-                panic!("Failed to acquire next image: {:?}", e);
+                return Err(device_lost_or_panic(e, "Failed to acquire next image"));
             }
         } as usize;
 
-        self.sdc.descriptor_components.uniform_buffers[present_index].write_data_direct(
+        // present_index is independent of current_frame, so the image may still be rendered to
+        // by another frame in flight
+        self.images_in_flight.resize(
+            self.sdc.rdc.swapchain_components.present_images.len(),
+            vk::Fence::null(),
+        );
+        let image_in_flight = self.images_in_flight[present_index];
+        if image_in_flight != vk::Fence::null() && image_in_flight != draw_commands_reuse_fence {
+            let image_finished = unsafe { self.sdc.device.get_fence_status(image_in_flight) }
+                .map_err(|e| device_lost_or_panic(e, "Failed to get image fence status"))?;
+            if !image_finished {
+                unsafe {
+                    self.sdc
+                        .device
+                        .wait_for_fences(&[image_in_flight], true, u64::MAX)
+                        .map_err(|e| device_lost_or_panic(e, "Failed to wait for image fence"))?
+                };
+            }
+        }
+        self.images_in_flight[present_index] = draw_commands_reuse_fence;
+
+        // indexed by current_frame rather than present_index, the fence waited on above guards
+        // the last reads of exactly these buffers whichever image was acquired
+        self.sdc.descriptor_components.write_frame_uniforms(
             &self.sdc.device,
-            &[UniformBuffers {
-                model_matrix: camera::MODEL_MATRIX,
+            current_frame,
+            &FrameUniforms {
                 view_matrix: camera.view_matrix(),
-                projection_matrix: camera
-                    .projection_matrix(self.sdc.rdc.swapchain_components.get_aspect_ratio()),
-            }],
+                projection_matrix: camera.projection_matrix(
+                    self.sdc.rdc.swapchain_components.get_aspect_ratio(),
+                    self.sdc.graphics_pipeline_components.config.reverse_z,
+                ),
+                light_direction: self.light_direction.push(0.0),
+            },
         );
+        if let Some(post_effect) = &mut self.sdc.post_effect {
+            post_effect.write_params(&self.sdc.device, current_frame, &self.post_effect_params);
+        }
+        // procedural geometry has no per object data
+        if let FrameGeometry::Mesh { model_matrices } = frame_geometry {
+            for (object_index, model_matrix) in model_matrices.iter().enumerate() {
+                let model_matrix = camera::MODEL_MATRIX * model_matrix;
+                self.sdc.descriptor_components.write_object_uniforms(
+                    &self.sdc.device,
+                    current_frame,
+                    object_index,
+                    &ObjectUniforms {
+                        model_matrix,
+                        // a singular matrix, e.g. a zero scale, flattens the object, so its
+                        // normals only have to stay finite
+                        normal_matrix: model_matrix
+                            .try_inverse()
+                            .map_or(model_matrix, |inverse| inverse.transpose()),
+                    },
+                );
+            }
+        }
 
-        let color_attachment = vk::RenderingAttachmentInfo::default()
-            .image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
-            .image_view(self.sdc.rdc.swapchain_components.present_image_views[present_index]);
+        let accumulation_image_components = &self.sdc.rdc.accumulation_image_components;
+
+        let (target_image_view, color_load_op) = match accumulation_image_components {
+            Some(accumulation_image_components) => (
+                accumulation_image_components.image_view,
+                vk::AttachmentLoadOp::LOAD,
+            ),
+            None => (
+                self.sdc.rdc.swapchain_components.present_image_views[present_index],
+                vk::AttachmentLoadOp::CLEAR,
+            ),
+        };
+
+        // with a post effect the scene goes to the render target, and the post effect pass writes
+        // target_image_view from it
+        let post_effect = self
+            .sdc
+            .post_effect
+            .as_ref()
+            .zip(self.sdc.rdc.render_target_components.as_ref());
+        let scene_image_view = match post_effect {
+            Some((_, render_target_components)) => render_target_components.image_view,
+            None => target_image_view,
+        };
+
+        let color_clear_value = ClearValue {
+            color: vk::ClearColorValue {
+                float32: self.clear_color,
+            },
+        };
+
+        let color_attachment = match &self.sdc.rdc.msaa_color_image_components {
+            // the multisampled image only has to be kept when it is loaded next frame
+            Some(msaa_color_image_components) => vk::RenderingAttachmentInfo::default()
+                .image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL)
+                .load_op(color_load_op)
+                .clear_value(color_clear_value)
+                .store_op(match color_load_op {
+                    vk::AttachmentLoadOp::LOAD => vk::AttachmentStoreOp::STORE,
+                    _ => vk::AttachmentStoreOp::DONT_CARE,
+                })
+                .image_view(msaa_color_image_components.image_view)
+                .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                .resolve_image_view(scene_image_view)
+                .resolve_image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL),
+            None => vk::RenderingAttachmentInfo::default()
+                .image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL)
+                .load_op(color_load_op)
+                .clear_value(color_clear_value)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .image_view(scene_image_view),
+        };
 
         let depth_attachment = vk::RenderingAttachmentInfo::default()
             .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
             .load_op(vk::AttachmentLoadOp::CLEAR)
             .clear_value(ClearValue {
                 depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 1.0,
+                    depth: self
+                        .sdc
+                        .graphics_pipeline_components
+                        .config
+                        .depth_clear_value(),
                     stencil: 0,
                 },
             })
@@ -463,22 +1270,72 @@ impl Renderer {
             .layer_count(1)
             .render_area(self.sdc.rdc.swapchain_components.surface_resolution.into());
 
-        record_submit_commandbuffer(
+        // the fullscreen triangle covers every pixel, so nothing is loaded
+        let post_effect_color_attachments = [vk::RenderingAttachmentInfo::default()
+            .image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .image_view(target_image_view)];
+        let post_effect_rendering_info = vk::RenderingInfo::default()
+            .color_attachments(&post_effect_color_attachments)
+            .layer_count(1)
+            .render_area(self.sdc.rdc.swapchain_components.surface_resolution.into());
+
+        // the acquired image is first written by the copy from the accumulation image
+        let present_wait_mask = match accumulation_image_components {
+            Some(_) => vk::PipelineStageFlags::TRANSFER,
+            None => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        };
+
+        // nothing is acquired or presented when headless
+        let semaphore_count = match headless {
+            true => 0,
+            false => 1,
+        };
+        let present_wait_masks = [present_wait_mask];
+        let present_complete_semaphores =
+            [self.sdc.semaphore_components.present_complete_semaphores[current_frame]];
+        let rendering_complete_semaphores =
+            [self.sdc.semaphore_components.rendering_complete_semaphores[current_frame]];
+        let present_layout = self.sdc.rdc.swapchain_components.present_layout;
+        // without TRANSFER_SRC usage capture_frame reports the error instead
+        let frame_capture_components =
+            self.sdc.rdc.frame_capture_components.as_ref().filter(|_| {
+                self.sdc
+                    .rdc
+                    .swapchain_components
+                    .image_usage
+                    .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+            });
+
+        // taken for the recording so it can be called mutably while self is borrowed
+        let mut overlay_callback = self.overlay_callback.take();
+        let submit_result = try_record_submit_commandbuffer(
             &self.sdc.device,
             self.sdc.graphics_queue,
-            self.sdc.command_buffer_components.draw_command_buffer,
-            self.sdc.command_buffer_components.draw_commands_reuse_fence,
-            &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT],
-            &[self.sdc.semaphore_components.present_complete_semaphore],
-            &[self.sdc.semaphore_components.rendering_complete_semaphore],
-            |device, draw_command_buffer| {
-                unsafe {
+            self.sdc.command_buffer_components.draw_command_buffers[current_frame],
+            draw_commands_reuse_fence,
+            SubmitSemaphores {
+                wait_mask: &present_wait_masks[..semaphore_count],
+                wait_semaphores: &present_complete_semaphores[..semaphore_count],
+                signal_semaphores: &rendering_complete_semaphores[..semaphore_count],
+            },
+            |device, draw_command_buffer| unsafe {
+                if let Some(timestamp_query_components) = &self.sdc.timestamp_query_components {
+                    timestamp_query_components.record_start(
+                        device,
+                        draw_command_buffer,
+                        current_frame,
+                    );
+                }
+                let present_image = self.sdc.rdc.swapchain_components.present_images[present_index];
+                if accumulation_image_components.is_none() {
                     // dynamic rendering image layout transiton. see https://lesleylai.info/en/vk-khr-dynamic-rendering/
                     let image_memory_barrier = vk::ImageMemoryBarrier::default()
                         .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
                         .old_layout(vk::ImageLayout::UNDEFINED)
                         .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                        .image(self.sdc.rdc.swapchain_components.present_images[present_index])
+                        .image(present_image)
                         .subresource_range(
                             ImageSubresourceRange::default()
                                 .aspect_mask(vk::ImageAspectFlags::COLOR)
@@ -496,92 +1353,122 @@ impl Renderer {
                         &[],
                         &[image_memory_barrier],
                     );
+                }
 
-                    // rendering
-                    device.cmd_begin_rendering(draw_command_buffer, &rendering_info);
-                    device.cmd_bind_pipeline(
-                        draw_command_buffer,
-                        vk::PipelineBindPoint::GRAPHICS,
-                        self.sdc.graphics_pipeline_components.graphics_pipelines
-                            [self.sdc.graphics_pipeline_components.render_pipeline_index],
-                    );
-                    device.cmd_set_scissor(draw_command_buffer, 0, &self.sdc.rdc.scissors);
-                    device.cmd_set_viewport(draw_command_buffer, 0, &self.sdc.rdc.viewports);
-                    device.cmd_bind_vertex_buffers(
-                        draw_command_buffer,
-                        0,
-                        &[self.sdc.vertex_buffer_components.vertex_buffer.buffer],
-                        &[0],
-                    );
-                    device.cmd_bind_index_buffer(
-                        draw_command_buffer,
-                        self.sdc.index_buffer_components.index_buffer.buffer,
-                        0,
-                        vk::IndexType::UINT32,
-                    );
-                    device.cmd_bind_descriptor_sets(
-                        draw_command_buffer,
-                        vk::PipelineBindPoint::GRAPHICS,
-                        self.sdc.graphics_pipeline_components.render_pipeline_layout,
-                        0,
-                        &[self
-                            .sdc
-                            .descriptor_components
-                            .uniform_buffer_descriptor_sets[present_index]],
-                        &[],
-                    );
-                    device.cmd_draw_indexed(
+                if let Some((_, render_target_components)) = post_effect {
+                    render_target_components.record_attachment_barrier(device, draw_command_buffer);
+                }
+
+                // rendering
+                self.record_draw_commands(
+                    device,
+                    draw_command_buffer,
+                    &rendering_info,
+                    FrameDrawInfo {
+                        current_frame,
+                        present_index,
+                        geometry: frame_geometry,
+                    },
+                    overlay_callback.as_mut(),
+                );
+
+                if let Some((post_effect, render_target_components)) = post_effect {
+                    render_target_components.record_sampled_barrier(device, draw_command_buffer);
+                    post_effect.record(
+                        device,
                         draw_command_buffer,
-                        index_buffer_components::INDICES.len() as u32,
-                        1,
-                        0,
-                        0,
-                        1,
+                        current_frame,
+                        &post_effect_rendering_info,
+                        &self.sdc.rdc.viewports,
+                        &self.sdc.rdc.scissors,
                     );
-                    device.cmd_end_rendering(draw_command_buffer);
+                }
 
-                    // dynamic rendering image layout transiton. see https://lesleylai.info/en/vk-khr-dynamic-rendering/
-                    let image_memory_barrier = vk::ImageMemoryBarrier::default()
-                        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
-                        .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                        .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-                        .image(self.sdc.rdc.swapchain_components.present_images[present_index])
-                        .subresource_range(
-                            ImageSubresourceRange::default()
-                                .aspect_mask(vk::ImageAspectFlags::COLOR)
-                                .base_mip_level(0)
-                                .level_count(1)
-                                .base_array_layer(0)
-                                .layer_count(1),
+                match accumulation_image_components {
+                    Some(accumulation_image_components) => {
+                        accumulation_image_components.record_copy_to_present_image(
+                            device,
+                            draw_command_buffer,
+                            present_image,
+                            present_layout,
+                            self.sdc.rdc.swapchain_components.surface_resolution,
                         );
-                    device.cmd_pipeline_barrier(
+                    }
+                    None => {
+                        // dynamic rendering image layout transiton. see https://lesleylai.info/en/vk-khr-dynamic-rendering/
+                        let image_memory_barrier = vk::ImageMemoryBarrier::default()
+                            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                            .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                            .new_layout(present_layout)
+                            .image(present_image)
+                            .subresource_range(
+                                ImageSubresourceRange::default()
+                                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                    .base_mip_level(0)
+                                    .level_count(1)
+                                    .base_array_layer(0)
+                                    .layer_count(1),
+                            );
+                        device.cmd_pipeline_barrier(
+                            draw_command_buffer,
+                            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                            vk::DependencyFlags::empty(),
+                            &[],
+                            &[],
+                            &[image_memory_barrier],
+                        );
+                    }
+                }
+                if let Some(frame_capture_components) = frame_capture_components {
+                    frame_capture_components.record_copy(
+                        device,
                         draw_command_buffer,
-                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-                        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                        vk::DependencyFlags::empty(),
-                        &[],
-                        &[],
-                        &[image_memory_barrier],
+                        present_image,
+                        present_layout,
                     );
-                };
+                }
+                if let Some(timestamp_query_components) = &self.sdc.timestamp_query_components {
+                    timestamp_query_components.record_end(
+                        device,
+                        draw_command_buffer,
+                        current_frame,
+                    );
+                }
             },
         );
+        self.overlay_callback = overlay_callback;
+        submit_result.map_err(|e| device_lost_or_panic(e, "Failed to submit draw commands"))?;
+        if let Some(timestamp_query_components) = self.sdc.timestamp_query_components.as_mut() {
+            timestamp_query_components.mark_pending(current_frame);
+        }
 
-        let wait_semaphores = [self.sdc.semaphore_components.rendering_complete_semaphore];
+        if headless {
+            // with a single offscreen image the frame has to finish before the next one reuses it
+            unsafe {
+                self.sdc
+                    .device
+                    .wait_for_fences(&[draw_commands_reuse_fence], true, u64::MAX)
+                    .map_err(|e| device_lost_or_panic(e, "Failed to wait for frame fence"))?
+            };
+            self.last_present_index = Some(present_index);
+            self.current_frame = (current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
+            return Ok(());
+        }
 
         let swapchains = [self.sdc.rdc.swapchain_components.swapchain];
 
         let image_indices = [present_index as u32];
 
         let present_info = vk::PresentInfoKHR::default()
-            .wait_semaphores(&wait_semaphores)
+            .wait_semaphores(&rendering_complete_semaphores)
             .swapchains(&swapchains)
             .image_indices(&image_indices);
 
         let present_result = unsafe {
             self.sdc
                 .swapchain_loader
-                .queue_present(self.sdc.graphics_queue, &present_info)
+                .queue_present(self.sdc.present_queue, &present_info)
         };
 
         match present_result {
@@ -589,41 +1476,1247 @@ impl Renderer {
                 if e == vk::Result::ERROR_OUT_OF_DATE_KHR || e == vk::Result::SUBOPTIMAL_KHR {
                     self.resize_dependent_component_rebuild_needed = true;
                 } else {
-                    panic!("Failed to present image {:?}", e);
+                    return Err(device_lost_or_panic(e, "Failed to present image"));
                 }
             }
-            _ => (),
+            _ => self.last_present_index = Some(present_index),
         }
+
+        self.current_frame = (current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
+        Ok(())
     }
 }
 
 impl Renderer {
-    fn handle_window_resize(&mut self) {
-        unsafe { self.sdc.device.device_wait_idle().unwrap() };
-        self.sdc
-            .rdc
-            .cleanup(&self.sdc.device, &self.sdc.swapchain_loader);
-        self.sdc.rdc = ResizeDependentComponents::new(
+    fn record_draw_commands(
+        &self,
+        device: &ash::Device,
+        draw_command_buffer: vk::CommandBuffer,
+        rendering_info: &vk::RenderingInfo,
+        frame: FrameDrawInfo,
+        overlay_callback: Option<&mut OverlayCallback>,
+    ) {
+        let FrameDrawInfo {
+            current_frame,
+            present_index,
+            geometry: frame_geometry,
+        } = frame;
+        unsafe {
+            device.cmd_begin_rendering(draw_command_buffer, rendering_info);
+            let split_geometry_components = &self.sdc.split_geometry_components;
+            let pipeline_index = match (frame_geometry, split_geometry_components) {
+                (FrameGeometry::Procedural { .. }, _) => PROCEDURAL_PIPELINE_INDEX,
+                (FrameGeometry::Mesh { .. }, Some(_)) => SPLIT_PIPELINE_INDEX,
+                (FrameGeometry::Mesh { .. }, None) => {
+                    self.sdc.graphics_pipeline_components.render_pipeline_index
+                }
+            };
+            device.cmd_bind_pipeline(
+                draw_command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.sdc.graphics_pipeline_components.graphics_pipelines[pipeline_index],
+            );
+            device.cmd_set_scissor(draw_command_buffer, 0, &self.sdc.rdc.scissors);
+            device.cmd_set_viewport(draw_command_buffer, 0, &self.sdc.rdc.viewports);
+            let pipeline_depth_bias = self.sdc.graphics_pipeline_components.config.depth_bias;
+            record_depth_bias(device, draw_command_buffer, pipeline_depth_bias);
+            device.cmd_bind_descriptor_sets(
+                draw_command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.sdc.graphics_pipeline_components.render_pipeline_layout,
+                0,
+                &[self.sdc.descriptor_components.frame_descriptor_sets[current_frame]],
+                &[],
+            );
+            device.cmd_push_constants(
+                draw_command_buffer,
+                self.sdc.graphics_pipeline_components.render_pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                FragmentPushConstants { tint: self.tint }.as_bytes(),
+            );
+            match (frame_geometry, split_geometry_components) {
+                (FrameGeometry::Procedural { vertex_count }, _) => {
+                    device.cmd_draw(draw_command_buffer, vertex_count, 1, 0, 0);
+                }
+                (FrameGeometry::Mesh { .. }, Some(split_geometry_components))
+                    if split_geometry_components.is_empty() => {}
+                (FrameGeometry::Mesh { model_matrices }, Some(split_geometry_components)) => {
+                    device.cmd_bind_vertex_buffers(
+                        draw_command_buffer,
+                        0,
+                        &[
+                            split_geometry_components
+                                .position_buffer_components
+                                .vertex_buffer
+                                .buffer,
+                            split_geometry_components
+                                .attribute_buffer_components
+                                .vertex_buffer
+                                .buffer,
+                            self.sdc.instance_buffer_components.vertex_buffer.buffer,
+                        ],
+                        &[0, 0, 0],
+                    );
+                    device.cmd_bind_index_buffer(
+                        draw_command_buffer,
+                        split_geometry_components
+                            .index_buffer_components
+                            .index_buffer
+                            .buffer,
+                        0,
+                        split_geometry_components.index_buffer_components.index_type,
+                    );
+                    self.record_model_draws(
+                        device,
+                        draw_command_buffer,
+                        current_frame,
+                        split_geometry_components
+                            .index_buffer_components
+                            .index_count,
+                        model_matrices.len(),
+                    );
+                }
+                (FrameGeometry::Mesh { model_matrices }, None) => {
+                    for (_, mesh) in self.sdc.meshes.iter().filter(|(_, mesh)| !mesh.is_empty()) {
+                        record_depth_bias(
+                            device,
+                            draw_command_buffer,
+                            mesh.depth_bias.or(pipeline_depth_bias),
+                        );
+                        device.cmd_bind_vertex_buffers(
+                            draw_command_buffer,
+                            0,
+                            &[
+                                mesh.vertex_buffer_components.vertex_buffer.buffer,
+                                self.sdc.instance_buffer_components.vertex_buffer.buffer,
+                            ],
+                            &[0, 0],
+                        );
+                        device.cmd_bind_index_buffer(
+                            draw_command_buffer,
+                            mesh.index_buffer_components.index_buffer.buffer,
+                            0,
+                            mesh.index_buffer_components.index_type,
+                        );
+                        self.record_model_draws(
+                            device,
+                            draw_command_buffer,
+                            current_frame,
+                            mesh.index_count(),
+                            model_matrices.len(),
+                        );
+                    }
+                }
+            }
+            if let (FrameGeometry::Mesh { .. }, Some(grid)) = (frame_geometry, &self.sdc.grid) {
+                device.cmd_bind_pipeline(
+                    draw_command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.sdc.graphics_pipeline_components.graphics_pipelines[GRID_PIPELINE_INDEX],
+                );
+                record_depth_bias(device, draw_command_buffer, self.grid_depth_bias);
+                device.cmd_bind_vertex_buffers(
+                    draw_command_buffer,
+                    0,
+                    &[grid.vertex_buffer_components.vertex_buffer.buffer],
+                    &[0],
+                );
+                device.cmd_draw(draw_command_buffer, grid.vertex_count, 1, 0, 0);
+            }
+            // last, so it only covers the pixels no mesh was drawn to
+            if let (FrameGeometry::Mesh { .. }, Some(_)) = (frame_geometry, &self.sdc.skybox) {
+                device.cmd_bind_pipeline(
+                    draw_command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.sdc.graphics_pipeline_components.graphics_pipelines[SKYBOX_PIPELINE_INDEX],
+                );
+                // a biased skybox would fail its test against the cleared depth
+                record_depth_bias(device, draw_command_buffer, None);
+                device.cmd_draw(draw_command_buffer, 3, 1, 0, 0);
+            }
+            if let Some(overlay_callback) = overlay_callback {
+                let pipeline_info = &self.sdc.graphics_pipeline_components.info;
+                overlay_callback(
+                    device,
+                    draw_command_buffer,
+                    &OverlayContext {
+                        present_index,
+                        extent: self.sdc.rdc.swapchain_components.surface_resolution,
+                        color_attachment_format: pipeline_info.color_attachment_format,
+                        depth_attachment_format: pipeline_info.depth_attachment_format,
+                        sample_count: pipeline_info.sample_count,
+                    },
+                );
+            }
+            device.cmd_end_rendering(draw_command_buffer);
+        }
+    }
+    // One indexed draw of the bound mesh per object, with that object's uniforms bound, each
+    // covering every instance
+    fn record_model_draws(
+        &self,
+        device: &ash::Device,
+        draw_command_buffer: vk::CommandBuffer,
+        current_frame: usize,
+        index_count: u32,
+        object_count: usize,
+    ) {
+        for object_index in 0..object_count {
+            self.bind_object_uniforms(device, draw_command_buffer, current_frame, object_index);
+            match &self.sdc.indirect_command_buffer {
+                Some(indirect_command_buffer) => self.record_indirect_draws(
+                    device,
+                    draw_command_buffer,
+                    indirect_command_buffer.buffer,
+                ),
+                None => unsafe {
+                    device.cmd_draw_indexed(
+                        draw_command_buffer,
+                        index_count,
+                        self.sdc.instance_count,
+                        0,
+                        0,
+                        0,
+                    )
+                },
+            }
+        }
+    }
+    fn record_indirect_draws(
+        &self,
+        device: &ash::Device,
+        draw_command_buffer: vk::CommandBuffer,
+        indirect_command_buffer: vk::Buffer,
+    ) {
+        let stride = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        let draw_count = self.sdc.indirect_draw_count;
+        unsafe {
+            if self.sdc.enabled_features.multi_draw_indirect == vk::TRUE {
+                device.cmd_draw_indexed_indirect(
+                    draw_command_buffer,
+                    indirect_command_buffer,
+                    0,
+                    draw_count,
+                    stride,
+                );
+                return;
+            }
+            // drawCount has to be 0 or 1 without the feature
+            for draw_index in 0..draw_count {
+                device.cmd_draw_indexed_indirect(
+                    draw_command_buffer,
+                    indirect_command_buffer,
+                    (draw_index * stride) as vk::DeviceSize,
+                    1,
+                    stride,
+                );
+            }
+        }
+    }
+    fn bind_object_uniforms(
+        &self,
+        device: &ash::Device,
+        draw_command_buffer: vk::CommandBuffer,
+        current_frame: usize,
+        object_index: usize,
+    ) {
+        let descriptor_components = &self.sdc.descriptor_components;
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                draw_command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.sdc.graphics_pipeline_components.render_pipeline_layout,
+                1,
+                &[descriptor_components.object_descriptor_sets[current_frame]],
+                &[descriptor_components.dynamic_offset(object_index)],
+            )
+        };
+    }
+}
+
+impl Renderer {
+    fn window_is_minimized(&self) -> bool {
+        self.sic.window.as_ref().is_some_and(|window| {
+            let window_size = window.inner_size();
+            window_size.width == 0 || window_size.height == 0
+        })
+    }
+    fn handle_window_resize(&mut self) -> Result<(), DeviceLost> {
+        if self.window_is_minimized() {
+            return Ok(());
+        }
+        self.last_present_index = None;
+        self.images_in_flight.clear();
+        // created from the old swapchain, which lets the driver reuse its resources and keeps
+        // presenting the frames in flight
+        let sdc = &mut self.sdc;
+        let rdc = ResizeDependentComponents::new(
+            &mut sdc.command_buffer_components.setup_context(
+                &sdc.device,
+                &mut sdc.memory_allocator,
+                sdc.graphics_queue,
+            ),
+            &SwapchainSettings {
+                window_surface: self.sic.window.as_ref().zip(self.sic.surface),
+                surface_loader: &self.sic.surface_loader,
+                swapchain_loader: &sdc.swapchain_loader,
+                physical_device: sdc.physical_device,
+                physical_device_memory_properties: &sdc.physical_device_memory_properties,
+                graphics_queue_family_index: sdc.graphics_queue_family_index,
+                present_queue_family_index: sdc.present_queue_family_index,
+                present_mode_preference: sdc.present_mode_preference,
+                desired_image_count: sdc.desired_swapchain_image_count,
+            },
+            sdc.depth_format,
+            sdc.msaa_samples,
+            &self.resize_dependent_options,
+            sdc.rdc.swapchain_components.swapchain,
+        )
+        .map_err(swapchain_device_lost_or_panic)?;
+        let old_rdc = std::mem::replace(&mut self.sdc.rdc, rdc);
+        // only the frames in flight can still use the old components, a lost device has none
+        // left
+        let device_idle = self.wait_for_device_idle();
+        let old_color_format = old_rdc.swapchain_components.surface_format.format;
+        let old_scene_color_format = old_rdc.scene_color_format();
+        old_rdc.cleanup(
             &self.sdc.device,
-            &self.sic.window,
-            self.sic.surface,
-            &self.sic.surface_loader,
             &self.sdc.swapchain_loader,
-            self.sdc.physical_device,
-            self.sdc.command_buffer_components.setup_command_buffer,
+            &mut self.sdc.memory_allocator,
+        );
+        device_idle?;
+        // the pipelines are built for the color attachment format, which e.g. an exclusive
+        // fullscreen surface may not share. The scene's changes when the post effect target is
+        // added or removed.
+        if self.sdc.rdc.scene_color_format() != old_scene_color_format {
+            self.rebuild_graphics_pipelines(self.sdc.graphics_pipeline_components.config)?;
+        }
+        if self.sdc.rdc.swapchain_components.surface_format.format != old_color_format {
+            if let Some(post_effect) = &mut self.sdc.post_effect {
+                post_effect.rebuild_pipeline(
+                    &self.sdc.device,
+                    self.sdc.pipeline_cache_components.pipeline_cache,
+                    self.sdc.rdc.swapchain_components.surface_format.format,
+                );
+            }
+        }
+        // the post effect samples the rebuilt target from now on
+        if let (Some(post_effect), Some(render_target_components)) = (
+            &self.sdc.post_effect,
+            &self.sdc.rdc.render_target_components,
+        ) {
+            post_effect.write_render_target(&self.sdc.device, render_target_components.image_view);
+        }
+        Ok(())
+    }
+    // Waits for every submitted frame, e.g. before destroying what they use
+    fn wait_for_device_idle(&self) -> Result<(), DeviceLost> {
+        unsafe { self.sdc.device.device_wait_idle() }
+            .map_err(|e| device_lost_or_panic(e, "Failed to wait for the device to be idle"))
+    }
+    // Waits until frame_index's last submission finished, so its per-frame resources can be
+    // replaced while the other frames keep rendering
+    fn wait_for_frame(&self, frame_index: usize) -> Result<(), DeviceLost> {
+        let draw_commands_reuse_fence = self
+            .sdc
+            .command_buffer_components
+            .draw_commands_reuse_fences[frame_index];
+        unsafe {
+            self.sdc
+                .device
+                .wait_for_fences(&[draw_commands_reuse_fence], true, u64::MAX)
+        }
+        .map_err(|e| device_lost_or_panic(e, "Failed to wait for frame fence"))
+    }
+    // Single entry point for changing fixed function pipeline state at runtime.
+    // The old pipelines are destroyed after the single wait for the device, the cleanup methods
+    // don't wait themselves.
+    fn rebuild_graphics_pipelines(
+        &mut self,
+        pipeline_config: PipelineConfig,
+    ) -> Result<(), DeviceLost> {
+        let graphics_pipeline_components = GraphicsPipelineComponents::new(
+            &self.sdc.device,
+            self.sdc.pipeline_cache_components.pipeline_cache,
+            &self.sdc.shaders.pipeline_shader_stages(),
+            &self.sdc.specialization_constants,
+            &self.sdc.descriptor_components.descriptor_set_layouts(),
+            &PipelineAttachments {
+                color_format: self.sdc.rdc.scene_color_format(),
+                depth_format: self.sdc.depth_format,
+                sample_count: self.sdc.msaa_samples,
+            },
+            &pipeline_config.validated(
+                &self.sdc.enabled_features,
+                &self.sdc.enabled_line_rasterization_features,
+            ),
+        );
+        let old_graphics_pipeline_components = std::mem::replace(
+            &mut self.sdc.graphics_pipeline_components,
+            graphics_pipeline_components,
+        );
+        let device_idle = self.wait_for_device_idle();
+        debug_assert!(
             self.sdc
                 .command_buffer_components
-                .setup_commands_reuse_fence,
-            &self.sdc.physical_device_memory_properties,
+                .frames_finished(&self.sdc.device),
+            "Destroying pipelines still in use"
+        );
+        old_graphics_pipeline_components.cleanup(&self.sdc.device);
+        device_idle
+    }
+    // e.g. EQUAL for the main pass after a depth prepass, flipped for reverse z
+    pub fn set_depth_compare_op(
+        &mut self,
+        depth_compare_op: vk::CompareOp,
+    ) -> Result<(), DeviceLost> {
+        let mut pipeline_config = self.sdc.graphics_pipeline_components.config;
+        if pipeline_config.depth_compare_op == depth_compare_op {
+            return Ok(());
+        }
+        pipeline_config.depth_compare_op = depth_compare_op;
+        self.rebuild_graphics_pipelines(pipeline_config)
+    }
+    // The camera projection follows automatically, it is built with the current setting each frame
+    pub fn set_reverse_z(&mut self, reverse_z: bool) -> Result<(), DeviceLost> {
+        let mut pipeline_config = self.sdc.graphics_pipeline_components.config;
+        if pipeline_config.reverse_z == reverse_z {
+            return Ok(());
+        }
+        if reverse_z {
+            warn_if_not_float_depth(self.sdc.depth_format);
+        }
+        pipeline_config.reverse_z = reverse_z;
+        self.rebuild_graphics_pipelines(pipeline_config)
+    }
+    // Call on WindowEvent::Resized, the swapchain is rebuilt on the next frame if the size changed
+    pub fn window_resized(&mut self, width: u32, height: u32) {
+        self.pending_window_extent = Some(vk::Extent2D { width, height });
+    }
+    // Borderless or exclusive fullscreen, None returns to a window. Ignored when headless.
+    pub fn set_fullscreen(&mut self, fullscreen: Option<Fullscreen>) {
+        let Some(window) = &self.sic.window else {
+            return;
+        };
+        window.set_fullscreen(fullscreen);
+        // the Resized event rebuilds the swapchain when the size changes, exclusive fullscreen
+        // can change the surface without changing the size
+        self.resize_dependent_component_rebuild_needed = true;
+    }
+    pub fn fullscreen(&self) -> Option<Fullscreen> {
+        self.sic.window.as_ref()?.fullscreen()
+    }
+    // Hides the cursor and locks it in place, or confines it to the window on platforms that
+    // can't lock it. Returns whether the grab took effect, always false when headless.
+    pub fn set_cursor_grab(&self, grab: bool) -> bool {
+        let Some(window) = &self.sic.window else {
+            return false;
+        };
+        if !grab {
+            let _ = window.set_cursor_grab(CursorGrabMode::None);
+            window.set_cursor_visible(true);
+            return false;
+        }
+        let grabbed = window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined));
+        if let Err(error) = &grabbed {
+            eprintln!("Failed to grab the cursor: {error}");
+        }
+        window.set_cursor_visible(grabbed.is_err());
+        grabbed.is_ok()
+    }
+    // Renders at a fixed extent instead of tracking the window size, None returns to tracking the window.
+    // The extent is clamped to what the surface supports.
+    // Headless renderers have no window to track and keep their current extent on None.
+    pub fn set_surface_extent_override(&mut self, surface_extent_override: Option<vk::Extent2D>) {
+        if surface_extent_override.is_none() && self.sic.surface.is_none() {
+            return;
+        }
+        self.resize_dependent_options.surface_extent_override = surface_extent_override;
+        self.resize_dependent_component_rebuild_needed = true;
+    }
+    // Disabling the clear keeps color from previous frames, for accumulation and trails.
+    // Rendering then goes to an owned color target that is copied to the swapchain each frame.
+    // Returns false, and frames stay cleared, if the surface doesn't allow copying into its
+    // images.
+    pub fn set_clear_color_enabled(&mut self, clear_color_enabled: bool) -> bool {
+        if !clear_color_enabled && !self.persistent_color_target_supported() {
+            return false;
+        }
+        if self.resize_dependent_options.persistent_color_target == clear_color_enabled {
+            self.resize_dependent_options.persistent_color_target = !clear_color_enabled;
+            self.resize_dependent_component_rebuild_needed = true;
+        }
+        true
+    }
+    fn persistent_color_target_supported(&self) -> bool {
+        self.sdc
+            .rdc
+            .swapchain_components
+            .image_usage
+            .contains(vk::ImageUsageFlags::TRANSFER_DST)
+    }
+    // Depth bias of every mesh draw without one of its own, None disables depth bias. A nonzero
+    // clamp is only honored if the device was created with depthBiasClamp enabled, which happens
+    // when the user settings request a clamped bias.
+    // Depth bias is dynamic state, so the pipelines are not rebuilt.
+    pub fn set_depth_bias(&mut self, depth_bias: Option<DepthBias>) {
+        let enabled_features = &self.sdc.enabled_features;
+        self.sdc.graphics_pipeline_components.config.depth_bias =
+            depth_bias.map(|depth_bias| depth_bias.validated(enabled_features));
+    }
+    // Offsets the grid's depth, e.g. to draw it over a floor at y = 0 without z-fighting. None
+    // draws it unbiased. The clamp has the same requirements as in set_depth_bias.
+    pub fn set_grid_depth_bias(&mut self, grid_depth_bias: Option<DepthBias>) {
+        self.grid_depth_bias = grid_depth_bias
+            .map(|grid_depth_bias| grid_depth_bias.validated(&self.sdc.enabled_features));
+    }
+    // Only honored if the device was created with depthBounds enabled, which happens when the
+    // user settings request depth bounds.
+    pub fn set_depth_bounds(
+        &mut self,
+        depth_bounds: Option<DepthBounds>,
+    ) -> Result<(), DeviceLost> {
+        let mut pipeline_config = self.sdc.graphics_pipeline_components.config;
+        if pipeline_config.depth_bounds == depth_bounds {
+            return Ok(());
+        }
+        pipeline_config.depth_bounds = depth_bounds;
+        self.rebuild_graphics_pipelines(pipeline_config)
+    }
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) -> Result<(), DeviceLost> {
+        let mut pipeline_config = self.sdc.graphics_pipeline_components.config;
+        if pipeline_config.blend_mode == blend_mode {
+            return Ok(());
+        }
+        pipeline_config.blend_mode = blend_mode;
+        self.rebuild_graphics_pipelines(pipeline_config)
+    }
+    pub fn set_cull_mode(
+        &mut self,
+        cull_mode: vk::CullModeFlags,
+        front_face: vk::FrontFace,
+    ) -> Result<(), DeviceLost> {
+        let mut pipeline_config = self.sdc.graphics_pipeline_components.config;
+        if pipeline_config.cull_mode == cull_mode && pipeline_config.front_face == front_face {
+            return Ok(());
+        }
+        pipeline_config.cull_mode = cull_mode;
+        pipeline_config.front_face = front_face;
+        self.rebuild_graphics_pipelines(pipeline_config)
+    }
+    // e.g. LINE_LIST or POINT_LIST to draw the meshes' indices as lines or points, culling is
+    // disabled for both
+    pub fn set_topology(&mut self, topology: vk::PrimitiveTopology) -> Result<(), DeviceLost> {
+        let mut pipeline_config = self.sdc.graphics_pipeline_components.config;
+        if pipeline_config.topology == topology {
+            return Ok(());
+        }
+        pipeline_config.topology = topology;
+        self.rebuild_graphics_pipelines(pipeline_config)
+    }
+    // FILL, LINE for wireframe or POINT. Falls back to FILL when the device lacks fillModeNonSolid.
+    pub fn set_polygon_mode(&mut self, polygon_mode: vk::PolygonMode) -> Result<(), DeviceLost> {
+        let mut pipeline_config = self.sdc.graphics_pipeline_components.config;
+        if pipeline_config.polygon_mode == polygon_mode {
+            return Ok(());
+        }
+        pipeline_config.polygon_mode = polygon_mode;
+        self.rebuild_graphics_pipelines(pipeline_config)
+    }
+    // Smooth, bresenham or stippled lines when the device supports VK_EXT_line_rasterization.
+    // Unsupported modes fall back to the default lines.
+    pub fn set_line_mode(&mut self, line_mode: Option<LineMode>) -> Result<(), DeviceLost> {
+        let mut pipeline_config = self.sdc.graphics_pipeline_components.config;
+        if pipeline_config.line_mode == line_mode {
+            return Ok(());
+        }
+        pipeline_config.line_mode = line_mode;
+        self.rebuild_graphics_pipelines(pipeline_config)
+    }
+    // Linear RGBA the color attachment is cleared to, unused while clearing is disabled
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.clear_color = clear_color;
+    }
+    // Multiplies the vertex color of everything drawn, e.g. to flash or highlight the scene.
+    // White leaves the colors unchanged.
+    pub fn set_tint(&mut self, tint: [f32; 4]) {
+        self.tint = tint;
+    }
+    // Read by the post effect's fragment shader from the next frame on, see PostEffectParams.
+    // Kept when the effect is replaced.
+    pub fn set_post_effect_params(&mut self, post_effect_params: PostEffectParams) {
+        self.post_effect_params = post_effect_params;
+    }
+    // For FXAA_POST_EFFECT_SOURCE, whose parameters start out as FxaaParams::default()
+    pub fn set_fxaa_params(&mut self, fxaa_params: FxaaParams) {
+        self.set_post_effect_params(fxaa_params.into());
+    }
+    // World space direction of the directional light
+    pub fn set_light_direction(&mut self, light_direction: Vector3<f32>) {
+        self.light_direction = light_direction.normalize();
+    }
+    // Called while recording every frame from then on, None removes it
+    pub fn set_overlay_callback(&mut self, overlay_callback: Option<OverlayCallback>) {
+        self.overlay_callback = overlay_callback;
+    }
+    // Time between the starts of the last two frames
+    pub fn last_frame_time(&self) -> Duration {
+        self.frame_timer.last_frame_time()
+    }
+    // Time the GPU spent on a recent frame, from MAX_FRAMES_IN_FLIGHT frames ago so reading it
+    // never waits. Zero until then or when the device has no timestamp support.
+    pub fn last_gpu_time(&self) -> Duration {
+        self.sdc
+            .timestamp_query_components
+            .as_ref()
+            .map_or(Duration::ZERO, TimestampQueryComponents::last_gpu_time)
+    }
+    // Averaged over the last frame_timer::FRAME_TIME_SAMPLES frames
+    pub fn average_fps(&self) -> f32 {
+        self.frame_timer.average_fps()
+    }
+    pub fn current_extent(&self) -> vk::Extent2D {
+        self.sdc.rdc.swapchain_components.surface_resolution
+    }
+    // Width over height of the drawn frames, which draw_frame projects the camera with
+    pub fn aspect_ratio(&self) -> f32 {
+        self.sdc.rdc.swapchain_components.get_aspect_ratio()
+    }
+    // Cursor position in physical pixels relative to the top left of the window
+    pub fn set_cursor_position(&mut self, x: f32, y: f32) {
+        self.cursor_position = Some((x, y));
+    }
+    // World space picking ray under the last cursor position, or the center of the screen before
+    // the cursor has moved. Returns (origin, normalized direction).
+    pub fn cursor_world_ray(&self, camera: &camera::Camera) -> (Point3<f32>, Vector3<f32>) {
+        let extent = self.current_extent();
+        let (width, height) = (extent.width as f32, extent.height as f32);
+        let (x, y) = self.cursor_position.unwrap_or((width / 2.0, height / 2.0));
+        camera.screen_ray(x, y, width, height)
+    }
+    // Physical devices available to this renderer's instance and window, for choosing
+    // UserSettings::preferred_physical_device_id before calling update_user_settings. The free
+    // enumerate_devices does the same without a renderer.
+    pub fn enumerate_devices(&self) -> Vec<DeviceInfo> {
+        enumerate_devices(&self.sic.entry, &self.sic.instance, self.sic.surface)
+    }
+    pub fn pipeline_info(&self) -> PipelineInfo {
+        self.sdc.graphics_pipeline_components.info
+    }
+    pub fn request_redraw(&self) {
+        if let Some(window) = &self.sic.window {
+            window.request_redraw();
+        }
+    }
+    // Physical pixels per logical pixel of the window's display, 1.0 when headless
+    pub fn scale_factor(&self) -> f64 {
+        self.sic
+            .window
+            .as_ref()
+            .map_or(1.0, |window| window.scale_factor())
+    }
+    // Replaces the drawn geometry with positions and attributes uploaded as two vertex streams.
+    // Returns false, and keeps the current geometry, if the streams' lengths differ. Empty
+    // geometry draws nothing.
+    pub fn set_geometry_split(
+        &mut self,
+        positions: &[[f32; 3]],
+        attributes: &[VertexAttribs],
+        indices: &[Index],
+    ) -> Result<bool, DeviceLost> {
+        if positions.len() != attributes.len() {
+            return Ok(false);
+        }
+        self.wait_for_device_idle()?;
+        if let Some(split_geometry_components) = self.sdc.split_geometry_components.take() {
+            split_geometry_components.cleanup(&self.sdc.device, &mut self.sdc.memory_allocator);
+        }
+        self.sdc.split_geometry_components = Some(SplitGeometryComponents::new(
+            &self.sdc.device,
+            &mut self.sdc.memory_allocator,
+            positions,
+            attributes,
+            indices,
+            &self
+                .sdc
+                .command_buffer_components
+                .upload_commands(self.sdc.graphics_queue),
+        )?);
+        Ok(true)
+    }
+    // Adds a mesh drawn after the existing ones. Without vertices or indices it draws nothing
+    // until update_mesh gives it some.
+    pub fn add_mesh(
+        &mut self,
+        vertices: &[Vertex],
+        indices: &[Index],
+    ) -> Result<MeshId, DeviceLost> {
+        let sdc = &mut self.sdc;
+        let mesh = Mesh::new(
+            &sdc.device,
+            &mut sdc.memory_allocator,
+            vertices,
+            indices,
+            &sdc.command_buffer_components
+                .upload_commands(sdc.graphics_queue),
+        )?;
+        let mesh_id = MeshId(sdc.next_mesh_id);
+        sdc.next_mesh_id += 1;
+        sdc.meshes.push((mesh_id, mesh));
+        Ok(mesh_id)
+    }
+    // Returns false if there was no mesh with mesh_id
+    pub fn remove_mesh(&mut self, mesh_id: MeshId) -> Result<bool, DeviceLost> {
+        let Some(position) = self.mesh_position(mesh_id) else {
+            return Ok(false);
+        };
+        self.wait_for_device_idle()?;
+        let (_, mesh) = self.sdc.meshes.remove(position);
+        mesh.cleanup(&self.sdc.device, &mut self.sdc.memory_allocator);
+        Ok(true)
+    }
+    // Replaces a mesh's geometry, DEFAULT_MESH_ID for the mesh the renderer started with.
+    // Returns false if there was no mesh with mesh_id. Empty geometry keeps the mesh, but draws
+    // nothing.
+    pub fn update_mesh(
+        &mut self,
+        mesh_id: MeshId,
+        vertices: &[Vertex],
+        indices: &[Index],
+    ) -> Result<bool, DeviceLost> {
+        let Some(position) = self.mesh_position(mesh_id) else {
+            return Ok(false);
+        };
+        self.wait_for_device_idle()?;
+        let sdc = &mut self.sdc;
+        let upload_commands = sdc
+            .command_buffer_components
+            .upload_commands(sdc.graphics_queue);
+        sdc.meshes[position].1.update(
+            &sdc.device,
+            &mut sdc.memory_allocator,
+            vertices,
+            indices,
+            &upload_commands,
+        )?;
+        Ok(true)
+    }
+    // Replaces the pipeline config's depth bias for one mesh, e.g. for a decal lying on another
+    // mesh. None goes back to the pipeline config's. Returns false if there was no mesh with
+    // mesh_id.
+    pub fn set_mesh_depth_bias(&mut self, mesh_id: MeshId, depth_bias: Option<DepthBias>) -> bool {
+        let Some(position) = self.mesh_position(mesh_id) else {
+            return false;
+        };
+        let enabled_features = &self.sdc.enabled_features;
+        self.sdc.meshes[position].1.depth_bias =
+            depth_bias.map(|depth_bias| depth_bias.validated(enabled_features));
+        true
+    }
+    // None if there is no mesh with mesh_id or it has no vertices
+    pub fn mesh_bounds(&self, mesh_id: MeshId) -> Option<Aabb> {
+        let position = self.mesh_position(mesh_id)?;
+        self.sdc.meshes[position].1.bounds
+    }
+    fn mesh_position(&self, mesh_id: MeshId) -> Option<usize> {
+        self.sdc.meshes.iter().position(|(id, _)| *id == mesh_id)
+    }
+    // Every mesh draw is repeated once per instance, the buffer is only reallocated when the
+    // instances don't fit. An empty slice draws no meshes. Every frame in flight reads the same
+    // buffer, so all of them have to finish first, but other queues aren't waited for.
+    pub fn set_instances(&mut self, instances: &[InstanceData]) -> Result<(), DeviceLost> {
+        for frame_index in 0..MAX_FRAMES_IN_FLIGHT {
+            self.wait_for_frame(frame_index)?;
+        }
+        let sdc = &mut self.sdc;
+        sdc.instance_count = instances.len() as u32;
+        sdc.instances_set = true;
+        if instances.is_empty() {
+            return Ok(());
+        }
+        if instances.len() > sdc.instance_buffer_components.vertex_buffer.capacity() {
+            sdc.instance_buffer_components.resize(
+                &sdc.device,
+                &mut sdc.memory_allocator,
+                instances.len(),
+            );
+        }
+        let instance_vertices: Vec<_> = instances.iter().map(InstanceVertex::from).collect();
+        let upload_commands = sdc
+            .command_buffer_components
+            .upload_commands(sdc.graphics_queue);
+        sdc.instance_buffer_components.update_vertices(
+            &sdc.device,
+            &instance_vertices,
+            &upload_commands,
+        )
+    }
+    // Copies data to the storage buffer shaders can read as set 0 binding 3, e.g. per-object
+    // data too large for uniforms, laid out the way the shader declares it. Every frame reads the
+    // same data until it is set again. Each frame's copy is written once that frame's last
+    // submission finished, rather than waiting for the whole device.
+    pub fn set_storage_data(&mut self, data: &[u8]) -> Result<(), DeviceLost> {
+        for frame_index in 0..MAX_FRAMES_IN_FLIGHT {
+            self.wait_for_frame(frame_index)?;
+            self.sdc.descriptor_components.write_storage_data(
+                &self.sdc.device,
+                &mut self.sdc.memory_allocator,
+                frame_index,
+                data,
+            );
+        }
+        self.sdc.storage_data_set = true;
+        Ok(())
+    }
+    // Replaces the texture bound at set 0 binding 1. Each frame's set is rewritten once that
+    // frame's last submission finished, rather than waiting for the whole device. The current
+    // texture is kept if path can't be loaded.
+    pub fn set_texture(&mut self, path: &str) -> anyhow::Result<()> {
+        let sdc = &mut self.sdc;
+        let texture = Texture::new_from_path(
+            &mut sdc.command_buffer_components.setup_context(
+                &sdc.device,
+                &mut sdc.memory_allocator,
+                sdc.graphics_queue,
+            ),
+            path,
+            &sdc.sampler_config,
+            sdc.sampler_anisotropy,
+        )?;
+        for frame_index in 0..MAX_FRAMES_IN_FLIGHT {
+            if let Err(device_lost) = self.wait_for_frame(frame_index) {
+                texture.cleanup(&self.sdc.device, &mut self.sdc.memory_allocator);
+                return Err(device_lost.into());
+            }
+            self.sdc.descriptor_components.update_texture(
+                &self.sdc.device,
+                frame_index,
+                texture.image_view,
+                texture.sampler,
+            );
+        }
+        let sdc = &mut self.sdc;
+        // every frame that could still sample the previous texture has finished
+        std::mem::replace(&mut sdc.texture, texture)
+            .cleanup(&sdc.device, &mut sdc.memory_allocator);
+        sdc.texture_set = true;
+        Ok(())
+    }
+    // Draws a cubemap behind the meshes, faces in the order +x, -x, +y, -y, +z, -z. Replaces
+    // the previous skybox, which is kept if a face can't be loaded.
+    pub fn set_skybox(&mut self, paths: [&str; 6]) -> anyhow::Result<()> {
+        self.wait_for_device_idle()?;
+        let sdc = &mut self.sdc;
+        let skybox = Texture::new_cubemap_from_paths(
+            &mut sdc.command_buffer_components.setup_context(
+                &sdc.device,
+                &mut sdc.memory_allocator,
+                sdc.graphics_queue,
+            ),
+            paths,
+            &sdc.sampler_config,
+            sdc.sampler_anisotropy,
+        )?;
+        sdc.descriptor_components.write_skybox(&sdc.device, &skybox);
+        if let Some(previous_skybox) = sdc.skybox.replace(skybox) {
+            previous_skybox.cleanup(&sdc.device, &mut sdc.memory_allocator);
+        }
+        Ok(())
+    }
+    // Draws a ground grid in the xz plane with red, green and blue x, y and z axes along with
+    // the meshes, using the same camera
+    pub fn set_show_grid(&mut self, show_grid: bool) -> Result<(), DeviceLost> {
+        if show_grid == self.sdc.grid.is_some() {
+            return Ok(());
+        }
+        self.wait_for_device_idle()?;
+        let sdc = &mut self.sdc;
+        match show_grid {
+            true => {
+                let upload_commands = sdc
+                    .command_buffer_components
+                    .upload_commands(sdc.graphics_queue);
+                sdc.grid = Some(GridComponents::new(
+                    &sdc.device,
+                    &mut sdc.memory_allocator,
+                    &upload_commands,
+                )?);
+            }
+            false => {
+                if let Some(grid) = sdc.grid.take() {
+                    grid.cleanup(&sdc.device, &mut sdc.memory_allocator);
+                }
+            }
+        }
+        Ok(())
+    }
+    // Replaces the cmd_draw_indexed of every mesh and object with these commands, indexing into the
+    // bound mesh's index buffer and the instances from set_instances. A non zero first_instance
+    // needs the drawIndirectFirstInstance feature. An empty slice goes back to direct draws.
+    pub fn set_indirect_commands(
+        &mut self,
+        commands: &[vk::DrawIndexedIndirectCommand],
+    ) -> Result<(), DeviceLost> {
+        self.wait_for_device_idle()?;
+        let sdc = &mut self.sdc;
+        sdc.indirect_draw_count = commands.len() as u32;
+        if commands.is_empty() {
+            if let Some(indirect_command_buffer) = sdc.indirect_command_buffer.take() {
+                indirect_command_buffer.cleanup(&sdc.device, &mut sdc.memory_allocator);
+            }
+            return Ok(());
+        }
+        let indirect_command_buffer = sdc.indirect_command_buffer.get_or_insert_with(|| {
+            Buffer::new(
+                &sdc.device,
+                &mut sdc.memory_allocator,
+                vk::BufferUsageFlags::INDIRECT_BUFFER,
+                vk::SharingMode::EXCLUSIVE,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                commands.len(),
+            )
+        });
+        if commands.len() > indirect_command_buffer.capacity() {
+            indirect_command_buffer.resize(&sdc.device, &mut sdc.memory_allocator, commands.len());
+        }
+        indirect_command_buffer.write_data_at(&sdc.device, 0, commands);
+        Ok(())
+    }
+    // Keeps a host visible copy of every drawn frame for capture_frame. The copy costs a
+    // transfer per frame, so it is off by default.
+    pub fn set_frame_capture_enabled(&mut self, frame_capture_enabled: bool) {
+        if self.resize_dependent_options.frame_capture == frame_capture_enabled {
+            return;
+        }
+        self.resize_dependent_options.frame_capture = frame_capture_enabled;
+        self.resize_dependent_component_rebuild_needed = true;
+    }
+    // Writes the most recently drawn frame to a PNG at path. Frame capture has to be enabled
+    // before the frame is drawn.
+    pub fn capture_frame(&mut self, path: &str) -> anyhow::Result<()> {
+        let swapchain_components = &self.sdc.rdc.swapchain_components;
+        if !swapchain_components
+            .image_usage
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            return Err(anyhow::anyhow!(
+                "The surface does not support reading back swapchain images"
+            ));
+        }
+        let Some(frame_capture_components) = &self.sdc.rdc.frame_capture_components else {
+            return Err(anyhow::anyhow!(
+                "Frame capture has to be enabled before the frame is drawn"
+            ));
+        };
+        if self.last_present_index.is_none() {
+            return Err(anyhow::anyhow!(
+                "No frame has been drawn since frame capture was enabled"
+            ));
+        }
+        // the copy is recorded by the last frame, which may still be in flight
+        self.wait_for_device_idle()?;
+        let image = frame_capture_components
+            .read(&self.sdc.device, swapchain_components.surface_format.format)?;
+        image.save(path)?;
+        Ok(())
+    }
+    #[cfg(feature = "shaderc")]
+    // Recompiles the shaders in UserSettings::shader_directory and rebuilds the pipelines with them,
+    // the current shaders are kept if compilation fails. Device loss is returned as a DeviceLost
+    // error.
+    pub fn reload_shaders(&mut self) -> anyhow::Result<()> {
+        let shaders =
+            shaders::Shaders::from_directory(&self.sdc.device, &self.sdc.shader_directory)
+                .map_err(anyhow::Error::msg)?
+                .with_entry_points(self.sdc.shaders.entry_points().clone());
+        let old_shaders = std::mem::replace(&mut self.sdc.shaders, shaders);
+        // the rebuild waits for the frames using the old shaders
+        let rebuilt = self.rebuild_graphics_pipelines(self.sdc.graphics_pipeline_components.config);
+        old_shaders.cleanup(&self.sdc.device);
+        Ok(rebuilt?)
+    }
+    #[cfg(feature = "shaderc")]
+    // Renders the scene into an offscreen target the size of the swapchain and draws that to the
+    // window with a fullscreen triangle and the fragment shader compiled from
+    // fragment_shader_source, see shaders/post_effect_vertex_shader.glsl for its interface. The
+    // overlay callback draws into the scene, so the effect applies to it too. None draws the
+    // scene directly again. The current effect is kept if compilation fails. Device loss is
+    // returned as a DeviceLost error.
+    pub fn set_post_effect(&mut self, fragment_shader_source: Option<&str>) -> anyhow::Result<()> {
+        let post_effect = match fragment_shader_source {
+            Some(fragment_shader_source) => {
+                let (vertex_shader_code, fragment_shader_code) =
+                    shaders::compile_post_effect(fragment_shader_source)
+                        .map_err(anyhow::Error::msg)?;
+                Some(PostEffectComponents::new(
+                    &self.sdc.device,
+                    &mut self.sdc.memory_allocator,
+                    self.sdc.pipeline_cache_components.pipeline_cache,
+                    self.sdc.rdc.swapchain_components.surface_format.format,
+                    &vertex_shader_code,
+                    &fragment_shader_code,
+                ))
+            }
+            None => None,
+        };
+        if let Err(device_lost) = self.wait_for_device_idle() {
+            if let Some(post_effect) = post_effect {
+                post_effect.cleanup(&self.sdc.device, &mut self.sdc.memory_allocator);
+            }
+            return Err(device_lost.into());
+        }
+        // without a target yet, the rebuild below writes it once it exists
+        if let (Some(post_effect), Some(render_target_components)) =
+            (&post_effect, &self.sdc.rdc.render_target_components)
+        {
+            post_effect.write_render_target(&self.sdc.device, render_target_components.image_view);
+        }
+        if let Some(old_post_effect) = std::mem::replace(&mut self.sdc.post_effect, post_effect) {
+            old_post_effect.cleanup(&self.sdc.device, &mut self.sdc.memory_allocator);
+        }
+        let post_effect_target = self.sdc.post_effect.is_some();
+        if self.resize_dependent_options.post_effect_target != post_effect_target {
+            self.resize_dependent_options.post_effect_target = post_effect_target;
+            self.resize_dependent_component_rebuild_needed = true;
+        }
+        Ok(())
+    }
+    // Rebuilds the device and everything created on it after draw_frame returned DeviceLost.
+    // Meshes, instances, textures and other state set since creation have to be set again. If
+    // this fails too, the renderer can only be dropped.
+    pub fn recreate_device(&mut self, user_settings: &UserSettings) -> Result<(), DeviceLost> {
+        if self.sdc_destroyed {
+            return Err(DeviceLost);
+        }
+        self.sdc.cleanup();
+        // the post effect is part of the dropped state, so its target isn't rebuilt either
+        self.resize_dependent_options.post_effect_target = false;
+        self.last_present_index = None;
+        self.images_in_flight.clear();
+        self.current_frame = 0;
+        self.replace_settings_dependent_components(user_settings)?;
+        self.device_lost = false;
+        Ok(())
+    }
+    // Rebuilds the device and everything created on it with new_user_settings. Meshes,
+    // instances, the texture, skybox, grid, storage data, indirect commands, split geometry and
+    // post effect live on the old device and are dropped, the returned DroppedState lists the
+    // ones that were set so they can be set again. Returns DeviceLost if the device was lost, see
+    // recreate_device.
+    pub fn update_user_settings(
+        &mut self,
+        new_user_settings: &UserSettings,
+    ) -> Result<DroppedState, DeviceLost> {
+        if self.sdc_destroyed {
+            return Err(DeviceLost);
+        }
+        self.wait_for_device_idle()?;
+        let dropped_state = self.dropped_state();
+        self.last_present_index = None;
+        self.images_in_flight.clear();
+        self.sic.pipeline_cache_data = self.sdc.pipeline_cache_components.data(&self.sdc.device);
+        // the old device and swapchain have to be gone before the new ones are created
+        self.sdc.cleanup();
+        // the rebuilt components start out without a post effect
+        self.resize_dependent_options.post_effect_target = false;
+        self.replace_settings_dependent_components(new_user_settings)?;
+        Ok(dropped_state)
+    }
+    fn dropped_state(&self) -> DroppedState {
+        let sdc = &self.sdc;
+        DroppedState {
+            meshes: sdc
+                .meshes
+                .iter()
+                .map(|(mesh_id, _)| *mesh_id)
+                .filter(|mesh_id| *mesh_id != DEFAULT_MESH_ID)
+                .collect(),
+            split_geometry: sdc.split_geometry_components.is_some(),
+            instances: sdc.instances_set,
+            indirect_commands: sdc.indirect_command_buffer.is_some(),
+            storage_data: sdc.storage_data_set,
+            texture: sdc.texture_set,
+            skybox: sdc.skybox.is_some(),
+            grid: sdc.grid.is_some(),
+            post_effect: sdc.post_effect.is_some(),
+        }
+    }
+    // Creates the settings dependent components in place of the cleaned up ones
+    fn replace_settings_dependent_components(
+        &mut self,
+        user_settings: &UserSettings,
+    ) -> Result<(), DeviceLost> {
+        match SettingsDependentComponents::new(
+            &self.sic,
+            user_settings,
+            &self.resize_dependent_options,
+        ) {
+            Ok(sdc) => self.sdc = sdc,
+            Err(device_lost) => {
+                self.sdc_destroyed = true;
+                self.device_lost = true;
+                return Err(device_lost);
+            }
+        }
+        // the rebuilt components start out with the built in shaders
+        #[cfg(feature = "shaderc")]
+        {
+            self.shader_watcher = user_settings
+                .shader_hot_reload
+                .then(|| ShaderWatcher::new(&user_settings.shader_directory));
+        }
+        Ok(())
+    }
+}
+
+// What apply_settings_from_file and save_settings_to_file read and write
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SettingsFile<U> {
+    user_settings: U,
+    camera: camera::CameraState,
+}
+
+#[cfg(feature = "serde")]
+impl Renderer {
+    // Rebuilds the renderer with the UserSettings in a JSON file written by save_settings_to_file
+    // and moves camera to the saved pose. The settings are returned so they can be changed and
+    // saved again, along with the runtime state the rebuild dropped, see update_user_settings.
+    pub fn apply_settings_from_file(
+        &mut self,
+        path: &str,
+        camera: &mut camera::Camera,
+    ) -> anyhow::Result<(UserSettings, DroppedState)> {
+        let contents = std::fs::read_to_string(path)?;
+        let settings_file: SettingsFile<UserSettings> = serde_json::from_str(&contents)?;
+        let dropped_state = self.update_user_settings(&settings_file.user_settings)?;
+        camera.set_state(&settings_file.camera);
+        Ok((settings_file.user_settings, dropped_state))
+    }
+    pub fn save_settings_to_file(
+        &self,
+        path: &str,
+        user_settings: &UserSettings,
+        camera: &camera::Camera,
+    ) -> anyhow::Result<()> {
+        let settings_file = SettingsFile {
+            user_settings,
+            camera: camera.state(),
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&settings_file)?)?;
+        Ok(())
+    }
+}
+
+// Raw handles for recording Vulkan commands alongside the renderer, e.g. for a UI layer. The
+// renderer keeps ownership, so nothing returned here may be destroyed, and objects created from
+// them have to be destroyed before the renderer is dropped. The device handles change when
+// update_user_settings or recreate_device rebuild the device, the swapchain format and extent
+// when the window is resized.
+impl Renderer {
+    pub fn instance(&self) -> &ash::Instance {
+        &self.sic.instance
+    }
+    pub fn device(&self) -> &ash::Device {
+        &self.sdc.device
+    }
+    pub fn physical_device(&self) -> vk::PhysicalDevice {
+        self.sdc.physical_device
+    }
+    // Submissions to it must not overlap with draw_frame
+    pub fn graphics_queue(&self) -> vk::Queue {
+        self.sdc.graphics_queue
+    }
+    pub fn graphics_queue_family_index(&self) -> u32 {
+        self.sdc.graphics_queue_family_index
+    }
+    // the same family as the graphics queue's unless that can't present to the surface
+    pub fn present_queue_family_index(&self) -> u32 {
+        self.sdc.present_queue_family_index
+    }
+    pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
+        &self.sdc.physical_device_memory_properties
+    }
+    // Records f into the renderer's setup command buffer, submits it to the graphics queue and
+    // waits for it to finish, e.g. for staging copies in tests against a headless renderer
+    pub fn run_setup<F: FnOnce(&ash::Device, vk::CommandBuffer)>(
+        &self,
+        f: F,
+    ) -> Result<(), DeviceLost> {
+        let setup_commands_reuse_fence = self
+            .sdc
+            .command_buffer_components
+            .setup_commands_reuse_fence;
+        try_record_submit_commandbuffer(
+            &self.sdc.device,
             self.sdc.graphics_queue,
+            self.sdc.command_buffer_components.setup_command_buffer,
+            setup_commands_reuse_fence,
+            SubmitSemaphores::default(),
+            f,
         )
+        .map_err(|e| device_lost_or_panic(e, "Failed to submit setup commands"))?;
+        unsafe {
+            self.sdc
+                .device
+                .wait_for_fences(&[setup_commands_reuse_fence], true, u64::MAX)
+        }
+        .map_err(|e| device_lost_or_panic(e, "Failed to wait for setup commands"))
     }
-    pub fn request_redraw(&self) {
-        self.sic.window.request_redraw();
+    pub fn swapchain_format(&self) -> vk::SurfaceFormatKHR {
+        self.sdc.rdc.swapchain_components.surface_format
+    }
+    pub fn swapchain_extent(&self) -> vk::Extent2D {
+        self.sdc.rdc.swapchain_components.surface_resolution
     }
-    pub fn update_user_settings(&mut self, new_user_settings: &UserSettings) {
-        unsafe { self.sdc.device.device_wait_idle().unwrap() };
-        self.sdc = SettingsDependentComponents::new(&self.sic, new_user_settings);
+}
+
+// Returned by draw_frame and anything else that waits for or submits to the device once it is
+// lost, e.g. after a driver reset or GPU hang. Call recreate_device to keep rendering, or drop
+// the renderer to exit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceLost;
+
+impl fmt::Display for DeviceLost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Vulkan device lost")
+    }
+}
+
+impl std::error::Error for DeviceLost {}
+
+// Runtime state update_user_settings dropped along with the old device, so the caller knows what
+// to set again. Pipeline state changed through the set_* functions goes back to the settings'.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DroppedState {
+    // meshes added with add_mesh, the default mesh is recreated with its original geometry
+    pub meshes: Vec<MeshId>,
+    pub split_geometry: bool,
+    pub instances: bool,
+    pub indirect_commands: bool,
+    pub storage_data: bool,
+    pub texture: bool,
+    pub skybox: bool,
+    pub grid: bool,
+    pub post_effect: bool,
+}
+
+// Device loss is left to the caller, any other error is a bug
+fn device_lost_or_panic(error: vk::Result, message: &str) -> DeviceLost {
+    match error {
+        vk::Result::ERROR_DEVICE_LOST => DeviceLost,
+        _ => panic!("{message}: {error:?}"),
+    }
+}
+
+// A swapchain the surface doesn't allow is a bug as well
+fn swapchain_device_lost_or_panic(error: ResizeDependentError) -> DeviceLost {
+    match error {
+        ResizeDependentError::Swapchain(error) => panic!("Failed to create swapchain: {error}"),
+        ResizeDependentError::DeviceLost => DeviceLost,
     }
 }
 
@@ -641,3 +2734,192 @@ fn find_memorytype_index(
         })
         .map(|(index, _memory_type)| index as _)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_type_matches_flags_and_allowed_bits() {
+        let device_local = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+        let host = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        let mut memory_properties = vk::PhysicalDeviceMemoryProperties {
+            memory_type_count: 2,
+            ..Default::default()
+        };
+        memory_properties.memory_types[0].property_flags = device_local;
+        memory_properties.memory_types[1].property_flags = host;
+        let requirements =
+            |memory_type_bits| vk::MemoryRequirements::default().memory_type_bits(memory_type_bits);
+
+        assert_eq!(
+            find_memorytype_index(&requirements(0b11), &memory_properties, device_local),
+            Some(0)
+        );
+        assert_eq!(
+            find_memorytype_index(&requirements(0b11), &memory_properties, host),
+            Some(1)
+        );
+        // a subset of a type's flags is enough
+        assert_eq!(
+            find_memorytype_index(
+                &requirements(0b11),
+                &memory_properties,
+                vk::MemoryPropertyFlags::HOST_VISIBLE
+            ),
+            Some(1)
+        );
+        // the resource doesn't allow the only host visible type
+        assert_eq!(
+            find_memorytype_index(&requirements(0b01), &memory_properties, host),
+            None
+        );
+        // types past memory_type_count are ignored even when their bit is set
+        memory_properties.memory_types[2].property_flags = device_local | host;
+        assert_eq!(
+            find_memorytype_index(
+                &requirements(0b111),
+                &memory_properties,
+                device_local | host
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn empty_meshes_are_kept_but_not_drawn() {
+        let Some(mut test_renderer) = crate::test::TestRenderer::new() else {
+            return;
+        };
+        let mesh_id = test_renderer.add_mesh(&[], &[]).unwrap();
+        assert_eq!(test_renderer.mesh_bounds(mesh_id), None);
+        test_renderer
+            .draw_frame(&camera::Camera::new(), &[Matrix4::identity()])
+            .unwrap();
+        assert!(test_renderer
+            .update_mesh(mesh_id, &VERTICES, &INDICES)
+            .unwrap());
+        assert!(test_renderer.mesh_bounds(mesh_id).is_some());
+        assert!(test_renderer.update_mesh(mesh_id, &VERTICES, &[]).unwrap());
+        test_renderer
+            .draw_frame(&camera::Camera::new(), &[Matrix4::identity()])
+            .unwrap();
+    }
+
+    #[test]
+    fn update_user_settings_reports_dropped_state() {
+        let Some(mut test_renderer) = crate::test::TestRenderer::new() else {
+            return;
+        };
+        let mesh_id = test_renderer.add_mesh(&VERTICES, &INDICES).unwrap();
+        test_renderer.set_show_grid(true).unwrap();
+        let dropped_state = test_renderer
+            .update_user_settings(&UserSettings::default())
+            .unwrap();
+        assert_eq!(
+            dropped_state,
+            DroppedState {
+                meshes: vec![mesh_id],
+                grid: true,
+                ..Default::default()
+            }
+        );
+        // nothing has been set on the new device yet
+        let dropped_state = test_renderer
+            .update_user_settings(&UserSettings::default())
+            .unwrap();
+        assert_eq!(dropped_state, DroppedState::default());
+    }
+
+    #[test]
+    fn excess_and_singular_model_matrices_dont_panic() {
+        let Some(mut test_renderer) = crate::test::TestRenderer::new() else {
+            return;
+        };
+        let mut model_matrices = vec![Matrix4::identity(); MAX_OBJECTS + 1];
+        model_matrices[0] = Matrix4::zeros();
+        test_renderer
+            .draw_frame(&camera::Camera::new(), &model_matrices)
+            .unwrap();
+    }
+
+    #[test]
+    fn mismatched_and_empty_split_geometry() {
+        let Some(mut test_renderer) = crate::test::TestRenderer::new() else {
+            return;
+        };
+        let attributes = VertexAttribs {
+            color: [1.0; 4],
+            normal: [0.0, 0.0, -1.0],
+        };
+        assert!(!test_renderer
+            .set_geometry_split(&[[0.0; 3]; 3], &[attributes; 2], &[0, 1, 2])
+            .unwrap());
+        assert!(test_renderer.set_geometry_split(&[], &[], &[]).unwrap());
+        test_renderer
+            .draw_frame(&camera::Camera::new(), &[Matrix4::identity()])
+            .unwrap();
+    }
+
+    #[test]
+    fn captured_frame_is_the_clear_color() {
+        let Some(mut test_renderer) = crate::test::TestRenderer::new() else {
+            return;
+        };
+        let path = std::env::temp_dir().join("ash_renderer_captured_frame.png");
+        let path = path.to_str().unwrap();
+        // nothing has been copied yet
+        assert!(test_renderer.capture_frame(path).is_err());
+        test_renderer.set_frame_capture_enabled(true);
+        test_renderer.set_clear_color([1.0, 0.0, 0.0, 1.0]);
+        test_renderer
+            .draw_frame(&camera::Camera::new(), &[])
+            .unwrap();
+        test_renderer.capture_frame(path).unwrap();
+        let image = image::open(path).unwrap().to_rgba8();
+        assert!(image.pixels().all(|pixel| pixel.0 == [255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn uncleared_frames_accumulate() {
+        let Some(mut test_renderer) = crate::test::TestRenderer::new() else {
+            return;
+        };
+        if !test_renderer.set_clear_color_enabled(false) {
+            return;
+        }
+        test_renderer.set_frame_capture_enabled(true);
+        test_renderer.set_clear_color([0.0, 0.0, 0.0, 1.0]);
+        test_renderer.set_blend_mode(BlendMode::AlphaBlend).unwrap();
+        test_renderer
+            .set_cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::COUNTER_CLOCKWISE)
+            .unwrap();
+        // half transparent triangle covering the view, blended over the previous frame
+        let vertex = |x, y| Vertex {
+            position: [x, y, 1.0],
+            color: [1.0, 1.0, 1.0, 0.5],
+            normal: [0.0, 0.0, -1.0],
+        };
+        test_renderer
+            .update_mesh(
+                DEFAULT_MESH_ID,
+                &[vertex(-10.0, -10.0), vertex(10.0, -10.0), vertex(0.0, 10.0)],
+                &[0, 1, 2],
+            )
+            .unwrap();
+        let camera = camera::Camera::look_at(Point3::origin(), Point3::new(0.0, 0.0, 1.0));
+        let path = std::env::temp_dir().join("ash_renderer_accumulated_frame.png");
+        let path = path.to_str().unwrap();
+        let mut center_pixels = Vec::new();
+        for _ in 0..2 {
+            test_renderer
+                .draw_frame(&camera, &[Matrix4::identity()])
+                .unwrap();
+            test_renderer.capture_frame(path).unwrap();
+            let image = image::open(path).unwrap().to_rgba8();
+            center_pixels.push(*image.get_pixel(image.width() / 2, image.height() / 2));
+        }
+        assert!(center_pixels[0].0[0] > 0);
+        assert!(center_pixels[1].0[0] > center_pixels[0].0[0]);
+    }
+}
@@ -1,27 +1,56 @@
 use ash::vk;
 
-use crate::renderer::command_buffer_components::record_submit_commandbuffer;
+use crate::renderer::command_buffer_components::{
+    try_record_submit_commandbuffer, SubmitSemaphores, UploadCommands,
+};
 
-use super::find_memorytype_index;
+use super::{
+    device_lost_or_panic,
+    memory_allocator::{Allocation, MemoryAllocator},
+    DeviceLost,
+};
 
 pub struct Buffer<T> {
     pub buffer: vk::Buffer,
-    pub memory: vk::DeviceMemory,
+    pub allocation: Allocation,
+    // number of T the buffer holds
+    capacity: usize,
+    // in bytes
     size: usize,
     usage: vk::BufferUsageFlags,
+    sharing_mode: vk::SharingMode,
     memory_properties: vk::MemoryPropertyFlags,
+    // only for host visible memory, which the allocator keeps mapped
     mapping: Option<ash::util::Align<T>>,
 }
 
 impl<T: Copy> Buffer<T> {
     pub fn new(
         device: &ash::Device,
-        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        memory_allocator: &mut MemoryAllocator,
         usage: vk::BufferUsageFlags,
         sharing_mode: vk::SharingMode,
         memory_properties: vk::MemoryPropertyFlags,
         buffer_len: usize,
-        persistent_mapping: bool,
+    ) -> Self {
+        Self::new_preferring(
+            device,
+            memory_allocator,
+            usage,
+            sharing_mode,
+            &[memory_properties],
+            buffer_len,
+        )
+    }
+    // Allocated with the first of memory_property_candidates the buffer's memory requirements
+    // allow, e.g. device local host visible memory before plain host visible memory
+    pub fn new_preferring(
+        device: &ash::Device,
+        memory_allocator: &mut MemoryAllocator,
+        usage: vk::BufferUsageFlags,
+        sharing_mode: vk::SharingMode,
+        memory_property_candidates: &[vk::MemoryPropertyFlags],
+        buffer_len: usize,
     ) -> Self {
         let buffer_size = size_of::<T>() * buffer_len;
         let buffer_create_info = vk::BufferCreateInfo::default()
@@ -32,100 +61,146 @@ impl<T: Copy> Buffer<T> {
         let buffer = unsafe { device.create_buffer(&buffer_create_info, None).unwrap() };
 
         let buffer_memory_reqs = unsafe { device.get_buffer_memory_requirements(buffer) };
+        let memory_properties = memory_allocator
+            .preferred_memory_properties(&buffer_memory_reqs, memory_property_candidates)
+            .expect("Failed to find suitable memory type");
 
-        let buffer_memory_index = find_memorytype_index(
-            &buffer_memory_reqs,
-            physical_device_memory_properties,
-            memory_properties,
-        )
-        .expect("Failed to find suitable memory type for buffer");
-
-        let buffer_allocate_info = vk::MemoryAllocateInfo::default()
-            .allocation_size(buffer_memory_reqs.size)
-            .memory_type_index(buffer_memory_index);
-
-        let memory = unsafe { device.allocate_memory(&buffer_allocate_info, None).unwrap() };
+        let allocation = memory_allocator.allocate(device, &buffer_memory_reqs, memory_properties);
 
         unsafe {
             device
-                .bind_buffer_memory(buffer, memory, 0)
+                .bind_buffer_memory(buffer, allocation.memory, allocation.offset)
                 .expect("Failed to bind buffer memory")
         };
 
-        let mapping = match persistent_mapping {
-            true => {
-                let data_ptr = unsafe {
-                    device
-                        .map_memory(
-                            memory,
-                            0,
-                            buffer_memory_reqs.size,
-                            vk::MemoryMapFlags::empty(),
-                        )
-                        .unwrap()
-                };
-
-                let vert_align = unsafe {
-                    ash::util::Align::new(data_ptr, align_of::<T>() as u64, buffer_memory_reqs.size)
-                };
-                Some(vert_align)
-            }
-            false => None,
+        let mapping = match allocation.mapped_ptr.is_null() {
+            true => None,
+            false => Some(unsafe {
+                ash::util::Align::new(
+                    allocation.mapped_ptr,
+                    align_of::<T>() as u64,
+                    allocation.size,
+                )
+            }),
         };
 
         Self {
             buffer,
-            memory,
+            allocation,
+            capacity: buffer_len,
             size: buffer_size,
             usage,
+            sharing_mode,
             memory_properties,
             mapping,
         }
     }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    fn assert_fits(&self, data_len: usize) {
+        assert!(
+            data_len <= self.capacity,
+            "Writing {data_len} elements to a buffer with capacity {}",
+            self.capacity
+        );
+    }
+    // Reallocates with room for buffer_len T and the same usage and memory properties, which
+    // buffers with the same usage always allow. The contents are not kept.
+    pub fn resize(
+        &mut self,
+        device: &ash::Device,
+        memory_allocator: &mut MemoryAllocator,
+        buffer_len: usize,
+    ) {
+        let resized = Buffer::new(
+            device,
+            memory_allocator,
+            self.usage,
+            self.sharing_mode,
+            self.memory_properties,
+            buffer_len,
+        );
+        std::mem::replace(self, resized).cleanup(device, memory_allocator);
+    }
     pub fn write_data_direct(&mut self, device: &ash::Device, data: &[T]) {
-        assert_eq!(
-            self.memory_properties & vk::MemoryPropertyFlags::HOST_VISIBLE,
-            vk::MemoryPropertyFlags::HOST_VISIBLE
+        self.assert_fits(data.len());
+        self.mapping
+            .as_mut()
+            .expect("Buffer memory is not host visible")
+            .copy_from_slice(data);
+        self.flush_host_writes(device, 0, data.len());
+    }
+    // Writes data to the elements from first_element on, the others are left as they are. Only
+    // the written range is flushed, e.g. to update part of a uniform buffer each frame.
+    pub fn write_data_at(&mut self, device: &ash::Device, first_element: usize, data: &[T]) {
+        self.assert_fits(first_element + data.len());
+        assert!(
+            !self.allocation.mapped_ptr.is_null(),
+            "Buffer memory is not host visible"
         );
-        assert_eq!(
-            self.memory_properties & vk::MemoryPropertyFlags::HOST_COHERENT,
-            vk::MemoryPropertyFlags::HOST_COHERENT
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                (self.allocation.mapped_ptr as *mut T).add(first_element),
+                data.len(),
+            )
+        };
+        self.flush_host_writes(device, first_element, data.len());
+    }
+    // Copies the whole buffer out, the memory must be host visible
+    pub fn read_data(&self, device: &ash::Device) -> Vec<T> {
+        assert!(
+            !self.allocation.mapped_ptr.is_null(),
+            "Buffer memory is not host visible"
         );
-        assert!(data.len() <= self.size);
-        if self.mapping.is_some() {
-            self.mapping.as_mut().unwrap().copy_from_slice(data);
+        unsafe {
+            if !self.allocation.host_coherent {
+                device
+                    .invalidate_mapped_memory_ranges(&[self.whole_memory_range()])
+                    .expect("Failed to invalidate mapped memory");
+            }
+            std::slice::from_raw_parts(self.allocation.mapped_ptr as *const T, self.capacity)
+                .to_vec()
+        }
+    }
+    // Flushes the elements first_element..first_element + len
+    fn flush_host_writes(&self, device: &ash::Device, first_element: usize, len: usize) {
+        if self.allocation.host_coherent || len == 0 {
             return;
         }
-        let buffer_memory_reqs = unsafe { device.get_buffer_memory_requirements(self.buffer) };
-
-        let data_ptr = unsafe {
-            device
-                .map_memory(
-                    self.memory,
-                    0,
-                    buffer_memory_reqs.size,
-                    vk::MemoryMapFlags::empty(),
-                )
-                .unwrap()
-        };
-
-        let mut vert_align = unsafe {
-            ash::util::Align::new(data_ptr, align_of::<T>() as u64, buffer_memory_reqs.size)
-        };
-        vert_align.copy_from_slice(data);
-
+        let (offset, size) = self.flushed_range(first_element, len);
+        let memory_range = vk::MappedMemoryRange::default()
+            .memory(self.allocation.memory)
+            .offset(offset)
+            .size(size);
         unsafe {
-            device.unmap_memory(self.memory);
+            device
+                .flush_mapped_memory_ranges(&[memory_range])
+                .expect("Failed to flush mapped memory")
         };
     }
+    // The memory range flushed after writing the elements first_element..first_element + len
+    fn flushed_range(&self, first_element: usize, len: usize) -> (vk::DeviceSize, vk::DeviceSize) {
+        flush_range(
+            &self.allocation,
+            (first_element * size_of::<T>()) as vk::DeviceSize,
+            (len * size_of::<T>()) as vk::DeviceSize,
+        )
+    }
+    // WHOLE_SIZE covers the rest of the allocator's block, which keeps the range valid without
+    // aligning it to nonCoherentAtomSize
+    fn whole_memory_range(&self) -> vk::MappedMemoryRange<'static> {
+        vk::MappedMemoryRange::default()
+            .memory(self.allocation.memory)
+            .size(vk::WHOLE_SIZE)
+    }
     pub fn write_from_staging(
         &self,
         staging_buffer: &Buffer<T>,
         device: &ash::Device,
-        command_buffer: vk::CommandBuffer,
-        command_buffer_reuse_fence: vk::Fence,
-        submit_queue: vk::Queue,
-    ) {
+        upload_commands: &UploadCommands,
+    ) -> Result<(), DeviceLost> {
         assert_eq!(
             self.usage & vk::BufferUsageFlags::TRANSFER_DST,
             vk::BufferUsageFlags::TRANSFER_DST
@@ -137,14 +212,29 @@ impl<T: Copy> Buffer<T> {
         assert!(self.size >= staging_buffer.size);
         let copy_region = vk::BufferCopy::default().size(staging_buffer.size as u64);
 
-        record_submit_commandbuffer(
+        // buffers are EXCLUSIVE, so the transfer queue family releases them to the graphics one
+        let ownership_transfer_barrier = upload_commands.ownership_transfer.map(|transfer| {
+            vk::BufferMemoryBarrier::default()
+                .src_queue_family_index(transfer.src_queue_family_index)
+                .dst_queue_family_index(transfer.dst_queue_family_index)
+                .buffer(self.buffer)
+                .size(vk::WHOLE_SIZE)
+        });
+        let signal_semaphores: Vec<vk::Semaphore> = upload_commands
+            .ownership_transfer
+            .iter()
+            .map(|ownership_transfer| ownership_transfer.semaphore)
+            .collect();
+
+        try_record_submit_commandbuffer(
             device,
-            submit_queue,
-            command_buffer,
-            command_buffer_reuse_fence,
-            &[],
-            &[],
-            &[],
+            upload_commands.queue,
+            upload_commands.command_buffer,
+            upload_commands.command_buffer_reuse_fence,
+            SubmitSemaphores {
+                signal_semaphores: &signal_semaphores,
+                ..Default::default()
+            },
             |device, command_buffer| unsafe {
                 device.cmd_copy_buffer(
                     command_buffer,
@@ -152,13 +242,231 @@ impl<T: Copy> Buffer<T> {
                     self.buffer,
                     &[copy_region],
                 );
+                if let Some(barrier) = ownership_transfer_barrier {
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[barrier.src_access_mask(vk::AccessFlags::TRANSFER_WRITE)],
+                        &[],
+                    );
+                }
             },
-        );
+        )
+        .map_err(|e| device_lost_or_panic(e, "Failed to submit buffer upload"))?;
+
+        if let (Some(ownership_transfer), Some(barrier)) = (
+            upload_commands.ownership_transfer,
+            ownership_transfer_barrier,
+        ) {
+            try_record_submit_commandbuffer(
+                device,
+                ownership_transfer.dst_queue,
+                ownership_transfer.dst_command_buffer,
+                ownership_transfer.dst_command_buffer_reuse_fence,
+                SubmitSemaphores {
+                    wait_mask: &[vk::PipelineStageFlags::VERTEX_INPUT],
+                    wait_semaphores: &[ownership_transfer.semaphore],
+                    ..Default::default()
+                },
+                |device, command_buffer| unsafe {
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TOP_OF_PIPE,
+                        vk::PipelineStageFlags::VERTEX_INPUT,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[barrier.dst_access_mask(
+                            vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::INDEX_READ,
+                        )],
+                        &[],
+                    );
+                },
+            )
+            .map_err(|e| device_lost_or_panic(e, "Failed to submit buffer ownership acquire"))?;
+        }
+        Ok(())
     }
-    pub fn cleanup(&self, device: &ash::Device) {
-        unsafe {
-            device.destroy_buffer(self.buffer, None);
-            device.free_memory(self.memory, None);
+    pub fn cleanup(&self, device: &ash::Device, memory_allocator: &mut MemoryAllocator) {
+        unsafe { device.destroy_buffer(self.buffer, None) };
+        memory_allocator.free(&self.allocation);
+    }
+}
+
+// The memory range of the bytes at offset..offset + size into allocation, widened to
+// nonCoherentAtomSize. The allocator's blocks are multiples of it unless a resource got a block
+// of its own, so an end past the allocation falls back to WHOLE_SIZE to stay inside the memory.
+fn flush_range(
+    allocation: &Allocation,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+) -> (vk::DeviceSize, vk::DeviceSize) {
+    let atom_size = allocation.non_coherent_atom_size;
+    let start = (allocation.offset + offset) / atom_size * atom_size;
+    let end = (allocation.offset + offset + size).next_multiple_of(atom_size);
+    match end <= allocation.offset + allocation.size {
+        true => (start, end - start),
+        false => (start, vk::WHOLE_SIZE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TestRenderer;
+
+    // never allocated, only for checks that don't touch the device
+    fn unallocated_buffer(buffer_len: usize) -> Buffer<u32> {
+        Buffer {
+            buffer: vk::Buffer::null(),
+            allocation: Allocation::unbound(),
+            capacity: buffer_len,
+            size: size_of::<u32>() * buffer_len,
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            memory_properties: vk::MemoryPropertyFlags::HOST_VISIBLE,
+            mapping: None,
         }
     }
+
+    #[test]
+    fn flushed_ranges_cover_the_write_in_whole_atoms() {
+        let mut allocation = Allocation::unbound();
+        allocation.offset = 1024;
+        allocation.size = 500;
+        allocation.non_coherent_atom_size = 64;
+        assert_eq!(flush_range(&allocation, 100, 8), (1088, 64));
+        assert_eq!(flush_range(&allocation, 60, 8), (1024, 128));
+        // rounding up would pass the end of the allocation
+        assert_eq!(flush_range(&allocation, 490, 8), (1472, vk::WHOLE_SIZE));
+    }
+
+    #[test]
+    fn staging_copy_reaches_the_destination_buffer() {
+        let Some(test_renderer) = TestRenderer::new() else {
+            return;
+        };
+        let device = test_renderer.device();
+        let mut memory_allocator =
+            MemoryAllocator::new(*test_renderer.memory_properties(), &test_renderer.limits());
+        let host_visible = vk::MemoryPropertyFlags::HOST_VISIBLE;
+        let new_buffer = |memory_allocator: &mut MemoryAllocator, usage| {
+            Buffer::<u32>::new(
+                device,
+                memory_allocator,
+                usage,
+                vk::SharingMode::EXCLUSIVE,
+                host_visible,
+                4,
+            )
+        };
+        let mut staging_buffer =
+            new_buffer(&mut memory_allocator, vk::BufferUsageFlags::TRANSFER_SRC);
+        let destination_buffer =
+            new_buffer(&mut memory_allocator, vk::BufferUsageFlags::TRANSFER_DST);
+
+        staging_buffer.write_data_direct(device, &[1, 2, 3, 4]);
+        test_renderer
+            .run_setup(|device, command_buffer| unsafe {
+                device.cmd_copy_buffer(
+                    command_buffer,
+                    staging_buffer.buffer,
+                    destination_buffer.buffer,
+                    &[vk::BufferCopy::default().size(staging_buffer.size as u64)],
+                );
+                // the fence alone doesn't make the copy visible to the host
+                let host_read_barrier = vk::MemoryBarrier::default()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::HOST_READ);
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::HOST,
+                    vk::DependencyFlags::empty(),
+                    &[host_read_barrier],
+                    &[],
+                    &[],
+                );
+            })
+            .unwrap();
+        assert_eq!(destination_buffer.read_data(device), vec![1, 2, 3, 4]);
+
+        staging_buffer.cleanup(device, &mut memory_allocator);
+        destination_buffer.cleanup(device, &mut memory_allocator);
+        memory_allocator.cleanup(device);
+    }
+
+    // Runs f with a host visible buffer of 4 elements, skipped without a Vulkan 1.3 device
+    fn with_host_visible_buffer(f: impl FnOnce(&ash::Device, &mut Buffer<u32>)) {
+        let Some(test_renderer) = TestRenderer::new() else {
+            return;
+        };
+        let device = test_renderer.device();
+        let mut memory_allocator =
+            MemoryAllocator::new(*test_renderer.memory_properties(), &test_renderer.limits());
+        let mut buffer = Buffer::<u32>::new(
+            device,
+            &mut memory_allocator,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::SharingMode::EXCLUSIVE,
+            vk::MemoryPropertyFlags::HOST_VISIBLE,
+            4,
+        );
+        f(device, &mut buffer);
+        buffer.cleanup(device, &mut memory_allocator);
+        memory_allocator.cleanup(device);
+    }
+
+    #[test]
+    fn exact_length_write_fits() {
+        let buffer = unallocated_buffer(4);
+        assert_eq!(buffer.capacity(), 4);
+        buffer.assert_fits(4);
+    }
+
+    #[test]
+    #[should_panic]
+    fn over_long_write_panics() {
+        // 5 elements fit in the 16 byte size, but not in the capacity
+        unallocated_buffer(4).assert_fits(5);
+    }
+
+    #[test]
+    fn write_ending_at_capacity_fits() {
+        with_host_visible_buffer(|device, buffer| {
+            buffer.write_data_direct(device, &[1, 2, 3, 4]);
+            buffer.write_data_at(device, 2, &[7, 8]);
+            assert_eq!(buffer.read_data(device), vec![1, 2, 7, 8]);
+        });
+    }
+
+    #[test]
+    fn write_past_capacity_panics() {
+        with_host_visible_buffer(|device, buffer| {
+            // 5 elements would fit in a byte count of 16, but not in the capacity
+            let write = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                buffer.write_data_at(device, 3, &[5, 6])
+            }));
+            assert!(write.is_err());
+            let write = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                buffer.write_data_direct(device, &[1, 2, 3, 4, 5])
+            }));
+            assert!(write.is_err());
+        });
+    }
+
+    #[test]
+    fn flushed_range_covers_the_written_elements() {
+        let mut buffer = unallocated_buffer(4);
+        buffer.allocation.offset = 64;
+        buffer.allocation.size = 16;
+        buffer.allocation.non_coherent_atom_size = 4;
+        // the last two elements, ending exactly at capacity
+        assert_eq!(buffer.flushed_range(2, 2), (72, 8));
+        assert_eq!(buffer.flushed_range(0, 4), (64, 16));
+        // one element past capacity would pass the end of the allocation
+        assert_eq!(buffer.flushed_range(3, 2), (76, vk::WHOLE_SIZE));
+    }
 }
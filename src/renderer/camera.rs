@@ -1,8 +1,35 @@
 use std::f32::consts::PI;
 
-use nalgebra::{Matrix4, Perspective3, Point3, Vector3};
+use nalgebra::{Matrix4, Orthographic3, Perspective3, Point3, Vector3, Vector4};
+use winit::keyboard::KeyCode;
+
+// keeps phi away from straight up and down, where forward would be parallel to up
+const PHI_EPSILON: f32 = 0.01;
+// zoom range for the scroll wheel
+const MIN_FOVY_DEGREES: f32 = 10.0;
+const MAX_FOVY_DEGREES: f32 = 100.0;
+const FOV_DEGREES_PER_SCROLL_LINE: f32 = 5.0;
+// orthographic zoom shrinks the view height by this factor per line
+const ORTHOGRAPHIC_ZOOM_PER_SCROLL_LINE: f32 = 1.1;
+// analog input at full deflection
+const ANALOG_UNITS_PER_SECOND: f32 = 2.0;
+const ANALOG_RADIANS_PER_SECOND: f32 = 2.0;
+// key movement below this fraction of speed stops, so damped coasting ends
+const STOPPED_SPEED_FRACTION: f32 = 1e-3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProjectionKind {
+    // vertical field of view, radians
+    Perspective { fovy: f32 },
+    // world space height of the view volume, the width follows from the aspect ratio
+    Orthographic { height: f32 },
+}
 
 // all angles are in radians
+// World space is right handed with -y as up, so a positive y position is below the origin.
+// view_matrix flips y once more to match Vulkan's downward framebuffer y, which puts up() at
+// the top of the screen and right() on the right.
 #[derive(Debug)]
 pub struct Camera {
     pub position: Point3<f32>,
@@ -12,11 +39,26 @@ pub struct Camera {
     // angle counterclockwise about the vertical axis, 0 is in the z direction
     // radians
     pub theta: f32,
+    // world up, -y
     up: Vector3<f32>,
-    fovy: f32,
+    projection: ProjectionKind,
     znear: f32,
     zfar: f32,
 }
+
+// The pose and projection of a Camera as plain values, e.g. to save it between runs. World up is
+// fixed, so it isn't part of the state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CameraState {
+    pub position: [f32; 3],
+    pub phi: f32,
+    pub theta: f32,
+    pub projection: ProjectionKind,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
 #[rustfmt::skip]
 pub const MODEL_MATRIX: Matrix4<f32> = Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -25,6 +67,12 @@ pub const MODEL_MATRIX: Matrix4<f32> = Matrix4::new(
     0.0, 0.0, 0.0, 1.0,
 );
 
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Camera {
     pub fn new() -> Self {
         Self {
@@ -32,18 +80,65 @@ impl Camera {
             phi: PI / 2.0,
             theta: 0.0,
             up: Vector3::y_axis().scale(-1.0),
-            fovy: 45.0,
+            projection: ProjectionKind::Perspective {
+                fovy: 45f32.to_radians(),
+            },
             znear: 0.01,
             zfar: 100.0,
         }
     }
-    fn forward(&self) -> Vector3<f32> {
-        let forward = Vector3::new(
+    // Placed at position and turned to face target, which must not be the same point
+    pub fn look_at(position: Point3<f32>, target: Point3<f32>) -> Self {
+        let direction = (target - position).normalize();
+        Self {
+            position,
+            // inverts forward(), straight up or down is clamped like mouse look
+            phi: (-direction.y).acos().clamp(PHI_EPSILON, PI - PHI_EPSILON),
+            theta: direction.x.atan2(direction.z),
+            ..Self::new()
+        }
+    }
+    // Back to the origin facing +z, the projection and clip planes are kept
+    pub fn reset(&mut self) {
+        let default = Self::new();
+        self.position = default.position;
+        self.phi = default.phi;
+        self.theta = default.theta;
+    }
+    pub fn state(&self) -> CameraState {
+        CameraState {
+            position: self.position.into(),
+            phi: self.phi,
+            theta: self.theta,
+            projection: self.projection,
+            znear: self.znear,
+            zfar: self.zfar,
+        }
+    }
+    // phi is clamped like mouse look
+    pub fn set_state(&mut self, state: &CameraState) {
+        self.position = state.position.into();
+        self.phi = state.phi.clamp(PHI_EPSILON, PI - PHI_EPSILON);
+        self.theta = state.theta;
+        self.projection = state.projection;
+        self.znear = state.znear;
+        self.zfar = state.zfar;
+    }
+    // normalized view direction
+    pub fn forward(&self) -> Vector3<f32> {
+        Vector3::new(
             self.phi.sin() * self.theta.sin(),
-            -1.0 * self.phi.cos(),
+            -self.phi.cos(),
             self.phi.sin() * self.theta.cos(),
-        );
-        forward
+        )
+    }
+    // normalized, always horizontal since phi never reaches straight up or down
+    pub fn right(&self) -> Vector3<f32> {
+        self.forward().cross(&self.up).normalize()
+    }
+    // normalized, perpendicular to forward and right, equal to world up when looking level
+    pub fn up(&self) -> Vector3<f32> {
+        self.right().cross(&self.forward())
     }
     pub fn view_matrix(&self) -> Matrix4<f32> {
         let look_at =
@@ -57,21 +152,218 @@ impl Camera {
         );
         negative_y * look_at
     }
-    pub fn projection_matrix(&self, aspect_ratio: f32) -> Matrix4<f32> {
-        Perspective3::new(aspect_ratio, self.fovy, self.znear, self.zfar).to_homogeneous()
+    pub fn projection(&self) -> ProjectionKind {
+        self.projection
+    }
+    // Switching keeps the position and orientation, only the projection changes
+    pub fn set_projection(&mut self, projection: ProjectionKind) {
+        self.projection = projection;
+    }
+    // None while orthographic
+    pub fn fovy(&self) -> Option<f32> {
+        match self.projection {
+            ProjectionKind::Perspective { fovy } => Some(fovy),
+            ProjectionKind::Orthographic { .. } => None,
+        }
+    }
+    // Switches an orthographic camera to perspective
+    pub fn set_fovy(&mut self, fovy: f32) {
+        self.projection = ProjectionKind::Perspective { fovy };
+    }
+    pub fn fov_degrees(&self) -> Option<f32> {
+        self.fovy().map(f32::to_degrees)
+    }
+    pub fn set_fov_degrees(&mut self, fov_degrees: f32) {
+        self.set_fovy(fov_degrees.to_radians());
+    }
+    pub fn znear(&self) -> f32 {
+        self.znear
+    }
+    pub fn set_znear(&mut self, znear: f32) {
+        self.znear = znear;
+    }
+    pub fn zfar(&self) -> f32 {
+        self.zfar
+    }
+    pub fn set_zfar(&mut self, zfar: f32) {
+        self.zfar = zfar;
+    }
+    // Keeps the orientation and backs away along forward() until the box, min and max corners in
+    // world space, fits the narrower of the vertical and horizontal field of view, or the
+    // orthographic extent, at aspect_ratio (width over height, see Renderer::aspect_ratio). The
+    // box stays behind the near plane and zfar is pushed out if its far side would be clipped.
+    pub fn frame_bounds(&mut self, min: Point3<f32>, max: Point3<f32>, aspect_ratio: f32) {
+        let center = nalgebra::center(&min, &max);
+        // fitting the bounding sphere makes the fit independent of the orientation
+        let radius = ((max - min).norm() / 2.0).max(f32::EPSILON);
+        let distance = match &mut self.projection {
+            ProjectionKind::Perspective { fovy } => {
+                // the horizontal field of view is the narrower one below an aspect ratio of 1
+                let half_fovx = ((*fovy / 2.0).tan() * aspect_ratio).atan();
+                radius / (*fovy / 2.0).min(half_fovx).sin()
+            }
+            ProjectionKind::Orthographic { height } => {
+                *height = 2.0 * radius / aspect_ratio.min(1.0);
+                0.0
+            }
+        };
+        // a wide field of view would otherwise put the near side of the box in front of znear
+        let distance = distance.max(radius + self.znear);
+        self.position = center - self.forward() * distance;
+        self.zfar = self.zfar.max(distance + radius);
+    }
+    // reverse_z maps znear to depth 1.0 and zfar to 0.0, matching PipelineConfig::reverse_z
+    pub fn projection_matrix(&self, aspect_ratio: f32, reverse_z: bool) -> Matrix4<f32> {
+        let depth_range = self.zfar - self.znear;
+        match self.projection {
+            ProjectionKind::Perspective { fovy } => {
+                let mut projection =
+                    Perspective3::new(aspect_ratio, fovy, self.znear, self.zfar).to_homogeneous();
+                if reverse_z {
+                    // solved directly rather than remapping the [-1, 1] depth to keep the precision
+                    projection[(2, 2)] = self.znear / depth_range;
+                    projection[(2, 3)] = self.znear * self.zfar / depth_range;
+                }
+                projection
+            }
+            ProjectionKind::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * aspect_ratio;
+                let mut projection = Orthographic3::new(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.znear,
+                    self.zfar,
+                )
+                .to_homogeneous();
+                if reverse_z {
+                    projection[(2, 2)] = 1.0 / depth_range;
+                    projection[(2, 3)] = self.zfar / depth_range;
+                }
+                projection
+            }
+        }
+    }
+    // World space ray through a pixel, returned as (origin on the near plane, normalized direction).
+    // Pixel coordinates start at the top left of the framebuffer like winit cursor positions.
+    pub fn screen_ray(
+        &self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    ) -> (Point3<f32>, Vector3<f32>) {
+        let inverse_view_projection = (self.projection_matrix(width / height, false)
+            * self.view_matrix())
+        .try_inverse()
+        .expect("Camera view projection matrix is not invertible");
+        // the view matrix flips y, so top left of the framebuffer is (-1, -1) in ndc
+        let ndc_x = 2.0 * x / width - 1.0;
+        let ndc_y = 2.0 * y / height - 1.0;
+        let unproject = |ndc_z: f32| {
+            let point = inverse_view_projection * Vector4::new(ndc_x, ndc_y, ndc_z, 1.0);
+            Point3::from(point.xyz() / point.w)
+        };
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+        (near, (far - near).normalize())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraAction {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+// Any number of keys can trigger the same action
+#[derive(Clone, Debug)]
+pub struct KeyBindings {
+    bindings: Vec<(KeyCode, CameraAction)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self::from_pairs([
+            (KeyCode::KeyW, CameraAction::Forward),
+            (KeyCode::ArrowUp, CameraAction::Forward),
+            (KeyCode::KeyS, CameraAction::Back),
+            (KeyCode::ArrowDown, CameraAction::Back),
+            (KeyCode::KeyA, CameraAction::Left),
+            (KeyCode::ArrowLeft, CameraAction::Left),
+            (KeyCode::KeyD, CameraAction::Right),
+            (KeyCode::ArrowRight, CameraAction::Right),
+            (KeyCode::Space, CameraAction::Up),
+            (KeyCode::KeyE, CameraAction::Up),
+            (KeyCode::ControlLeft, CameraAction::Down),
+            (KeyCode::KeyQ, CameraAction::Down),
+        ])
+    }
+}
+
+impl KeyBindings {
+    // A key bound more than once keeps its last action
+    pub fn from_pairs(pairs: impl IntoIterator<Item = (KeyCode, CameraAction)>) -> Self {
+        let mut key_bindings = Self {
+            bindings: Vec::new(),
+        };
+        for (key_code, action) in pairs {
+            key_bindings.bind(key_code, action);
+        }
+        key_bindings
+    }
+    pub fn bind(&mut self, key_code: KeyCode, action: CameraAction) {
+        self.unbind(key_code);
+        self.bindings.push((key_code, action));
+    }
+    pub fn unbind(&mut self, key_code: KeyCode) {
+        self.bindings
+            .retain(|&(bound_key_code, _)| bound_key_code != key_code);
+    }
+    pub fn action(&self, key_code: KeyCode) -> Option<CameraAction> {
+        self.bindings
+            .iter()
+            .find(|&&(bound_key_code, _)| bound_key_code == key_code)
+            .map(|&(_, action)| action)
     }
 }
 
 #[derive(Debug)]
 pub struct CameraController {
+    // units per second of held keys
     pub speed: f32,
     pub mouse_sens: f32,
     pub mouse_delta_x: f32,
     pub mouse_delta_y: f32,
+    // lines scrolled since the last update, positive zooms in
+    pub scroll_delta: f32,
     pub forward_pressed: bool,
     pub backward_pressed: bool,
     pub left_pressed: bool,
     pub right_pressed: bool,
+    pub up_pressed: bool,
+    pub down_pressed: bool,
+    // key movement in units per second, approaches the held direction times speed
+    pub velocity: Vector3<f32>,
+    // per second, how quickly velocity catches up while keys are held and decays once they're
+    // released, the infinite defaults start and stop instantly
+    pub acceleration: f32,
+    pub damping: f32,
+    // held values in -1.0..=1.0, e.g. from a gamepad stick, scaled by the update's delta time
+    // unlike the presses and mouse deltas above
+    pub analog_forward: f32,
+    pub analog_right: f32,
+    // positive turns right and looks down, like positive mouse deltas
+    pub analog_yaw: f32,
+    pub analog_pitch: f32,
+    pub analog_speed: f32,
+    pub analog_look_speed: f32,
 }
 
 impl CameraController {
@@ -81,31 +373,335 @@ impl CameraController {
             mouse_sens,
             mouse_delta_x: 0.0,
             mouse_delta_y: 0.0,
+            scroll_delta: 0.0,
             forward_pressed: false,
             backward_pressed: false,
             left_pressed: false,
             right_pressed: false,
+            up_pressed: false,
+            down_pressed: false,
+            velocity: Vector3::zeros(),
+            acceleration: f32::INFINITY,
+            damping: f32::INFINITY,
+            analog_forward: 0.0,
+            analog_right: 0.0,
+            analog_yaw: 0.0,
+            analog_pitch: 0.0,
+            analog_speed: ANALOG_UNITS_PER_SECOND,
+            analog_look_speed: ANALOG_RADIANS_PER_SECOND,
         }
     }
 
-    pub fn update_camera(&mut self, camera: &mut Camera) {
+    pub fn set_pressed(&mut self, action: CameraAction, pressed: bool) {
+        let flag = match action {
+            CameraAction::Forward => &mut self.forward_pressed,
+            CameraAction::Back => &mut self.backward_pressed,
+            CameraAction::Left => &mut self.left_pressed,
+            CameraAction::Right => &mut self.right_pressed,
+            CameraAction::Up => &mut self.up_pressed,
+            CameraAction::Down => &mut self.down_pressed,
+        };
+        *flag = pressed;
+    }
+    // True while the next update_camera would move the camera, from held keys and sticks or input
+    // that hasn't been applied yet
+    pub fn is_active(&self) -> bool {
+        self.forward_pressed
+            || self.backward_pressed
+            || self.left_pressed
+            || self.right_pressed
+            || self.up_pressed
+            || self.down_pressed
+            // an infinite damping stops the camera on the next update without moving it
+            || (self.damping.is_finite() && self.velocity != Vector3::zeros())
+            || [
+                self.mouse_delta_x,
+                self.mouse_delta_y,
+                self.scroll_delta,
+                self.analog_forward,
+                self.analog_right,
+                self.analog_yaw,
+                self.analog_pitch,
+            ]
+            .iter()
+            .any(|&value| value != 0.0)
+    }
+    // delta_time is the seconds since the last update, all movement and analog look are scaled
+    // by it, mouse and scroll deltas are applied as they are
+    pub fn update_camera(&mut self, camera: &mut Camera, delta_time: f32) {
         let forward = camera.forward();
-        let right = forward.cross(&Vector3::y_axis().scale(-1.0));
+        // vertical movement follows world up rather than camera.up()
+        let up = camera.up;
+        let right = camera.right();
+        let mut direction = Vector3::zeros();
         if self.forward_pressed {
-            camera.position += forward * self.speed;
+            direction += forward;
         }
         if self.backward_pressed {
-            camera.position -= forward * self.speed;
+            direction -= forward;
         }
         if self.left_pressed {
-            camera.position -= right * self.speed;
+            direction -= right;
         }
         if self.right_pressed {
-            camera.position += right * self.speed;
+            direction += right;
+        }
+        if self.up_pressed {
+            direction += up;
+        }
+        if self.down_pressed {
+            direction -= up;
+        }
+        let rate = match direction == Vector3::zeros() {
+            true => self.damping,
+            false => self.acceleration,
+        };
+        self.velocity +=
+            (direction * self.speed - self.velocity) * approach_fraction(rate, delta_time);
+        if self.velocity.norm() < self.speed * STOPPED_SPEED_FRACTION {
+            self.velocity = Vector3::zeros();
+        }
+        camera.position += self.velocity * delta_time;
+        camera.position += (forward * self.analog_forward + right * self.analog_right)
+            * self.analog_speed
+            * delta_time;
+        let analog_look = self.analog_look_speed * delta_time;
+        camera.theta += self.mouse_delta_x * self.mouse_sens + self.analog_yaw * analog_look;
+        camera.phi =
+            (camera.phi + self.mouse_delta_y * self.mouse_sens + self.analog_pitch * analog_look)
+                .clamp(PHI_EPSILON, PI - PHI_EPSILON);
+        match camera.projection {
+            ProjectionKind::Perspective { fovy } => camera.set_fov_degrees(
+                (fovy.to_degrees() - self.scroll_delta * FOV_DEGREES_PER_SCROLL_LINE)
+                    .clamp(MIN_FOVY_DEGREES, MAX_FOVY_DEGREES),
+            ),
+            ProjectionKind::Orthographic { height } => {
+                camera.projection = ProjectionKind::Orthographic {
+                    height: height / ORTHOGRAPHIC_ZOOM_PER_SCROLL_LINE.powf(self.scroll_delta),
+                }
+            }
         }
-        camera.theta += self.mouse_delta_x * self.mouse_sens;
-        camera.phi += self.mouse_delta_y * self.mouse_sens;
         self.mouse_delta_x = 0.0;
         self.mouse_delta_y = 0.0;
+        self.scroll_delta = 0.0;
+    }
+}
+
+// How much of the remaining difference an exponential approach at rate per second covers in
+// delta_time, an infinite rate covers all of it even when delta_time is 0
+fn approach_fraction(rate: f32, delta_time: f32) -> f32 {
+    match rate.is_infinite() {
+        true => 1.0,
+        false => 1.0 - (-rate * delta_time).exp(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screen_ray_at_center_is_forward() {
+        let mut camera = Camera::new();
+        camera.position = Point3::new(1.0, 2.0, 3.0);
+        camera.theta = 0.7;
+        camera.phi = 1.2;
+        let (origin, direction) = camera.screen_ray(400.0, 300.0, 800.0, 600.0);
+        assert!((direction - camera.forward()).norm() < 1e-4);
+        assert!((origin - camera.position).cross(&camera.forward()).norm() < 1e-4);
+    }
+
+    #[test]
+    fn reverse_z_maps_near_to_one_and_far_to_zero() {
+        let camera = Camera::new();
+        let projection = camera.projection_matrix(1.5, true);
+        let depth = |view_z: f32| {
+            let clip = projection * Vector4::new(0.0, 0.0, view_z, 1.0);
+            clip.z / clip.w
+        };
+        assert!((depth(-camera.znear()) - 1.0).abs() < 1e-5);
+        assert!(depth(-camera.zfar()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let mut camera = Camera::new();
+        camera.set_projection(ProjectionKind::Orthographic { height: 4.0 });
+        let (center_origin, center_direction) = camera.screen_ray(400.0, 300.0, 800.0, 600.0);
+        let (corner_origin, corner_direction) = camera.screen_ray(0.0, 0.0, 800.0, 600.0);
+        assert!((center_direction - camera.forward()).norm() < 1e-4);
+        assert!((corner_direction - camera.forward()).norm() < 1e-4);
+        // half the height, 2, up and half the width, 8 / 3 from the aspect ratio, to the side
+        assert!(((corner_origin - center_origin).norm() - 10.0 / 3.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn look_at_faces_the_target() {
+        let position = Point3::new(1.0, 2.0, 3.0);
+        let target = Point3::new(-2.0, 0.5, 7.0);
+        let camera = Camera::look_at(position, target);
+        assert_eq!(camera.position, position);
+        assert!((camera.forward() - (target - position).normalize()).norm() < 1e-5);
+    }
+
+    #[test]
+    fn reset_restores_the_default_pose() {
+        let mut camera = Camera::look_at(Point3::new(1.0, 2.0, 3.0), Point3::origin());
+        camera.set_projection(ProjectionKind::Orthographic { height: 2.0 });
+        camera.reset();
+        let default = Camera::new();
+        assert_eq!(camera.position, default.position);
+        assert_eq!((camera.phi, camera.theta), (default.phi, default.theta));
+        assert_eq!(
+            camera.projection(),
+            ProjectionKind::Orthographic { height: 2.0 }
+        );
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let mut camera = Camera::look_at(Point3::new(1.0, 2.0, 3.0), Point3::new(0.0, 5.0, -1.0));
+        camera.set_projection(ProjectionKind::Orthographic { height: 3.0 });
+        camera.set_zfar(250.0);
+        let mut restored = Camera::new();
+        restored.set_state(&camera.state());
+        assert_eq!(restored.state(), camera.state());
+        assert_eq!(restored.view_matrix(), camera.view_matrix());
+    }
+
+    #[test]
+    fn basis_matches_the_view_matrix() {
+        let camera = Camera::look_at(Point3::new(1.0, -2.0, 3.0), Point3::new(4.0, 0.0, -1.0));
+        let view_matrix = camera.view_matrix();
+        let to_view = |direction: Vector3<f32>| view_matrix.transform_vector(&direction);
+        // view space looks down -z, and y is flipped to point down the screen
+        assert!((to_view(camera.forward()) - Vector3::new(0.0, 0.0, -1.0)).norm() < 1e-5);
+        assert!((to_view(camera.right()) - Vector3::new(1.0, 0.0, 0.0)).norm() < 1e-5);
+        assert!((to_view(camera.up()) - Vector3::new(0.0, -1.0, 0.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn framed_bounds_are_centered_and_in_front() {
+        let mut camera = Camera::look_at(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 1.0));
+        let (min, max) = (Point3::new(10.0, -1.0, 10.0), Point3::new(12.0, 1.0, 40.0));
+        camera.frame_bounds(min, max, 2.0);
+        let center = nalgebra::center(&min, &max);
+        assert!(((center - camera.position).normalize() - camera.forward()).norm() < 1e-5);
+        let radius = (max - min).norm() / 2.0;
+        let ProjectionKind::Perspective { fovy } = camera.projection() else {
+            panic!("Framing changed the projection kind");
+        };
+        // wider than tall, so the bounding sphere touches the edges of the vertical field of view
+        let distance = (center - camera.position).norm();
+        assert!((radius / distance - (fovy / 2.0).sin()).abs() < 1e-5);
+        assert!(distance - radius >= camera.znear());
+        assert!(camera.zfar() >= distance + radius);
+
+        // taller than wide, so it touches the edges of the horizontal field of view instead
+        camera.frame_bounds(min, max, 0.5);
+        let distance = (center - camera.position).norm();
+        let half_fovx = ((fovy / 2.0).tan() * 0.5).atan();
+        assert!((radius / distance - half_fovx.sin()).abs() < 1e-5);
+
+        // a field of view this wide would put the sphere through the near plane
+        camera.set_fov_degrees(170.0);
+        camera.set_znear(1.0);
+        camera.frame_bounds(min, max, 1.0);
+        let distance = (center - camera.position).norm();
+        assert!(radius / 85f32.to_radians().sin() < radius + 1.0);
+        assert!(distance - radius >= 1.0 - 1e-4);
+    }
+
+    #[test]
+    fn fov_is_only_reported_for_perspective() {
+        let mut camera = Camera::new();
+        camera.set_fov_degrees(60.0);
+        assert!((camera.fovy().unwrap() - 60f32.to_radians()).abs() < 1e-6);
+        camera.set_projection(ProjectionKind::Orthographic { height: 2.0 });
+        assert_eq!(camera.fov_degrees(), None);
+        camera.set_fovy(1.0);
+        assert_eq!(
+            camera.projection(),
+            ProjectionKind::Perspective { fovy: 1.0 }
+        );
+    }
+
+    #[test]
+    fn analog_movement_scales_with_delta_time() {
+        let mut camera = Camera::new();
+        let mut camera_controller = CameraController::new(0.0, 0.0);
+        camera_controller.analog_forward = 0.5;
+        camera_controller.update_camera(&mut camera, 0.25);
+        let expected = camera.forward() * 0.5 * ANALOG_UNITS_PER_SECOND * 0.25;
+        assert!((camera.position.coords - expected).norm() < 1e-6);
+        // held values are not consumed by an update
+        assert_eq!(camera_controller.analog_forward, 0.5);
+    }
+
+    #[test]
+    fn controller_is_active_until_input_is_applied_and_released() {
+        let mut camera = Camera::new();
+        let mut camera_controller = CameraController::new(1.0, 1.0);
+        assert!(!camera_controller.is_active());
+        camera_controller.mouse_delta_x = 3.0;
+        assert!(camera_controller.is_active());
+        camera_controller.update_camera(&mut camera, 0.1);
+        assert!(!camera_controller.is_active());
+        camera_controller.set_pressed(CameraAction::Left, true);
+        camera_controller.update_camera(&mut camera, 0.1);
+        assert!(camera_controller.is_active());
+        camera_controller.set_pressed(CameraAction::Left, false);
+        assert!(!camera_controller.is_active());
+    }
+
+    #[test]
+    fn damped_movement_coasts_to_a_stop() {
+        let mut camera = Camera::new();
+        let mut camera_controller = CameraController::new(1.0, 0.0);
+        camera_controller.set_pressed(CameraAction::Forward, true);
+        camera_controller.update_camera(&mut camera, 0.1);
+        // the snappy defaults move at full speed right away
+        assert!((camera.position.coords - camera.forward() * 0.1).norm() < 1e-6);
+
+        camera_controller.damping = 10.0;
+        camera_controller.set_pressed(CameraAction::Forward, false);
+        camera_controller.update_camera(&mut camera, 0.1);
+        let coasted = camera.position.coords - camera.forward() * 0.1;
+        assert!((coasted - camera.forward() * (-1.0f32).exp() * 0.1).norm() < 1e-5);
+        assert!(camera_controller.is_active());
+        for _ in 0..100 {
+            camera_controller.update_camera(&mut camera, 0.1);
+        }
+        assert!(!camera_controller.is_active());
+    }
+
+    #[test]
+    fn rebinding_a_key_replaces_its_action() {
+        let mut key_bindings = KeyBindings::default();
+        assert_eq!(
+            key_bindings.action(KeyCode::KeyW),
+            Some(CameraAction::Forward)
+        );
+        key_bindings.bind(KeyCode::KeyW, CameraAction::Up);
+        assert_eq!(key_bindings.action(KeyCode::KeyW), Some(CameraAction::Up));
+        // the other forward binding is untouched
+        assert_eq!(
+            key_bindings.action(KeyCode::ArrowUp),
+            Some(CameraAction::Forward)
+        );
+        let key_bindings = KeyBindings::from_pairs([(KeyCode::KeyI, CameraAction::Forward)]);
+        assert_eq!(key_bindings.action(KeyCode::KeyW), None);
+    }
+
+    #[test]
+    fn pitch_is_clamped() {
+        let mut camera = Camera::new();
+        let mut camera_controller = CameraController::new(0.0, 1.0);
+        camera_controller.mouse_delta_y = 10.0;
+        camera_controller.update_camera(&mut camera, 0.0);
+        assert_eq!(camera.phi, PI - PHI_EPSILON);
+        camera_controller.mouse_delta_y = -10.0;
+        camera_controller.update_camera(&mut camera, 0.0);
+        assert_eq!(camera.phi, PHI_EPSILON);
     }
 }
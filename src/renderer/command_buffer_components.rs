@@ -1,15 +1,88 @@
-use ash::vk;
+use ash::{prelude::VkResult, vk};
+
+use super::{device_lost_or_panic, memory_allocator::MemoryAllocator, DeviceLost};
+
+// Frames the CPU may record ahead of the GPU. Draw command buffers, their fences and the frame
+// semaphores are indexed by the current frame, cycling through 0..MAX_FRAMES_IN_FLIGHT.
+pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
 
 pub struct CommandBufferComponents {
     pub reuse_command_pool: vk::CommandPool,
-    pub draw_command_buffer: vk::CommandBuffer,
-    pub draw_commands_reuse_fence: vk::Fence,
+    pub draw_command_buffers: Vec<vk::CommandBuffer>,
+    pub draw_commands_reuse_fences: Vec<vk::Fence>,
     pub setup_command_buffer: vk::CommandBuffer,
     pub setup_commands_reuse_fence: vk::Fence,
+    pub graphics_queue_family_index: u32,
+    // only when the device has a dedicated transfer queue
+    pub transfer_command_components: Option<TransferCommandComponents>,
+}
+
+pub struct TransferCommandComponents {
+    pub queue_family_index: u32,
+    pub queue: vk::Queue,
+    pub command_pool: vk::CommandPool,
+    pub command_buffer: vk::CommandBuffer,
+    pub reuse_fence: vk::Fence,
+    // signaled by the ownership release on the transfer queue, waited on by the acquire
+    pub ownership_transfer_semaphore: vk::Semaphore,
+}
+
+// Where buffer uploads are submitted. With a dedicated transfer queue the copy runs there and
+// the buffer is handed over to the graphics queue family afterwards.
+#[derive(Clone, Copy)]
+pub struct UploadCommands {
+    pub command_buffer: vk::CommandBuffer,
+    pub command_buffer_reuse_fence: vk::Fence,
+    pub queue: vk::Queue,
+    pub ownership_transfer: Option<QueueOwnershipTransfer>,
+}
+
+// What creating a resource with one off setup commands needs, e.g. to upload a texture or move
+// an image into its first layout. Submissions go to the graphics queue, which has to be waited
+// on through command_buffer_reuse_fence before the results are used.
+pub struct SetupContext<'a> {
+    pub device: &'a ash::Device,
+    pub memory_allocator: &'a mut MemoryAllocator,
+    pub command_buffer: vk::CommandBuffer,
+    pub command_buffer_reuse_fence: vk::Fence,
+    pub queue: vk::Queue,
+}
+
+impl SetupContext<'_> {
+    // Records and submits f without waiting for it to finish
+    pub fn submit<F: FnOnce(&ash::Device, vk::CommandBuffer)>(
+        &self,
+        f: F,
+    ) -> Result<(), DeviceLost> {
+        try_record_submit_commandbuffer(
+            self.device,
+            self.queue,
+            self.command_buffer,
+            self.command_buffer_reuse_fence,
+            SubmitSemaphores::default(),
+            f,
+        )
+        .map_err(|e| device_lost_or_panic(e, "Failed to submit setup commands"))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct QueueOwnershipTransfer {
+    pub src_queue_family_index: u32,
+    pub dst_queue_family_index: u32,
+    pub semaphore: vk::Semaphore,
+    // the acquire is recorded and submitted here
+    pub dst_command_buffer: vk::CommandBuffer,
+    pub dst_command_buffer_reuse_fence: vk::Fence,
+    pub dst_queue: vk::Queue,
 }
 
 impl CommandBufferComponents {
-    pub fn new(graphics_queue_family_index: u32, device: &ash::Device) -> CommandBufferComponents {
+    pub fn new(
+        graphics_queue_family_index: u32,
+        transfer_queue: Option<(u32, vk::Queue)>,
+        device: &ash::Device,
+    ) -> CommandBufferComponents {
         let reuse_pool_create_info = vk::CommandPoolCreateInfo::default()
             .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
             .queue_family_index(graphics_queue_family_index);
@@ -21,7 +94,7 @@ impl CommandBufferComponents {
         };
 
         let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
-            .command_buffer_count(2)
+            .command_buffer_count(1 + MAX_FRAMES_IN_FLIGHT as u32)
             .command_pool(reuse_command_pool)
             .level(vk::CommandBufferLevel::PRIMARY);
 
@@ -33,16 +106,18 @@ impl CommandBufferComponents {
 
         let setup_command_buffer = command_buffers[0];
 
-        let draw_command_buffer = command_buffers[1];
+        let draw_command_buffers = command_buffers[1..].to_vec();
 
         let fence_create_info =
             vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
 
-        let draw_commands_reuse_fence = unsafe {
-            device
-                .create_fence(&fence_create_info, None)
-                .expect("Failed to create fence")
-        };
+        let draw_commands_reuse_fences = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| unsafe {
+                device
+                    .create_fence(&fence_create_info, None)
+                    .expect("Failed to create fence")
+            })
+            .collect();
 
         let setup_commands_reuse_fence = unsafe {
             device
@@ -50,73 +125,156 @@ impl CommandBufferComponents {
                 .expect("Failed to create fence")
         };
 
+        let transfer_command_components = transfer_queue.map(|(queue_family_index, queue)| {
+            let transfer_pool_create_info = vk::CommandPoolCreateInfo::default()
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .queue_family_index(queue_family_index);
+            unsafe {
+                let command_pool = device
+                    .create_command_pool(&transfer_pool_create_info, None)
+                    .unwrap();
+                let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
+                    .command_buffer_count(1)
+                    .command_pool(command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY);
+                let command_buffer = device
+                    .allocate_command_buffers(&command_buffer_allocate_info)
+                    .unwrap()[0];
+                TransferCommandComponents {
+                    queue_family_index,
+                    queue,
+                    command_pool,
+                    command_buffer,
+                    reuse_fence: device
+                        .create_fence(&fence_create_info, None)
+                        .expect("Failed to create fence"),
+                    ownership_transfer_semaphore: device
+                        .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                        .unwrap(),
+                }
+            }
+        });
+
         CommandBufferComponents {
             reuse_command_pool,
-            draw_command_buffer,
-            draw_commands_reuse_fence,
+            draw_command_buffers,
+            draw_commands_reuse_fences,
             setup_command_buffer,
             setup_commands_reuse_fence,
+            graphics_queue_family_index,
+            transfer_command_components,
+        }
+    }
+    // True once every submitted frame's commands have completed, for checking that resources
+    // they use can be destroyed
+    pub fn frames_finished(&self, device: &ash::Device) -> bool {
+        self.draw_commands_reuse_fences
+            .iter()
+            .all(|&fence| unsafe { device.get_fence_status(fence) }.unwrap_or(true))
+    }
+    pub fn setup_context<'a>(
+        &self,
+        device: &'a ash::Device,
+        memory_allocator: &'a mut MemoryAllocator,
+        graphics_queue: vk::Queue,
+    ) -> SetupContext<'a> {
+        SetupContext {
+            device,
+            memory_allocator,
+            command_buffer: self.setup_command_buffer,
+            command_buffer_reuse_fence: self.setup_commands_reuse_fence,
+            queue: graphics_queue,
+        }
+    }
+    // Uploads go through the transfer queue when there is one, the graphics queue otherwise
+    pub fn upload_commands(&self, graphics_queue: vk::Queue) -> UploadCommands {
+        match &self.transfer_command_components {
+            Some(transfer_command_components) => UploadCommands {
+                command_buffer: transfer_command_components.command_buffer,
+                command_buffer_reuse_fence: transfer_command_components.reuse_fence,
+                queue: transfer_command_components.queue,
+                ownership_transfer: Some(QueueOwnershipTransfer {
+                    src_queue_family_index: transfer_command_components.queue_family_index,
+                    dst_queue_family_index: self.graphics_queue_family_index,
+                    semaphore: transfer_command_components.ownership_transfer_semaphore,
+                    dst_command_buffer: self.setup_command_buffer,
+                    dst_command_buffer_reuse_fence: self.setup_commands_reuse_fence,
+                    dst_queue: graphics_queue,
+                }),
+            },
+            None => UploadCommands {
+                command_buffer: self.setup_command_buffer,
+                command_buffer_reuse_fence: self.setup_commands_reuse_fence,
+                queue: graphics_queue,
+                ownership_transfer: None,
+            },
         }
     }
     pub fn cleanup(&self, device: &ash::Device) {
         unsafe {
+            if let Some(transfer_command_components) = &self.transfer_command_components {
+                device.destroy_command_pool(transfer_command_components.command_pool, None);
+                device.destroy_fence(transfer_command_components.reuse_fence, None);
+                device.destroy_semaphore(
+                    transfer_command_components.ownership_transfer_semaphore,
+                    None,
+                );
+            }
             device.destroy_command_pool(self.reuse_command_pool, None);
             device.destroy_fence(self.setup_commands_reuse_fence, None);
-            device.destroy_fence(self.draw_commands_reuse_fence, None);
+            for &fence in self.draw_commands_reuse_fences.iter() {
+                device.destroy_fence(fence, None);
+            }
         }
     }
 }
 
-pub fn record_submit_commandbuffer<F: FnOnce(&ash::Device, vk::CommandBuffer)>(
+// What a submission waits on before the stages in wait_mask, one per wait semaphore, and
+// signals once it's done
+#[derive(Clone, Copy, Default)]
+pub struct SubmitSemaphores<'a> {
+    pub wait_mask: &'a [vk::PipelineStageFlags],
+    pub wait_semaphores: &'a [vk::Semaphore],
+    pub signal_semaphores: &'a [vk::Semaphore],
+}
+
+// Errors such as ERROR_DEVICE_LOST are returned to the caller
+pub fn try_record_submit_commandbuffer<F: FnOnce(&ash::Device, vk::CommandBuffer)>(
     device: &ash::Device,
     queue: vk::Queue,
     command_buffer: vk::CommandBuffer,
     command_buffer_reuse_fence: vk::Fence,
-    wait_mask: &[vk::PipelineStageFlags],
-    wait_semaphores: &[vk::Semaphore],
-    signal_semaphores: &[vk::Semaphore],
+    semaphores: SubmitSemaphores,
     submission_function: F,
-) {
+) -> VkResult<()> {
     unsafe {
-        device
-            .wait_for_fences(&[command_buffer_reuse_fence], true, u64::MAX)
-            .expect("Wait for fence failed.");
+        device.wait_for_fences(&[command_buffer_reuse_fence], true, u64::MAX)?;
 
-        device
-            .reset_fences(&[command_buffer_reuse_fence])
-            .expect("Reset fences failed.");
+        device.reset_fences(&[command_buffer_reuse_fence])?;
 
-        device
-            .reset_command_buffer(
-                command_buffer,
-                vk::CommandBufferResetFlags::RELEASE_RESOURCES,
-            )
-            .expect("Reset command buffer failed.");
+        device.reset_command_buffer(
+            command_buffer,
+            vk::CommandBufferResetFlags::RELEASE_RESOURCES,
+        )?;
 
         let command_buffer_begin_info = vk::CommandBufferBeginInfo::default()
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
 
-        device
-            .begin_command_buffer(command_buffer, &command_buffer_begin_info)
-            .expect("Begin commandbuffer failed.");
+        device.begin_command_buffer(command_buffer, &command_buffer_begin_info)?;
 
         (submission_function)(device, command_buffer);
 
-        device
-            .end_command_buffer(command_buffer)
-            .expect("End commandbuffer failed.");
+        device.end_command_buffer(command_buffer)?;
 
         let command_buffers = vec![command_buffer];
 
         let submit_info = vk::SubmitInfo::default()
-            .wait_semaphores(wait_semaphores)
-            .wait_dst_stage_mask(wait_mask)
+            .wait_semaphores(semaphores.wait_semaphores)
+            .wait_dst_stage_mask(semaphores.wait_mask)
             .command_buffers(&command_buffers)
-            .signal_semaphores(signal_semaphores);
+            .signal_semaphores(semaphores.signal_semaphores);
 
-        device
-            .queue_submit(queue, &[submit_info], command_buffer_reuse_fence)
-            .expect("queue submit failed.");
+        device.queue_submit(queue, &[submit_info], command_buffer_reuse_fence)
     }
 }
 
@@ -2,6 +2,27 @@ use std::{borrow::Cow, ffi::CStr};
 
 use ash::{ext::debug_utils, vk};
 
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DebugConfig {
+    // Loads VK_LAYER_KHRONOS_validation and the debug messenger, skipped with a warning
+    // when the layer isn't installed. Defaults to on for debug builds.
+    pub enable_validation: bool,
+    // Enables the validation layer's best-practices checks. Only takes effect when
+    // validation is enabled and VK_LAYER_KHRONOS_validation is present.
+    // Read once at instance creation.
+    pub best_practices_validation: bool,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            enable_validation: cfg!(debug_assertions),
+            best_practices_validation: false,
+        }
+    }
+}
+
 pub struct DebugComponents {
     debug_utils_loader: debug_utils::Instance,
     debug_callback: vk::DebugUtilsMessengerEXT,
@@ -1,68 +1,227 @@
 use ash::vk;
-use nalgebra::Matrix4;
+use nalgebra::{Matrix4, Vector4};
 
-use super::{buffer::Buffer};
+use super::{
+    buffer::Buffer, command_buffer_components::MAX_FRAMES_IN_FLIGHT,
+    memory_allocator::MemoryAllocator, textures::Texture,
+};
 
+// Set 0, written once per frame
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
-pub struct UniformBuffers {
-    pub model_matrix: Matrix4<f32>,
+pub struct FrameUniforms {
     pub view_matrix: Matrix4<f32>,
     pub projection_matrix: Matrix4<f32>,
+    // world space direction the light travels in, w is unused
+    pub light_direction: Vector4<f32>,
+}
+
+// Set 1, written per object and selected with a dynamic offset
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ObjectUniforms {
+    pub model_matrix: Matrix4<f32>,
+    // inverse transpose of the model matrix, only the upper 3x3 is used
+    pub normal_matrix: Matrix4<f32>,
 }
 
+// Objects whose uniforms fit in one uniform buffer
+pub const MAX_OBJECTS: usize = 256;
+
+// Distance between consecutive objects' uniforms, dynamic offsets have to be multiples of
+// minUniformBufferOffsetAlignment
+pub fn object_uniform_stride(min_uniform_buffer_offset_alignment: vk::DeviceSize) -> usize {
+    size_of::<ObjectUniforms>().next_multiple_of(min_uniform_buffer_offset_alignment as usize)
+}
+
+// Bytes each frame's storage buffer starts out with, write_storage_data grows them as needed
+const INITIAL_STORAGE_BUFFER_SIZE: usize = 4096;
+
+// One binding of a descriptor set layout
+#[derive(Debug, Clone, Copy)]
+pub struct DescriptorBinding {
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub stage_flags: vk::ShaderStageFlags,
+    pub count: u32,
+}
+
+impl DescriptorBinding {
+    const fn new(
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> Self {
+        Self {
+            binding,
+            descriptor_type,
+            stage_flags,
+            count: 1,
+        }
+    }
+    fn layout_binding(&self) -> vk::DescriptorSetLayoutBinding<'static> {
+        vk::DescriptorSetLayoutBinding::default()
+            .binding(self.binding)
+            .descriptor_type(self.descriptor_type)
+            .descriptor_count(self.count)
+            .stage_flags(self.stage_flags)
+    }
+}
+
+const FRAME_UNIFORM_BINDING: u32 = 0;
+const TEXTURE_BINDING: u32 = 1;
+// left unwritten until write_skybox
+const SKYBOX_BINDING: u32 = 2;
+const STORAGE_BUFFER_BINDING: u32 = 3;
+
+const FRAME_SET_BINDINGS: [DescriptorBinding; 4] = [
+    DescriptorBinding::new(
+        FRAME_UNIFORM_BINDING,
+        vk::DescriptorType::UNIFORM_BUFFER,
+        vk::ShaderStageFlags::from_raw(
+            vk::ShaderStageFlags::VERTEX.as_raw() | vk::ShaderStageFlags::FRAGMENT.as_raw(),
+        ),
+    ),
+    DescriptorBinding::new(
+        TEXTURE_BINDING,
+        vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        vk::ShaderStageFlags::FRAGMENT,
+    ),
+    DescriptorBinding::new(
+        SKYBOX_BINDING,
+        vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        vk::ShaderStageFlags::FRAGMENT,
+    ),
+    DescriptorBinding::new(
+        STORAGE_BUFFER_BINDING,
+        vk::DescriptorType::STORAGE_BUFFER,
+        vk::ShaderStageFlags::from_raw(
+            vk::ShaderStageFlags::VERTEX.as_raw() | vk::ShaderStageFlags::FRAGMENT.as_raw(),
+        ),
+    ),
+];
+
+const OBJECT_SET_BINDINGS: [DescriptorBinding; 1] = [DescriptorBinding::new(
+    0,
+    vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+    vk::ShaderStageFlags::VERTEX,
+)];
+
+// One pool size per descriptor type, enough for set_count sets of each layout
+fn pool_sizes(set_layouts: &[(&[DescriptorBinding], u32)]) -> Vec<vk::DescriptorPoolSize> {
+    let mut pool_sizes: Vec<vk::DescriptorPoolSize> = Vec::new();
+    for &(bindings, set_count) in set_layouts {
+        for binding in bindings {
+            let descriptor_count = binding.count * set_count;
+            match pool_sizes
+                .iter_mut()
+                .find(|pool_size| pool_size.ty == binding.descriptor_type)
+            {
+                Some(pool_size) => pool_size.descriptor_count += descriptor_count,
+                None => pool_sizes.push(
+                    vk::DescriptorPoolSize::default()
+                        .ty(binding.descriptor_type)
+                        .descriptor_count(descriptor_count),
+                ),
+            }
+        }
+    }
+    pool_sizes
+}
+
+fn create_descriptor_set_layout(
+    device: &ash::Device,
+    bindings: &[DescriptorBinding],
+) -> vk::DescriptorSetLayout {
+    let layout_bindings: Vec<_> = bindings
+        .iter()
+        .map(DescriptorBinding::layout_binding)
+        .collect();
+    unsafe {
+        device
+            .create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&layout_bindings),
+                None,
+            )
+            .expect("Failed to create descriptor set layout.")
+    }
+}
+
+// Buffers and sets are indexed by the current frame, the draw commands reuse fence guards them
 pub struct DescriptorComponents {
     pub descriptor_pool: vk::DescriptorPool,
-    pub uniform_buffer_descriptor_sets: Vec<vk::DescriptorSet>,
-    pub uniform_buffer_descriptor_set_layout: vk::DescriptorSetLayout,
-    pub uniform_buffers: Vec<Buffer<UniformBuffers>>,
+    pub frame_descriptor_set_layout: vk::DescriptorSetLayout,
+    pub object_descriptor_set_layout: vk::DescriptorSetLayout,
+    pub frame_descriptor_sets: Vec<vk::DescriptorSet>,
+    pub object_descriptor_sets: Vec<vk::DescriptorSet>,
+    pub frame_uniform_buffers: Vec<Buffer<FrameUniforms>>,
+    // each holding MAX_OBJECTS ObjectUniforms object_uniform_stride apart
+    pub object_uniform_buffers: Vec<Buffer<u8>>,
+    pub object_uniform_stride: usize,
+    // read only by shaders that declare set 0 binding 3, e.g. large per-object data
+    pub storage_buffers: Vec<Buffer<u8>>,
 }
 
 impl DescriptorComponents {
     pub fn new(
         device: &ash::Device,
-        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
-        present_image_count: u32,
+        memory_allocator: &mut MemoryAllocator,
+        texture: &Texture,
+        min_uniform_buffer_offset_alignment: vk::DeviceSize,
     ) -> DescriptorComponents {
         // Buffers
-        let mut uniform_buffers = Vec::with_capacity(present_image_count as usize);
-        for _ in 0..present_image_count {
-            let uniform_buffer = Buffer::<UniformBuffers>::new(
+        // written by the host every frame, device local host visible memory saves the GPU
+        // reading them over the bus on systems with resizable BAR
+        let host_memory_properties =
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        let uniform_memory_properties = [
+            vk::MemoryPropertyFlags::DEVICE_LOCAL | host_memory_properties,
+            host_memory_properties,
+        ];
+        let object_uniform_stride = object_uniform_stride(min_uniform_buffer_offset_alignment);
+        let mut frame_uniform_buffers = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        let mut object_uniform_buffers = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        let mut storage_buffers = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            frame_uniform_buffers.push(Buffer::<FrameUniforms>::new_preferring(
                 device,
-                physical_device_memory_properties,
+                memory_allocator,
                 vk::BufferUsageFlags::UNIFORM_BUFFER,
                 vk::SharingMode::EXCLUSIVE,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                &uniform_memory_properties,
                 1,
-                true,
-            );
-            uniform_buffers.push(uniform_buffer);
+            ));
+            object_uniform_buffers.push(Buffer::<u8>::new_preferring(
+                device,
+                memory_allocator,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                vk::SharingMode::EXCLUSIVE,
+                &uniform_memory_properties,
+                object_uniform_stride * MAX_OBJECTS,
+            ));
+            storage_buffers.push(Buffer::<u8>::new_preferring(
+                device,
+                memory_allocator,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::SharingMode::EXCLUSIVE,
+                &uniform_memory_properties,
+                INITIAL_STORAGE_BUFFER_SIZE,
+            ));
         }
 
-        // Uniform Buffer Descriptor Sets
-        let uniform_buffer_descriptor_set_layout_bindings =
-            [vk::DescriptorSetLayoutBinding::default()
-                .binding(0)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                .descriptor_count(1)
-                .stage_flags(vk::ShaderStageFlags::VERTEX)];
+        // Descriptor Set Layouts
+        let frame_descriptor_set_layout = create_descriptor_set_layout(device, &FRAME_SET_BINDINGS);
+        let object_descriptor_set_layout =
+            create_descriptor_set_layout(device, &OBJECT_SET_BINDINGS);
 
-        let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
-            .bindings(&uniform_buffer_descriptor_set_layout_bindings);
-
-        let uniform_buffer_descriptor_set_layout = unsafe {
-            device
-                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)
-                .expect("Failed to create descriptor set layout.")
-        };
-
-        let pool_sizes = [vk::DescriptorPoolSize::default()
-            .descriptor_count(present_image_count)
-            .ty(vk::DescriptorType::UNIFORM_BUFFER)];
+        let pool_sizes = pool_sizes(&[
+            (&FRAME_SET_BINDINGS, MAX_FRAMES_IN_FLIGHT as u32),
+            (&OBJECT_SET_BINDINGS, MAX_FRAMES_IN_FLIGHT as u32),
+        ]);
 
         let pool_create_info = vk::DescriptorPoolCreateInfo::default()
             .pool_sizes(&pool_sizes)
-            .max_sets(present_image_count);
+            .max_sets(2 * MAX_FRAMES_IN_FLIGHT as u32);
 
         let descriptor_pool = unsafe {
             device
@@ -70,51 +229,244 @@ impl DescriptorComponents {
                 .expect("Failed to create descriptor pool.")
         };
 
-        let set_layouts = vec![uniform_buffer_descriptor_set_layout; present_image_count as usize];
+        let allocate_descriptor_sets = |set_layout| {
+            let set_layouts = [set_layout; MAX_FRAMES_IN_FLIGHT];
+            let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&set_layouts);
+            unsafe {
+                device
+                    .allocate_descriptor_sets(&descriptor_set_allocate_info)
+                    .expect("Failed to allocate descriptor sets.")
+            }
+        };
+        let frame_descriptor_sets = allocate_descriptor_sets(frame_descriptor_set_layout);
+        let object_descriptor_sets = allocate_descriptor_sets(object_descriptor_set_layout);
 
-        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
-            .descriptor_pool(descriptor_pool)
-            .set_layouts(&set_layouts);
+        for frame in 0..MAX_FRAMES_IN_FLIGHT {
+            let frame_buffer_info = [vk::DescriptorBufferInfo::default()
+                .buffer(frame_uniform_buffers[frame].buffer)
+                .offset(0)
+                .range(size_of::<FrameUniforms>() as u64)];
 
-        let uniform_buffer_descriptor_sets = unsafe {
-            device
-                .allocate_descriptor_sets(&descriptor_set_allocate_info)
-                .expect("Failed to allocate descriptor sets.")
-        };
+            let frame_descriptor_write = vk::WriteDescriptorSet::default()
+                .dst_set(frame_descriptor_sets[frame])
+                .dst_binding(FRAME_UNIFORM_BINDING)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_count(1)
+                .buffer_info(&frame_buffer_info);
+
+            let descriptor_image_info = [vk::DescriptorImageInfo::default()
+                .sampler(texture.sampler)
+                .image_view(texture.image_view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+
+            let texture_descriptor_write = vk::WriteDescriptorSet::default()
+                .dst_set(frame_descriptor_sets[frame])
+                .dst_binding(TEXTURE_BINDING)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .image_info(&descriptor_image_info);
 
-        for i in 0..uniform_buffer_descriptor_sets.len() {
-            let descriptor_buffer_info = [vk::DescriptorBufferInfo::default()
-                .buffer(uniform_buffers[i].buffer)
+            let object_buffer_info = [vk::DescriptorBufferInfo::default()
+                .buffer(object_uniform_buffers[frame].buffer)
                 .offset(0)
-                .range(size_of::<UniformBuffers>() as u64)];
+                .range(size_of::<ObjectUniforms>() as u64)];
 
-            let descriptor_write = vk::WriteDescriptorSet::default()
-                .dst_set(uniform_buffer_descriptor_sets[i])
+            let object_descriptor_write = vk::WriteDescriptorSet::default()
+                .dst_set(object_descriptor_sets[frame])
                 .dst_binding(0)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
                 .descriptor_count(1)
-                .buffer_info(&descriptor_buffer_info);
+                .buffer_info(&object_buffer_info);
 
             unsafe {
-                device.update_descriptor_sets(&[descriptor_write], &[]);
+                device.update_descriptor_sets(
+                    &[
+                        frame_descriptor_write,
+                        texture_descriptor_write,
+                        object_descriptor_write,
+                    ],
+                    &[],
+                );
             }
         }
 
-        DescriptorComponents {
+        let descriptor_components = DescriptorComponents {
             descriptor_pool,
-            uniform_buffer_descriptor_set_layout,
-            uniform_buffer_descriptor_sets,
-            uniform_buffers,
+            frame_descriptor_set_layout,
+            object_descriptor_set_layout,
+            frame_descriptor_sets,
+            object_descriptor_sets,
+            frame_uniform_buffers,
+            object_uniform_buffers,
+            object_uniform_stride,
+            storage_buffers,
+        };
+        for frame_index in 0..MAX_FRAMES_IN_FLIGHT {
+            descriptor_components.write_storage_buffer(device, frame_index);
+        }
+        descriptor_components
+    }
+    // In set order, for the pipeline layout
+    pub fn descriptor_set_layouts(&self) -> [vk::DescriptorSetLayout; 2] {
+        [
+            self.frame_descriptor_set_layout,
+            self.object_descriptor_set_layout,
+        ]
+    }
+    // Points one frame's texture binding at another image. Only while no submitted draw of that
+    // frame uses its set, i.e. once the frame's draw commands reuse fence is signaled.
+    pub fn update_texture(
+        &self,
+        device: &ash::Device,
+        frame_index: usize,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+    ) {
+        let descriptor_image_info = [vk::DescriptorImageInfo::default()
+            .sampler(sampler)
+            .image_view(image_view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let texture_descriptor_write = vk::WriteDescriptorSet::default()
+            .dst_set(self.frame_descriptor_sets[frame_index])
+            .dst_binding(TEXTURE_BINDING)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .image_info(&descriptor_image_info);
+        unsafe { device.update_descriptor_sets(&[texture_descriptor_write], &[]) };
+    }
+    // Only while no submitted frame uses the sets
+    pub fn write_skybox(&self, device: &ash::Device, skybox: &Texture) {
+        let descriptor_image_info = [vk::DescriptorImageInfo::default()
+            .sampler(skybox.sampler)
+            .image_view(skybox.image_view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let skybox_descriptor_writes: Vec<_> = self
+            .frame_descriptor_sets
+            .iter()
+            .map(|&frame_descriptor_set| {
+                vk::WriteDescriptorSet::default()
+                    .dst_set(frame_descriptor_set)
+                    .dst_binding(SKYBOX_BINDING)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .image_info(&descriptor_image_info)
+            })
+            .collect();
+        unsafe { device.update_descriptor_sets(&skybox_descriptor_writes, &[]) };
+    }
+    // Copies data to the start of frame_index's storage buffer, growing it when it doesn't fit.
+    // Only once that frame's last submission finished.
+    pub fn write_storage_data(
+        &mut self,
+        device: &ash::Device,
+        memory_allocator: &mut MemoryAllocator,
+        frame_index: usize,
+        data: &[u8],
+    ) {
+        let storage_buffer = &mut self.storage_buffers[frame_index];
+        let resized = data.len() > storage_buffer.capacity();
+        if resized {
+            storage_buffer.resize(device, memory_allocator, data.len());
         }
+        storage_buffer.write_data_at(device, 0, data);
+        if resized {
+            self.write_storage_buffer(device, frame_index);
+        }
+    }
+    fn write_storage_buffer(&self, device: &ash::Device, frame_index: usize) {
+        let storage_buffer_info = [vk::DescriptorBufferInfo::default()
+            .buffer(self.storage_buffers[frame_index].buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)];
+        let storage_buffer_descriptor_write = vk::WriteDescriptorSet::default()
+            .dst_set(self.frame_descriptor_sets[frame_index])
+            .dst_binding(STORAGE_BUFFER_BINDING)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .buffer_info(&storage_buffer_info);
+        unsafe { device.update_descriptor_sets(&[storage_buffer_descriptor_write], &[]) };
+    }
+    // Selects object_index's uniforms when binding the object descriptor set
+    pub fn dynamic_offset(&self, object_index: usize) -> u32 {
+        (object_index * self.object_uniform_stride) as u32
+    }
+    // Only once frame's draw commands reuse fence is signaled, the GPU may still be reading the
+    // buffer before that
+    pub fn write_frame_uniforms(
+        &mut self,
+        device: &ash::Device,
+        frame: usize,
+        uniforms: &FrameUniforms,
+    ) {
+        self.frame_uniform_buffers[frame].write_data_direct(device, std::slice::from_ref(uniforms));
+    }
+    // Same as write_frame_uniforms, only once frame's fence is signaled
+    pub fn write_object_uniforms(
+        &mut self,
+        device: &ash::Device,
+        frame: usize,
+        object_index: usize,
+        uniforms: &ObjectUniforms,
+    ) {
+        assert!(
+            object_index < MAX_OBJECTS,
+            "Object index {object_index} out of range"
+        );
+        let uniform_bytes = unsafe {
+            std::slice::from_raw_parts(
+                uniforms as *const ObjectUniforms as *const u8,
+                size_of::<ObjectUniforms>(),
+            )
+        };
+        let offset = self.dynamic_offset(object_index) as usize;
+        self.object_uniform_buffers[frame].write_data_at(device, offset, uniform_bytes);
     }
 
-    pub fn cleanup(&mut self, device: &ash::Device) {
+    pub fn cleanup(&mut self, device: &ash::Device, memory_allocator: &mut MemoryAllocator) {
         unsafe {
             device.destroy_descriptor_pool(self.descriptor_pool, None);
-            device.destroy_descriptor_set_layout(self.uniform_buffer_descriptor_set_layout, None);
-            for i in 0..self.uniform_buffers.len() {
-                self.uniform_buffers[i].cleanup(device);
+            device.destroy_descriptor_set_layout(self.frame_descriptor_set_layout, None);
+            device.destroy_descriptor_set_layout(self.object_descriptor_set_layout, None);
+            for i in 0..MAX_FRAMES_IN_FLIGHT {
+                self.frame_uniform_buffers[i].cleanup(device, memory_allocator);
+                self.object_uniform_buffers[i].cleanup(device, memory_allocator);
+                self.storage_buffers[i].cleanup(device, memory_allocator);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_sizes_are_summed_per_descriptor_type() {
+        let pool_sizes = pool_sizes(&[(&FRAME_SET_BINDINGS, 2), (&OBJECT_SET_BINDINGS, 3)]);
+        let descriptor_count = |ty| {
+            pool_sizes
+                .iter()
+                .find(|pool_size| pool_size.ty == ty)
+                .map(|pool_size| pool_size.descriptor_count)
+        };
+        assert_eq!(pool_sizes.len(), 4);
+        assert_eq!(
+            descriptor_count(vk::DescriptorType::UNIFORM_BUFFER),
+            Some(2)
+        );
+        assert_eq!(
+            descriptor_count(vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+            Some(4)
+        );
+        assert_eq!(
+            descriptor_count(vk::DescriptorType::STORAGE_BUFFER),
+            Some(2)
+        );
+        assert_eq!(
+            descriptor_count(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC),
+            Some(3)
+        );
+    }
+}
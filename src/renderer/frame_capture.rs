@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Result};
+use ash::vk;
+use image::RgbaImage;
+
+use super::{buffer::Buffer, memory_allocator::MemoryAllocator};
+
+// Host visible copy of the last drawn frame while frame capture is enabled. A presented image
+// belongs to the presentation engine until it is acquired again, so the copy is recorded into
+// the frame's own command buffer before the image is presented.
+pub struct FrameCaptureComponents {
+    pub readback_buffer: Buffer<u8>,
+    extent: vk::Extent2D,
+}
+
+impl FrameCaptureComponents {
+    pub fn new(
+        device: &ash::Device,
+        memory_allocator: &mut MemoryAllocator,
+        extent: vk::Extent2D,
+    ) -> FrameCaptureComponents {
+        let readback_buffer = Buffer::<u8>::new(
+            device,
+            memory_allocator,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::SharingMode::EXCLUSIVE,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            (extent.width * extent.height * 4) as usize,
+        );
+        FrameCaptureComponents {
+            readback_buffer,
+            extent,
+        }
+    }
+    // Copies the finished frame out of image, which has to be in layout and created with
+    // TRANSFER_SRC usage. The image is returned to layout afterwards.
+    pub fn record_copy(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        layout: vk::ImageLayout,
+    ) {
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1);
+        // the frame was either rendered into image or copied there from the accumulation image
+        let transfer_barrier = vk::ImageMemoryBarrier::default()
+            .image(image)
+            .src_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::TRANSFER_WRITE,
+            )
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .subresource_range(subresource_range);
+        let copy_region = vk::BufferImageCopy::default()
+            .image_subresource(
+                vk::ImageSubresourceLayers::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1),
+            )
+            .image_extent(self.extent.into());
+        let present_barrier = vk::ImageMemoryBarrier::default()
+            .image(image)
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(layout)
+            .subresource_range(subresource_range);
+        let host_read_barrier = vk::BufferMemoryBarrier::default()
+            .buffer(self.readback_buffer.buffer)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .size(vk::WHOLE_SIZE);
+        unsafe {
+            // also orders the copy after the previous frame's copy into the same buffer
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[transfer_barrier],
+            );
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.readback_buffer.buffer,
+                &[copy_region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE | vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[host_read_barrier],
+                &[present_barrier],
+            );
+        }
+    }
+    // The last recorded copy as RGBA, it has to have finished. format is the format of the
+    // copied image.
+    pub fn read(&self, device: &ash::Device, format: vk::Format) -> Result<RgbaImage> {
+        let bgra = match format {
+            vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => true,
+            vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => false,
+            _ => return Err(anyhow!("Capturing {format:?} images is not supported")),
+        };
+        let mut pixels = self.readback_buffer.read_data(device);
+        if bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        // presentation ignores alpha since the swapchain is created with OPAQUE composite alpha
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel[3] = u8::MAX;
+        }
+        RgbaImage::from_raw(self.extent.width, self.extent.height, pixels)
+            .ok_or_else(|| anyhow!("Captured data does not match the image size"))
+    }
+    pub fn cleanup(&self, device: &ash::Device, memory_allocator: &mut MemoryAllocator) {
+        self.readback_buffer.cleanup(device, memory_allocator);
+    }
+}
@@ -0,0 +1,72 @@
+use std::time::{Duration, Instant};
+
+// Frames the average is taken over
+pub const FRAME_TIME_SAMPLES: usize = 64;
+
+// Rolling frame times measured between consecutive frame starts
+pub struct FrameTimer {
+    last_frame_start: Option<Instant>,
+    frame_times: [Duration; FRAME_TIME_SAMPLES],
+    // next slot of frame_times to write, wraps around once the buffer is full
+    next_sample: usize,
+    sample_count: usize,
+}
+
+impl FrameTimer {
+    pub fn new() -> Self {
+        Self {
+            last_frame_start: None,
+            frame_times: [Duration::ZERO; FRAME_TIME_SAMPLES],
+            next_sample: 0,
+            sample_count: 0,
+        }
+    }
+    pub fn start_frame(&mut self) {
+        self.start_frame_at(Instant::now());
+    }
+    fn start_frame_at(&mut self, frame_start: Instant) {
+        if let Some(last_frame_start) = self.last_frame_start {
+            self.frame_times[self.next_sample] = frame_start - last_frame_start;
+            self.next_sample = (self.next_sample + 1) % FRAME_TIME_SAMPLES;
+            self.sample_count = (self.sample_count + 1).min(FRAME_TIME_SAMPLES);
+        }
+        self.last_frame_start = Some(frame_start);
+    }
+    // Zero until two frames have started
+    pub fn last_frame_time(&self) -> Duration {
+        match self.sample_count {
+            0 => Duration::ZERO,
+            _ => self.frame_times[(self.next_sample + FRAME_TIME_SAMPLES - 1) % FRAME_TIME_SAMPLES],
+        }
+    }
+    pub fn average_fps(&self) -> f32 {
+        let total: Duration = self.frame_times[..self.sample_count].iter().sum();
+        match total.is_zero() {
+            true => 0.0,
+            false => self.sample_count as f32 / total.as_secs_f32(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn average_covers_only_the_latest_samples() {
+        let mut frame_timer = FrameTimer::new();
+        let start = Instant::now();
+        frame_timer.start_frame_at(start);
+        for i in 1..=FRAME_TIME_SAMPLES {
+            frame_timer.start_frame_at(start + Duration::from_millis(10) * i as u32);
+        }
+        assert!((frame_timer.average_fps() - 100.0).abs() < 1e-2);
+
+        let slow_start = start + Duration::from_millis(10) * FRAME_TIME_SAMPLES as u32;
+        for i in 1..=FRAME_TIME_SAMPLES {
+            frame_timer.start_frame_at(slow_start + Duration::from_millis(20) * i as u32);
+        }
+        assert_eq!(frame_timer.last_frame_time(), Duration::from_millis(20));
+        assert!((frame_timer.average_fps() - 50.0).abs() < 1e-2);
+    }
+}
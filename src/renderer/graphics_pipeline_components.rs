@@ -2,9 +2,477 @@ use std::mem::offset_of;
 
 use ash::vk;
 
-use super::{resize_dependent_components::DEPTH_IMAGE_FORMAT, vertex_buffer_components::Vertex};
+use super::{
+    resize_dependent_components::needs_srgb_encoding,
+    shaders::{
+        SpecializationConstants, ENCODE_SRGB_CONSTANT_ID, LIGHTING_ENABLED_CONSTANT_ID,
+        SKYBOX_DEPTH_CONSTANT_ID,
+    },
+    vertex_buffer_components::{InstanceVertex, Vertex, VertexAttribs},
+};
+
+// indices into graphics_pipelines
+pub const INTERLEAVED_PIPELINE_INDEX: usize = 0;
+pub const SPLIT_PIPELINE_INDEX: usize = 1;
+pub const PROCEDURAL_PIPELINE_INDEX: usize = 2;
+pub const SKYBOX_PIPELINE_INDEX: usize = 3;
+pub const GRID_PIPELINE_INDEX: usize = 4;
+
+// Pushed before each frame's draws, the fragment shader declares the same block
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct FragmentPushConstants {
+    // multiplies the interpolated vertex color
+    pub tint: [f32; 4],
+}
+
+impl FragmentPushConstants {
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self as *const Self as *const u8, size_of::<Self>()) }
+    }
+}
+
+// instance data follows the vertex streams of each pipeline
+pub const INTERLEAVED_INSTANCE_BINDING: u32 = 1;
+pub const SPLIT_INSTANCE_BINDING: u32 = 2;
+
+// Fixed function state the graphics pipelines are built with.
+// Changing any of it requires rebuilding the pipelines.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PipelineConfig {
+    // Written for the standard depth direction, flipped when reverse_z is on
+    #[cfg_attr(feature = "serde", serde(with = "super::vk_serde::compare_op"))]
+    pub depth_compare_op: vk::CompareOp,
+    // Clears depth to 0.0 with the near plane at 1.0, which keeps far more precision in the
+    // distance when paired with a float depth format
+    pub reverse_z: bool,
+    pub depth_bias: Option<DepthBias>,
+    // None disables the depth bounds test, which needs the depthBounds device feature
+    pub depth_bounds: Option<DepthBounds>,
+    // None uses the implementation's default line rasterization
+    pub line_mode: Option<LineMode>,
+    // LINE draws wireframes and POINT draws vertices, both need the fillModeNonSolid feature
+    #[cfg_attr(feature = "serde", serde(with = "super::vk_serde::polygon_mode"))]
+    pub polygon_mode: vk::PolygonMode,
+    // Counterclockwise front faces with back faces culled by default, NONE draws both sides
+    // for meshes with inconsistent winding.
+    #[cfg_attr(feature = "serde", serde(with = "super::vk_serde::cull_mode_flags"))]
+    pub cull_mode: vk::CullModeFlags,
+    #[cfg_attr(feature = "serde", serde(with = "super::vk_serde::front_face"))]
+    pub front_face: vk::FrontFace,
+    pub blend_mode: BlendMode,
+    // How mesh indices are assembled, e.g. LINE_LIST or POINT_LIST for debug geometry. Procedural
+    // draws always use triangles.
+    #[cfg_attr(feature = "serde", serde(with = "super::vk_serde::primitive_topology"))]
+    pub topology: vk::PrimitiveTopology,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+            reverse_z: false,
+            depth_bias: None,
+            depth_bounds: None,
+            line_mode: None,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            blend_mode: BlendMode::default(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+        }
+    }
+}
+
+impl PipelineConfig {
+    pub fn effective_depth_compare_op(&self) -> vk::CompareOp {
+        if !self.reverse_z {
+            return self.depth_compare_op;
+        }
+        match self.depth_compare_op {
+            vk::CompareOp::LESS => vk::CompareOp::GREATER,
+            vk::CompareOp::LESS_OR_EQUAL => vk::CompareOp::GREATER_OR_EQUAL,
+            vk::CompareOp::GREATER => vk::CompareOp::LESS,
+            vk::CompareOp::GREATER_OR_EQUAL => vk::CompareOp::LESS_OR_EQUAL,
+            // symmetric ops compare the same either way
+            depth_compare_op => depth_compare_op,
+        }
+    }
+    // Lines and points have no facing, so nothing is culled for them
+    pub fn effective_cull_mode(&self) -> vk::CullModeFlags {
+        match self.topology {
+            vk::PrimitiveTopology::POINT_LIST
+            | vk::PrimitiveTopology::LINE_LIST
+            | vk::PrimitiveTopology::LINE_STRIP
+            | vk::PrimitiveTopology::LINE_LIST_WITH_ADJACENCY
+            | vk::PrimitiveTopology::LINE_STRIP_WITH_ADJACENCY => vk::CullModeFlags::NONE,
+            _ => self.cull_mode,
+        }
+    }
+    pub fn depth_clear_value(&self) -> f32 {
+        if self.reverse_z {
+            0.0
+        } else {
+            1.0
+        }
+    }
+    pub fn requires_depth_bias_clamp(&self) -> bool {
+        self.depth_bias
+            .is_some_and(|depth_bias| depth_bias.clamp != 0.0)
+    }
+    pub fn requires_depth_bounds(&self) -> bool {
+        self.depth_bounds.is_some()
+    }
+    // Drops anything the device was not created with support for.
+    pub fn validated(
+        &self,
+        enabled_features: &vk::PhysicalDeviceFeatures,
+        line_rasterization_features: &vk::PhysicalDeviceLineRasterizationFeaturesEXT,
+    ) -> PipelineConfig {
+        let mut config = *self;
+        if let Some(stipple) = config
+            .line_mode
+            .as_mut()
+            .and_then(|line_mode| line_mode.stipple.as_mut())
+        {
+            stipple.factor = stipple.factor.clamp(1, 256);
+        }
+        if let Some(line_mode) = config.line_mode {
+            if !line_mode.is_supported(line_rasterization_features) {
+                eprintln!("{line_mode:?} is not supported on this device, using default lines");
+                config.line_mode = None;
+            }
+        }
+        config.depth_bias = config
+            .depth_bias
+            .map(|depth_bias| depth_bias.validated(enabled_features));
+        if config.depth_bounds.is_some() && enabled_features.depth_bounds == vk::FALSE {
+            eprintln!("depthBounds is not enabled on this device, disabling the depth bounds test");
+            config.depth_bounds = None;
+        }
+        if config.polygon_mode != vk::PolygonMode::FILL
+            && enabled_features.fill_mode_non_solid == vk::FALSE
+        {
+            eprintln!(
+                "fillModeNonSolid is not enabled on this device, using FILL instead of {:?}",
+                config.polygon_mode
+            );
+            config.polygon_mode = vk::PolygonMode::FILL;
+        }
+        config
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    // Standard "over" blending using the fragment alpha. Depth writes stay on, so transparent
+    // geometry has to be drawn back to front after the opaque geometry.
+    AlphaBlend,
+}
+
+// Shared by anything offsetting depth to avoid z-fighting (overlays, decals, shadows).
+// A nonzero clamp requires the depthBiasClamp device feature.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DepthBias {
+    pub constant_factor: f32,
+    pub clamp: f32,
+    pub slope_factor: f32,
+}
+
+impl DepthBias {
+    // Drops the clamp if the device was not created with depthBiasClamp enabled.
+    pub fn validated(&self, enabled_features: &vk::PhysicalDeviceFeatures) -> DepthBias {
+        let mut depth_bias = *self;
+        if depth_bias.clamp != 0.0 && enabled_features.depth_bias_clamp == vk::FALSE {
+            eprintln!(
+                "depthBiasClamp is not enabled on this device, depth bias will not be clamped"
+            );
+            depth_bias.clamp = 0.0;
+        }
+        depth_bias
+    }
+}
+
+// Depth bias is dynamic state of every render pipeline, so it has to be recorded after binding
+// one and can differ per draw, e.g. to pull the grid in front of a floor. None disables it.
+pub fn record_depth_bias(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    depth_bias: Option<DepthBias>,
+) {
+    let DepthBias {
+        constant_factor,
+        clamp,
+        slope_factor,
+    } = depth_bias.unwrap_or_default();
+    unsafe {
+        device.cmd_set_depth_bias_enable(command_buffer, depth_bias.is_some());
+        device.cmd_set_depth_bias(command_buffer, constant_factor, clamp, slope_factor);
+    }
+}
+
+// Fragments are discarded when the depth already stored at their location is outside
+// min..=max. Both bounds must be within 0.0..=1.0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DepthBounds {
+    pub min: f32,
+    pub max: f32,
+}
+
+// Line rasterization from VK_EXT_line_rasterization, only available when the device supports
+// the extension and the feature for the chosen mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineMode {
+    // RECTANGULAR, BRESENHAM or RECTANGULAR_SMOOTH
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "super::vk_serde::line_rasterization_mode")
+    )]
+    pub rasterization_mode: vk::LineRasterizationModeEXT,
+    pub stipple: Option<LineStipple>,
+}
+
+impl LineMode {
+    fn is_supported(
+        &self,
+        line_rasterization_features: &vk::PhysicalDeviceLineRasterizationFeaturesEXT,
+    ) -> bool {
+        let (mode_supported, stipple_supported) = match self.rasterization_mode {
+            vk::LineRasterizationModeEXT::RECTANGULAR => (
+                line_rasterization_features.rectangular_lines,
+                line_rasterization_features.stippled_rectangular_lines,
+            ),
+            vk::LineRasterizationModeEXT::BRESENHAM => (
+                line_rasterization_features.bresenham_lines,
+                line_rasterization_features.stippled_bresenham_lines,
+            ),
+            vk::LineRasterizationModeEXT::RECTANGULAR_SMOOTH => (
+                line_rasterization_features.smooth_lines,
+                line_rasterization_features.stippled_smooth_lines,
+            ),
+            // DEFAULT is expressed as no line mode
+            _ => (vk::FALSE, vk::FALSE),
+        };
+        mode_supported == vk::TRUE && (self.stipple.is_none() || stipple_supported == vk::TRUE)
+    }
+}
+
+// Each bit of the pattern covers factor pixels along the line, starting from the lowest bit.
+// factor must be in 1..=256, PipelineConfig::validated clamps it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineStipple {
+    pub factor: u32,
+    pub pattern: u16,
+}
+
+impl Default for LineStipple {
+    // solid lines
+    fn default() -> Self {
+        Self {
+            factor: 1,
+            pattern: u16::MAX,
+        }
+    }
+}
+
+// Effective state of the graphics pipelines, for debugging.
+#[derive(Clone, Copy, Debug)]
+pub struct PipelineInfo {
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub polygon_mode: vk::PolygonMode,
+    pub topology: vk::PrimitiveTopology,
+    pub line_rasterization_mode: vk::LineRasterizationModeEXT,
+    pub blend_enable: bool,
+    pub depth_test_enable: bool,
+    pub depth_write_enable: bool,
+    pub depth_bounds_test_enable: bool,
+    pub depth_compare_op: vk::CompareOp,
+    pub sample_count: vk::SampleCountFlags,
+    pub color_attachment_format: vk::Format,
+    pub depth_attachment_format: vk::Format,
+}
+
+// Vertex attribute formats come from the field types so they can't drift from the structs.
+trait AttributeFormat {
+    const FORMAT: vk::Format;
+}
+
+impl AttributeFormat for [f32; 2] {
+    const FORMAT: vk::Format = vk::Format::R32G32_SFLOAT;
+}
+
+impl AttributeFormat for [f32; 3] {
+    const FORMAT: vk::Format = vk::Format::R32G32B32_SFLOAT;
+}
+
+impl AttributeFormat for [f32; 4] {
+    const FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+}
+
+// e.g. attribute_format(|vertex: &Vertex| &vertex.position)
+fn attribute_format<S, T: AttributeFormat>(_field: fn(&S) -> &T) -> vk::Format {
+    T::FORMAT
+}
+
+fn instance_input_binding_description(binding: u32) -> vk::VertexInputBindingDescription {
+    vk::VertexInputBindingDescription::default()
+        .binding(binding)
+        .stride(size_of::<InstanceVertex>() as u32)
+        .input_rate(vk::VertexInputRate::INSTANCE)
+}
+
+// matrix attributes take one location per column, 3 through 6 for the model matrix and 7
+// through 9 for the normal matrix
+fn instance_attribute_descriptions(binding: u32) -> [vk::VertexInputAttributeDescription; 7] {
+    let model_column = |column: u32| vk::VertexInputAttributeDescription {
+        location: 3 + column,
+        binding,
+        format: attribute_format(|instance: &InstanceVertex| &instance.model_matrix[0]),
+        offset: offset_of!(InstanceVertex, model_matrix) as u32
+            + column * size_of::<[f32; 4]>() as u32,
+    };
+    let normal_column = |column: u32| vk::VertexInputAttributeDescription {
+        location: 7 + column,
+        binding,
+        format: attribute_format(|instance: &InstanceVertex| &instance.normal_matrix[0]),
+        offset: offset_of!(InstanceVertex, normal_matrix) as u32
+            + column * size_of::<[f32; 3]>() as u32,
+    };
+    [
+        model_column(0),
+        model_column(1),
+        model_column(2),
+        model_column(3),
+        normal_column(0),
+        normal_column(1),
+        normal_column(2),
+    ]
+}
+
+fn vertex_input_binding_descriptions() -> [vk::VertexInputBindingDescription; 2] {
+    [
+        vk::VertexInputBindingDescription::default()
+            .binding(0)
+            .stride(size_of::<Vertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX),
+        instance_input_binding_description(INTERLEAVED_INSTANCE_BINDING),
+    ]
+}
+
+fn vertex_input_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 10] {
+    let [model_0, model_1, model_2, model_3, normal_0, normal_1, normal_2] =
+        instance_attribute_descriptions(INTERLEAVED_INSTANCE_BINDING);
+    [
+        vk::VertexInputAttributeDescription {
+            location: 0,
+            binding: 0,
+            format: attribute_format(|vertex: &Vertex| &vertex.position),
+            offset: offset_of!(Vertex, position) as u32,
+        },
+        vk::VertexInputAttributeDescription {
+            location: 1,
+            binding: 0,
+            format: attribute_format(|vertex: &Vertex| &vertex.color),
+            offset: offset_of!(Vertex, color) as u32,
+        },
+        vk::VertexInputAttributeDescription {
+            location: 2,
+            binding: 0,
+            format: attribute_format(|vertex: &Vertex| &vertex.normal),
+            offset: offset_of!(Vertex, normal) as u32,
+        },
+        model_0,
+        model_1,
+        model_2,
+        model_3,
+        normal_0,
+        normal_1,
+        normal_2,
+    ]
+}
+
+// only position and color, the grid is neither lit nor instanced
+fn grid_vertex_input_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+    let [position, color, ..] = vertex_input_attribute_descriptions();
+    [position, color]
+}
+
+// positions and attributes in separate buffers, shader locations match the interleaved layout
+fn split_vertex_input_binding_descriptions() -> [vk::VertexInputBindingDescription; 3] {
+    [
+        vk::VertexInputBindingDescription::default()
+            .binding(0)
+            .stride(size_of::<[f32; 3]>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX),
+        vk::VertexInputBindingDescription::default()
+            .binding(1)
+            .stride(size_of::<VertexAttribs>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX),
+        instance_input_binding_description(SPLIT_INSTANCE_BINDING),
+    ]
+}
+
+fn split_vertex_input_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 10] {
+    let [model_0, model_1, model_2, model_3, normal_0, normal_1, normal_2] =
+        instance_attribute_descriptions(SPLIT_INSTANCE_BINDING);
+    [
+        vk::VertexInputAttributeDescription {
+            location: 0,
+            binding: 0,
+            format: attribute_format(|vertex: &Vertex| &vertex.position),
+            offset: 0,
+        },
+        vk::VertexInputAttributeDescription {
+            location: 1,
+            binding: 1,
+            format: attribute_format(|attribs: &VertexAttribs| &attribs.color),
+            offset: offset_of!(VertexAttribs, color) as u32,
+        },
+        vk::VertexInputAttributeDescription {
+            location: 2,
+            binding: 1,
+            format: attribute_format(|attribs: &VertexAttribs| &attribs.normal),
+            offset: offset_of!(VertexAttribs, normal) as u32,
+        },
+        model_0,
+        model_1,
+        model_2,
+        model_3,
+        normal_0,
+        normal_1,
+        normal_2,
+    ]
+}
+
+// Shader stages of each pipeline before specialization, the split pipeline shares the
+// interleaved pipeline's stages
+pub struct PipelineShaderStages<'a> {
+    pub interleaved: Vec<vk::PipelineShaderStageCreateInfo<'a>>,
+    pub procedural: Vec<vk::PipelineShaderStageCreateInfo<'a>>,
+    pub skybox: Vec<vk::PipelineShaderStageCreateInfo<'a>>,
+    pub grid: Vec<vk::PipelineShaderStageCreateInfo<'a>>,
+}
+
+// What the pipelines render into
+#[derive(Clone, Copy, Debug)]
+pub struct PipelineAttachments {
+    pub color_format: vk::Format,
+    pub depth_format: vk::Format,
+    pub sample_count: vk::SampleCountFlags,
+}
 
 pub struct GraphicsPipelineComponents {
+    pub config: PipelineConfig,
+    pub info: PipelineInfo,
     pub graphics_pipelines: Vec<vk::Pipeline>,
     pub render_pipeline_layout: vk::PipelineLayout,
     pub render_pipeline_index: usize,
@@ -13,15 +481,76 @@ pub struct GraphicsPipelineComponents {
 impl GraphicsPipelineComponents {
     pub fn new(
         device: &ash::Device,
-        surface_format: &vk::SurfaceFormatKHR,
-        pipeline_shader_stage_infos: &[vk::PipelineShaderStageCreateInfo],
+        pipeline_cache: vk::PipelineCache,
+        shader_stages: &PipelineShaderStages,
+        specialization_constants: &SpecializationConstants,
         descriptor_set_layouts: &[vk::DescriptorSetLayout],
-        scissors: &[vk::Rect2D],
-        viewports: &[vk::Viewport],
+        attachments: &PipelineAttachments,
+        config: &PipelineConfig,
     ) -> GraphicsPipelineComponents {
+        // everything below is built from this so it always reflects the created pipelines
+        let info = PipelineInfo {
+            cull_mode: config.effective_cull_mode(),
+            front_face: config.front_face,
+            polygon_mode: config.polygon_mode,
+            topology: config.topology,
+            line_rasterization_mode: config
+                .line_mode
+                .map_or(vk::LineRasterizationModeEXT::DEFAULT, |line_mode| {
+                    line_mode.rasterization_mode
+                }),
+            blend_enable: config.blend_mode == BlendMode::AlphaBlend,
+            depth_test_enable: true,
+            depth_write_enable: true,
+            depth_bounds_test_enable: config.depth_bounds.is_some(),
+            depth_compare_op: config.effective_depth_compare_op(),
+            sample_count: attachments.sample_count,
+            color_attachment_format: attachments.color_format,
+            depth_attachment_format: attachments.depth_format,
+        };
+
+        // shaders output linear color, encoded here unless the attachment stores it as is
+        let encode_srgb = needs_srgb_encoding(attachments.color_format);
+
+        // the same constants specialize every stage
+        let specialization_constants = specialization_constants
+            .clone()
+            .with_reserved_bool(ENCODE_SRGB_CONSTANT_ID, encode_srgb);
+        let specialization_info = &specialization_constants.info();
+        let pipeline_shader_stage_infos: Vec<_> = shader_stages
+            .interleaved
+            .iter()
+            .map(|stage_info| stage_info.specialization_info(specialization_info))
+            .collect();
+        let procedural_shader_stage_infos: Vec<_> = shader_stages
+            .procedural
+            .iter()
+            .map(|stage_info| stage_info.specialization_info(specialization_info))
+            .collect();
+        // the skybox sits at the cleared depth, behind everything drawn
+        let skybox_specialization_constants = SpecializationConstants::default()
+            .with_reserved_f32(SKYBOX_DEPTH_CONSTANT_ID, config.depth_clear_value())
+            .with_reserved_bool(ENCODE_SRGB_CONSTANT_ID, encode_srgb);
+        let skybox_specialization_info = skybox_specialization_constants.info();
+        let skybox_shader_stage_infos: Vec<_> = shader_stages
+            .skybox
+            .iter()
+            .map(|stage_info| stage_info.specialization_info(&skybox_specialization_info))
+            .collect();
+        let grid_specialization_constants = SpecializationConstants::default()
+            .with_bool(LIGHTING_ENABLED_CONSTANT_ID, false)
+            .with_reserved_bool(ENCODE_SRGB_CONSTANT_ID, encode_srgb);
+        let grid_specialization_info = grid_specialization_constants.info();
+        let grid_shader_stage_infos: Vec<_> = shader_stages
+            .grid
+            .iter()
+            .map(|stage_info| stage_info.specialization_info(&grid_specialization_info))
+            .collect();
+
+        // both are dynamic state, only the counts are fixed
         let viewport_state = vk::PipelineViewportStateCreateInfo::default()
-            .scissors(scissors)
-            .viewports(viewports);
+            .scissor_count(1)
+            .viewport_count(1);
 
         let noop_stencil_state = vk::StencilOpState::default()
             .fail_op(vk::StencilOp::KEEP)
@@ -29,36 +558,61 @@ impl GraphicsPipelineComponents {
             .depth_fail_op(vk::StencilOp::KEEP)
             .compare_op(vk::CompareOp::ALWAYS);
 
+        let depth_bounds = config.depth_bounds;
         let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default()
-            .depth_test_enable(true)
-            .depth_write_enable(true)
-            .depth_bounds_test_enable(true)
+            .depth_test_enable(info.depth_test_enable)
+            .depth_write_enable(info.depth_write_enable)
+            .depth_bounds_test_enable(info.depth_bounds_test_enable)
             .stencil_test_enable(false)
-            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+            .depth_compare_op(info.depth_compare_op)
             .front(noop_stencil_state)
             .back(noop_stencil_state)
-            .max_depth_bounds(100.0)
-            .min_depth_bounds(0.0);
+            .min_depth_bounds(depth_bounds.map_or(0.0, |depth_bounds| depth_bounds.min))
+            .max_depth_bounds(depth_bounds.map_or(1.0, |depth_bounds| depth_bounds.max));
+
+        // passes where the depth is still the clear value and leaves it there
+        let skybox_depth_compare_op = PipelineConfig {
+            depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+            ..*config
+        }
+        .effective_depth_compare_op();
+        let skybox_depth_stencil_state = depth_stencil_state
+            .depth_write_enable(false)
+            .depth_bounds_test_enable(false)
+            .depth_compare_op(skybox_depth_compare_op);
+
+        // the grid is occluded by and occludes geometry, but has no depth bounds
+        let grid_depth_stencil_state = depth_stencil_state.depth_bounds_test_enable(false);
 
-        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_states = [
+            vk::DynamicState::VIEWPORT,
+            vk::DynamicState::SCISSOR,
+            vk::DynamicState::DEPTH_BIAS_ENABLE,
+            vk::DynamicState::DEPTH_BIAS,
+        ];
         let dynamic_state_info =
             vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
 
+        // factors are ignored while blending is disabled
         let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState::default()
-            .blend_enable(false)
-            .src_color_blend_factor(vk::BlendFactor::SRC_COLOR)
-            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_DST_COLOR)
+            .blend_enable(info.blend_enable)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
             .color_blend_op(vk::BlendOp::ADD)
-            .src_alpha_blend_factor(vk::BlendFactor::ZERO)
-            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
             .alpha_blend_op(vk::BlendOp::ADD)
             .color_write_mask(vk::ColorComponentFlags::RGBA)];
         let color_blend_state = vk::PipelineColorBlendStateCreateInfo::default()
             .logic_op(vk::LogicOp::CLEAR)
             .attachments(&color_blend_attachment_states);
 
-        let render_layout_create_info =
-            vk::PipelineLayoutCreateInfo::default().set_layouts(descriptor_set_layouts);
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .size(size_of::<FragmentPushConstants>() as u32)];
+        let render_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(descriptor_set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
 
         let render_pipeline_layout = unsafe {
             device
@@ -66,50 +620,73 @@ impl GraphicsPipelineComponents {
                 .expect("Failed to create pipeline layout")
         };
 
-        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
-            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-            .cull_mode(vk::CullModeFlags::BACK)
+        let line_stipple = config.line_mode.and_then(|line_mode| line_mode.stipple);
+        let mut line_state = vk::PipelineRasterizationLineStateCreateInfoEXT::default()
+            .line_rasterization_mode(info.line_rasterization_mode)
+            .stippled_line_enable(line_stipple.is_some())
+            .line_stipple_factor(line_stipple.unwrap_or_default().factor)
+            .line_stipple_pattern(line_stipple.unwrap_or_default().pattern);
+
+        // depth bias is dynamic, config.depth_bias is recorded for the mesh draws
+        let mut rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
+            .front_face(info.front_face)
+            .cull_mode(info.cull_mode)
+            .line_width(1.0)
+            .polygon_mode(info.polygon_mode);
+        // chaining the line state at all requires the extension to be enabled
+        if config.line_mode.is_some() {
+            rasterization_state = rasterization_state.push_next(&mut line_state);
+        }
+
+        // the fullscreen triangle and the grid lines are never culled or wireframe
+        let skybox_rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
+            .cull_mode(vk::CullModeFlags::NONE)
             .line_width(1.0)
             .polygon_mode(vk::PolygonMode::FILL);
 
         let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+            .rasterization_samples(info.sample_count);
 
-        let vertex_input_binding_descriptions = [vk::VertexInputBindingDescription::default()
-            .binding(0)
-            .stride(size_of::<Vertex>() as u32)
-            .input_rate(vk::VertexInputRate::VERTEX)];
-
-        let vertex_input_attribute_descriptions = [
-            vk::VertexInputAttributeDescription {
-                location: 0,
-                binding: 0,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: offset_of!(Vertex, position) as u32,
-            },
-            vk::VertexInputAttributeDescription {
-                location: 1,
-                binding: 0,
-                format: vk::Format::R32G32B32A32_SFLOAT,
-                offset: offset_of!(Vertex, color) as u32,
-            },
-        ];
+        let vertex_input_binding_descriptions = vertex_input_binding_descriptions();
+        let vertex_input_attribute_descriptions = vertex_input_attribute_descriptions();
 
         let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default()
             .vertex_attribute_descriptions(&vertex_input_attribute_descriptions)
             .vertex_binding_descriptions(&vertex_input_binding_descriptions);
 
-        let vertex_input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::default()
+        let split_vertex_input_binding_descriptions = split_vertex_input_binding_descriptions();
+        let split_vertex_input_attribute_descriptions = split_vertex_input_attribute_descriptions();
+
+        let split_vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_attribute_descriptions(&split_vertex_input_attribute_descriptions)
+            .vertex_binding_descriptions(&split_vertex_input_binding_descriptions);
+
+        let grid_vertex_input_binding_descriptions = [vertex_input_binding_descriptions[0]];
+        let grid_vertex_input_attribute_descriptions = grid_vertex_input_attribute_descriptions();
+
+        let grid_vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_attribute_descriptions(&grid_vertex_input_attribute_descriptions)
+            .vertex_binding_descriptions(&grid_vertex_input_binding_descriptions);
+
+        // no vertex buffers, the vertex shader derives everything from gl_VertexIndex
+        let procedural_vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default();
+
+        let vertex_input_assembly_state =
+            vk::PipelineInputAssemblyStateCreateInfo::default().topology(info.topology);
+        // the fullscreen triangle and skybox need triangles whatever the meshes use
+        let procedural_input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::default()
             .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        let grid_input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(vk::PrimitiveTopology::LINE_LIST);
 
-        let color_attachment_formats = &[surface_format.format];
+        let color_attachment_formats = &[info.color_attachment_format];
         let mut pipeline_rendering_create_info = vk::PipelineRenderingCreateInfo::default()
             .color_attachment_formats(color_attachment_formats)
-            .depth_attachment_format(DEPTH_IMAGE_FORMAT);
+            .depth_attachment_format(info.depth_attachment_format);
 
         let graphics_pipeline_create_info = vk::GraphicsPipelineCreateInfo::default()
             .push_next(&mut pipeline_rendering_create_info)
-            .stages(pipeline_shader_stage_infos)
+            .stages(&pipeline_shader_stage_infos)
             .dynamic_state(&dynamic_state_info)
             .multisample_state(&multisample_state)
             .color_blend_state(&color_blend_state)
@@ -120,25 +697,60 @@ impl GraphicsPipelineComponents {
             .vertex_input_state(&vertex_input_state)
             .depth_stencil_state(&depth_stencil_state);
 
+        let split_graphics_pipeline_create_info =
+            graphics_pipeline_create_info.vertex_input_state(&split_vertex_input_state);
+
+        let procedural_graphics_pipeline_create_info = graphics_pipeline_create_info
+            .stages(&procedural_shader_stage_infos)
+            .vertex_input_state(&procedural_vertex_input_state)
+            .input_assembly_state(&procedural_input_assembly_state);
+
+        let skybox_color_blend_attachment_states =
+            [vk::PipelineColorBlendAttachmentState::default()
+                .color_write_mask(vk::ColorComponentFlags::RGBA)];
+        let skybox_color_blend_state = vk::PipelineColorBlendStateCreateInfo::default()
+            .attachments(&skybox_color_blend_attachment_states);
+        let skybox_graphics_pipeline_create_info = procedural_graphics_pipeline_create_info
+            .stages(&skybox_shader_stage_infos)
+            .rasterization_state(&skybox_rasterization_state)
+            .color_blend_state(&skybox_color_blend_state)
+            .depth_stencil_state(&skybox_depth_stencil_state);
+
+        let grid_graphics_pipeline_create_info = graphics_pipeline_create_info
+            .stages(&grid_shader_stage_infos)
+            .vertex_input_state(&grid_vertex_input_state)
+            .input_assembly_state(&grid_input_assembly_state)
+            .rasterization_state(&skybox_rasterization_state)
+            .color_blend_state(&skybox_color_blend_state)
+            .depth_stencil_state(&grid_depth_stencil_state);
+
         let graphics_pipelines = unsafe {
             device
                 .create_graphics_pipelines(
-                    vk::PipelineCache::null(),
-                    &[graphics_pipeline_create_info],
+                    pipeline_cache,
+                    &[
+                        graphics_pipeline_create_info,
+                        split_graphics_pipeline_create_info,
+                        procedural_graphics_pipeline_create_info,
+                        skybox_graphics_pipeline_create_info,
+                        grid_graphics_pipeline_create_info,
+                    ],
                     None,
                 )
                 .expect("Failed to create graphics pipelines")
         };
 
         GraphicsPipelineComponents {
+            config: *config,
+            info,
             graphics_pipelines,
             render_pipeline_layout,
-            render_pipeline_index: 0,
+            render_pipeline_index: INTERLEAVED_PIPELINE_INDEX,
         }
     }
+    // No submitted frame may still use the pipelines, callers wait for the device to be idle
     pub fn cleanup(&self, device: &ash::Device) {
         unsafe {
-            device.device_wait_idle().unwrap();
             for &pipeline in self.graphics_pipelines.iter() {
                 device.destroy_pipeline(pipeline, None);
             }
@@ -146,3 +758,168 @@ impl GraphicsPipelineComponents {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_size(format: vk::Format) -> u32 {
+        match format {
+            vk::Format::R32G32_SFLOAT => 8,
+            vk::Format::R32G32B32_SFLOAT => 12,
+            vk::Format::R32G32B32A32_SFLOAT => 16,
+            _ => panic!("Unexpected vertex attribute format {format:?}"),
+        }
+    }
+
+    fn assert_attributes_fit(
+        bindings: &[vk::VertexInputBindingDescription],
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) {
+        for attribute in attributes {
+            let binding = bindings
+                .iter()
+                .find(|binding| binding.binding == attribute.binding)
+                .expect("Attribute references a missing binding");
+            assert!(
+                attribute.offset + format_size(attribute.format) <= binding.stride,
+                "Attribute at location {} overruns its binding",
+                attribute.location
+            );
+        }
+    }
+
+    #[test]
+    fn reverse_z_flips_depth_comparisons() {
+        let config = PipelineConfig {
+            reverse_z: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.effective_depth_compare_op(),
+            vk::CompareOp::GREATER_OR_EQUAL
+        );
+        assert_eq!(config.depth_clear_value(), 0.0);
+        let config = PipelineConfig {
+            depth_compare_op: vk::CompareOp::EQUAL,
+            ..config
+        };
+        assert_eq!(config.effective_depth_compare_op(), vk::CompareOp::EQUAL);
+    }
+
+    #[test]
+    fn lines_and_points_are_never_culled() {
+        let mut config = PipelineConfig::default();
+        assert_eq!(config.effective_cull_mode(), vk::CullModeFlags::BACK);
+        config.topology = vk::PrimitiveTopology::LINE_LIST;
+        assert_eq!(config.effective_cull_mode(), vk::CullModeFlags::NONE);
+        config.topology = vk::PrimitiveTopology::POINT_LIST;
+        assert_eq!(config.effective_cull_mode(), vk::CullModeFlags::NONE);
+    }
+
+    #[test]
+    fn depth_bounds_are_dropped_without_the_feature() {
+        let config = PipelineConfig {
+            depth_bounds: Some(DepthBounds { min: 0.0, max: 0.5 }),
+            ..Default::default()
+        };
+        let line_rasterization_features = vk::PhysicalDeviceLineRasterizationFeaturesEXT::default();
+        let validated = config.validated(
+            &vk::PhysicalDeviceFeatures::default(),
+            &line_rasterization_features,
+        );
+        assert_eq!(validated.depth_bounds, None);
+        let validated = config.validated(
+            &vk::PhysicalDeviceFeatures::default().depth_bounds(true),
+            &line_rasterization_features,
+        );
+        assert_eq!(validated.depth_bounds, config.depth_bounds);
+    }
+
+    #[test]
+    fn line_stipple_factor_is_clamped() {
+        let features = vk::PhysicalDeviceLineRasterizationFeaturesEXT::default()
+            .rectangular_lines(true)
+            .stippled_rectangular_lines(true);
+        let stippled = |factor| PipelineConfig {
+            line_mode: Some(LineMode {
+                rasterization_mode: vk::LineRasterizationModeEXT::RECTANGULAR,
+                stipple: Some(LineStipple {
+                    factor,
+                    ..Default::default()
+                }),
+            }),
+            ..Default::default()
+        };
+        let validated_factor = |factor| {
+            stippled(factor)
+                .validated(&vk::PhysicalDeviceFeatures::default(), &features)
+                .line_mode
+                .and_then(|line_mode| line_mode.stipple)
+                .map(|stipple| stipple.factor)
+        };
+        assert_eq!(LineStipple::default().factor, 1);
+        assert_eq!(validated_factor(0), Some(1));
+        assert_eq!(validated_factor(4), Some(4));
+        assert_eq!(validated_factor(1000), Some(256));
+    }
+
+    #[test]
+    fn depth_bias_clamp_is_dropped_without_the_feature() {
+        let depth_bias = DepthBias {
+            constant_factor: -1.0,
+            clamp: 0.01,
+            slope_factor: -1.0,
+        };
+        let validated = depth_bias.validated(&vk::PhysicalDeviceFeatures::default());
+        assert_eq!(
+            validated,
+            DepthBias {
+                clamp: 0.0,
+                ..depth_bias
+            }
+        );
+        let validated =
+            depth_bias.validated(&vk::PhysicalDeviceFeatures::default().depth_bias_clamp(true));
+        assert_eq!(validated, depth_bias);
+    }
+
+    #[test]
+    fn interleaved_layout_matches_vertex() {
+        let bindings = vertex_input_binding_descriptions();
+        assert_eq!(bindings[0].stride as usize, size_of::<Vertex>());
+        let attributes = vertex_input_attribute_descriptions();
+        assert_eq!(attributes[0].format, vk::Format::R32G32B32_SFLOAT);
+        assert_attributes_fit(&bindings, &attributes);
+    }
+
+    #[test]
+    fn split_layout_matches_vertex_attribs() {
+        let bindings = split_vertex_input_binding_descriptions();
+        assert_eq!(bindings[1].stride as usize, size_of::<VertexAttribs>());
+        let attributes = split_vertex_input_attribute_descriptions();
+        assert_eq!(
+            attributes.map(|attribute| attribute.format),
+            vertex_input_attribute_descriptions().map(|attribute| attribute.format)
+        );
+        assert_attributes_fit(&bindings, &attributes);
+    }
+
+    #[test]
+    fn instance_attributes_cover_the_instance_vertex() {
+        let bindings = vertex_input_binding_descriptions();
+        let instance_binding = bindings[INTERLEAVED_INSTANCE_BINDING as usize];
+        assert_eq!(instance_binding.input_rate, vk::VertexInputRate::INSTANCE);
+        assert_eq!(
+            instance_binding.stride as usize,
+            size_of::<InstanceVertex>()
+        );
+        let instance_attributes = instance_attribute_descriptions(INTERLEAVED_INSTANCE_BINDING);
+        let covered: u32 = instance_attributes
+            .iter()
+            .map(|attribute| format_size(attribute.format))
+            .sum();
+        assert_eq!(covered, instance_binding.stride);
+        assert_attributes_fit(&bindings, &instance_attributes);
+    }
+}
@@ -0,0 +1,96 @@
+use super::{
+    command_buffer_components::UploadCommands,
+    memory_allocator::MemoryAllocator,
+    vertex_buffer_components::{Vertex, VertexBufferComponents},
+    DeviceLost,
+};
+
+// Lines from -GRID_HALF_EXTENT to GRID_HALF_EXTENT, GRID_SPACING apart
+const GRID_HALF_EXTENT: i32 = 10;
+const GRID_SPACING: f32 = 1.0;
+const GRID_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+const X_AXIS_COLOR: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+const Y_AXIS_COLOR: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+const Z_AXIS_COLOR: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+// Line list for the ground grid in the xz plane and the three axes through the origin. The grid
+// lines through the origin are left out, the axes take their place.
+pub fn grid_vertices() -> Vec<Vertex> {
+    let extent = GRID_HALF_EXTENT as f32 * GRID_SPACING;
+    let line = |from: [f32; 3], to: [f32; 3], color: [f32; 4]| {
+        [from, to].map(|position| Vertex {
+            position,
+            color,
+            normal: [0.0; 3],
+        })
+    };
+    let mut vertices = Vec::new();
+    for i in (-GRID_HALF_EXTENT..=GRID_HALF_EXTENT).filter(|&i| i != 0) {
+        let offset = i as f32 * GRID_SPACING;
+        vertices.extend(line(
+            [offset, 0.0, -extent],
+            [offset, 0.0, extent],
+            GRID_COLOR,
+        ));
+        vertices.extend(line(
+            [-extent, 0.0, offset],
+            [extent, 0.0, offset],
+            GRID_COLOR,
+        ));
+    }
+    vertices.extend(line([-extent, 0.0, 0.0], [extent, 0.0, 0.0], X_AXIS_COLOR));
+    vertices.extend(line([0.0, -extent, 0.0], [0.0, extent, 0.0], Y_AXIS_COLOR));
+    vertices.extend(line([0.0, 0.0, -extent], [0.0, 0.0, extent], Z_AXIS_COLOR));
+    vertices
+}
+
+// Drawn with the grid pipeline, which has no instance or object transforms
+pub struct GridComponents {
+    pub vertex_buffer_components: VertexBufferComponents,
+    pub vertex_count: u32,
+}
+
+impl GridComponents {
+    pub fn new(
+        device: &ash::Device,
+        memory_allocator: &mut MemoryAllocator,
+        upload_commands: &UploadCommands,
+    ) -> Result<GridComponents, DeviceLost> {
+        let vertices = grid_vertices();
+        let mut vertex_buffer_components =
+            VertexBufferComponents::new_unintialized(device, memory_allocator, vertices.len());
+        if let Err(device_lost) =
+            vertex_buffer_components.update_vertices(device, &vertices, upload_commands)
+        {
+            vertex_buffer_components.cleanup(device, memory_allocator);
+            return Err(device_lost);
+        }
+        Ok(GridComponents {
+            vertex_buffer_components,
+            vertex_count: vertices.len() as u32,
+        })
+    }
+    pub fn cleanup(&self, device: &ash::Device, memory_allocator: &mut MemoryAllocator) {
+        self.vertex_buffer_components
+            .cleanup(device, memory_allocator);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_is_a_line_list_with_colored_axes() {
+        let vertices = grid_vertices();
+        assert_eq!(vertices.len() % 2, 0);
+        // two lines per offset except 0, plus the three axes
+        assert_eq!(vertices.len(), (4 * GRID_HALF_EXTENT as usize + 3) * 2);
+        let axes = &vertices[vertices.len() - 6..];
+        assert!(axes[..2].iter().all(|vertex| vertex.color == X_AXIS_COLOR));
+        assert!(axes[2..4].iter().all(|vertex| vertex.position[1] != 0.0));
+        assert!(vertices[..vertices.len() - 6]
+            .iter()
+            .all(|vertex| vertex.position[1] == 0.0 && vertex.color == GRID_COLOR));
+    }
+}
@@ -1,62 +1,107 @@
 use ash::vk;
 
-use super::buffer::Buffer;
+use super::{
+    buffer::Buffer, command_buffer_components::UploadCommands, memory_allocator::MemoryAllocator,
+    DeviceLost,
+};
 
 pub type Index = u32;
 pub const INDICES: [Index; 6] = [0, 1, 2, 3, 4, 5];
 
+// UINT16 when every index of a mesh with vertex_count vertices fits, UINT32 otherwise.
+// 0xFFFF is left unused since it is the primitive restart index for UINT16.
+pub fn smallest_index_type(vertex_count: usize) -> vk::IndexType {
+    match vertex_count <= u16::MAX as usize {
+        true => vk::IndexType::UINT16,
+        false => vk::IndexType::UINT32,
+    }
+}
+
+fn index_size(index_type: vk::IndexType) -> usize {
+    match index_type {
+        vk::IndexType::UINT16 => size_of::<u16>(),
+        vk::IndexType::UINT32 => size_of::<u32>(),
+        _ => panic!("Unsupported index type {index_type:?}"),
+    }
+}
+
+// Indices are always passed in as Index and narrowed on upload when index_type is UINT16,
+// so the buffers are sized in bytes rather than indices.
 pub struct IndexBufferComponents {
-    pub index_buffer: Buffer<Index>,
-    pub index_staging_buffer: Buffer<Index>,
+    pub index_buffer: Buffer<u8>,
+    pub index_staging_buffer: Buffer<u8>,
+    pub index_type: vk::IndexType,
+    // indices written by the last update_indices, what draws should use rather than the capacity
+    pub index_count: u32,
 }
 
 impl IndexBufferComponents {
     pub fn new_unintiailized(
         device: &ash::Device,
-        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        memory_allocator: &mut MemoryAllocator,
+        index_count: usize,
+        index_type: vk::IndexType,
     ) -> IndexBufferComponents {
-        let index_buffer = Buffer::<Index>::new(
+        let buffer_len = index_count * index_size(index_type);
+        let index_buffer = Buffer::<u8>::new(
             device,
-            physical_device_memory_properties,
+            memory_allocator,
             vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
             vk::SharingMode::EXCLUSIVE,
             vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            INDICES.len(),
-            false,
+            buffer_len,
         );
-        let index_staging_buffer = Buffer::<Index>::new(
+        let index_staging_buffer = Buffer::<u8>::new(
             device,
-            physical_device_memory_properties,
+            memory_allocator,
             vk::BufferUsageFlags::TRANSFER_SRC,
             vk::SharingMode::EXCLUSIVE,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            INDICES.len(),
-            false,
+            buffer_len,
         );
         IndexBufferComponents {
             index_buffer,
             index_staging_buffer,
+            index_type,
+            index_count: 0,
         }
     }
+    // Number of indices the buffers hold
+    pub fn capacity(&self) -> usize {
+        self.index_buffer.capacity() / index_size(self.index_type)
+    }
     pub fn update_indices(
         &mut self,
         device: &ash::Device,
         indices: &[Index],
-        command_buffer: vk::CommandBuffer,
-        command_buffer_reuse_fence: vk::Fence,
-        queue: vk::Queue,
-    ) {
-        self.index_staging_buffer.write_data_direct(device, indices);
+        upload_commands: &UploadCommands,
+    ) -> Result<(), DeviceLost> {
+        let index_bytes: Vec<u8> = match self.index_type {
+            vk::IndexType::UINT16 => indices
+                .iter()
+                .flat_map(|&index| {
+                    u16::try_from(index)
+                        .expect("Index does not fit in a UINT16 index buffer")
+                        .to_ne_bytes()
+                })
+                .collect(),
+            _ => indices
+                .iter()
+                .flat_map(|index| index.to_ne_bytes())
+                .collect(),
+        };
+        self.index_staging_buffer
+            .write_data_direct(device, &index_bytes);
         self.index_buffer.write_from_staging(
             &self.index_staging_buffer,
             device,
-            command_buffer,
-            command_buffer_reuse_fence,
-            queue,
-        );
+            upload_commands,
+        )?;
+        self.index_count = indices.len() as u32;
+        Ok(())
     }
-    pub fn cleanup(&self, device: &ash::Device) {
-        self.index_buffer.cleanup(device);
-        self.index_staging_buffer.cleanup(device);
+    pub fn cleanup(&self, device: &ash::Device, memory_allocator: &mut MemoryAllocator) {
+        self.index_buffer.cleanup(device, memory_allocator);
+        self.index_staging_buffer.cleanup(device, memory_allocator);
     }
 }
@@ -0,0 +1,312 @@
+use std::ffi::c_void;
+
+use ash::vk;
+
+use super::find_memorytype_index;
+
+// Size of the device memory blocks resources are sub-allocated from, resources that don't fit
+// get a block of their own size
+const BLOCK_SIZE: vk::DeviceSize = 32 * 1024 * 1024;
+
+// Hands out ranges of a few large device memory blocks instead of allocating memory per
+// resource, which keeps well below maxMemoryAllocationCount. Host visible blocks stay mapped
+// for their whole lifetime, since memory can only be mapped once at a time.
+pub struct MemoryAllocator {
+    physical_device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+    // linear buffers and optimally tiled images in one block have to be this far apart
+    buffer_image_granularity: vk::DeviceSize,
+    non_coherent_atom_size: vk::DeviceSize,
+    blocks: Vec<MemoryBlock>,
+}
+
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    memory_type_index: u32,
+    // null unless the memory type is host visible
+    mapped_ptr: *mut c_void,
+    host_coherent: bool,
+    free_ranges: FreeRanges,
+}
+
+#[derive(Clone, Copy)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    // points at offset, null unless the memory is host visible
+    pub mapped_ptr: *mut c_void,
+    pub host_coherent: bool,
+    // flushed and invalidated ranges of non coherent memory are aligned to it
+    pub non_coherent_atom_size: vk::DeviceSize,
+    block_index: usize,
+}
+
+impl Allocation {
+    // Not backed by memory, for tests of resources that never touch the device
+    #[cfg(test)]
+    pub fn unbound() -> Allocation {
+        Allocation {
+            memory: vk::DeviceMemory::null(),
+            offset: 0,
+            size: 0,
+            mapped_ptr: std::ptr::null_mut(),
+            host_coherent: true,
+            non_coherent_atom_size: 1,
+            block_index: 0,
+        }
+    }
+}
+
+impl MemoryAllocator {
+    pub fn new(
+        physical_device_memory_properties: vk::PhysicalDeviceMemoryProperties,
+        limits: &vk::PhysicalDeviceLimits,
+    ) -> MemoryAllocator {
+        MemoryAllocator {
+            physical_device_memory_properties,
+            buffer_image_granularity: limits.buffer_image_granularity,
+            non_coherent_atom_size: limits.non_coherent_atom_size,
+            blocks: Vec::new(),
+        }
+    }
+    pub fn allocate(
+        &mut self,
+        device: &ash::Device,
+        memory_requirements: &vk::MemoryRequirements,
+        memory_properties: vk::MemoryPropertyFlags,
+    ) -> Allocation {
+        let memory_type_index = find_memorytype_index(
+            memory_requirements,
+            &self.physical_device_memory_properties,
+            memory_properties,
+        )
+        .expect("Failed to find suitable memory type");
+
+        // padding every allocation to the granularity keeps buffers and images off shared pages
+        let alignment = memory_requirements
+            .alignment
+            .max(self.buffer_image_granularity);
+        let size = memory_requirements
+            .size
+            .next_multiple_of(self.buffer_image_granularity);
+
+        let existing = self
+            .blocks
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, block)| block.memory_type_index == memory_type_index)
+            .find_map(|(block_index, block)| {
+                block
+                    .free_ranges
+                    .allocate(size, alignment)
+                    .map(|offset| (block_index, offset))
+            });
+        let (block_index, offset) = match existing {
+            Some(existing) => existing,
+            None => {
+                let mut block =
+                    self.allocate_block(device, memory_type_index, size.max(BLOCK_SIZE));
+                let offset = block
+                    .free_ranges
+                    .allocate(size, alignment)
+                    .expect("New memory block is too small");
+                self.blocks.push(block);
+                (self.blocks.len() - 1, offset)
+            }
+        };
+
+        let block = &self.blocks[block_index];
+        let mapped_ptr = match block.mapped_ptr.is_null() {
+            true => std::ptr::null_mut(),
+            false => unsafe { block.mapped_ptr.byte_add(offset as usize) },
+        };
+        Allocation {
+            memory: block.memory,
+            offset,
+            size,
+            mapped_ptr,
+            host_coherent: block.host_coherent,
+            non_coherent_atom_size: self.non_coherent_atom_size,
+            block_index,
+        }
+    }
+    // The first candidate a memory type allowed by memory_requirements has all the flags of, e.g.
+    // to prefer device local host visible (resizable BAR) memory and fall back to plain host
+    // visible memory.
+    pub fn preferred_memory_properties(
+        &self,
+        memory_requirements: &vk::MemoryRequirements,
+        candidates: &[vk::MemoryPropertyFlags],
+    ) -> Option<vk::MemoryPropertyFlags> {
+        candidates.iter().copied().find(|&memory_properties| {
+            find_memorytype_index(
+                memory_requirements,
+                &self.physical_device_memory_properties,
+                memory_properties,
+            )
+            .is_some()
+        })
+    }
+    // The range becomes available again, blocks are only freed by cleanup
+    pub fn free(&mut self, allocation: &Allocation) {
+        self.blocks[allocation.block_index]
+            .free_ranges
+            .free(allocation.offset, allocation.size);
+    }
+    fn allocate_block(
+        &self,
+        device: &ash::Device,
+        memory_type_index: u32,
+        size: vk::DeviceSize,
+    ) -> MemoryBlock {
+        let property_flags = self.physical_device_memory_properties.memory_types
+            [memory_type_index as usize]
+            .property_flags;
+        let allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(size)
+            .memory_type_index(memory_type_index);
+        let memory = unsafe {
+            device
+                .allocate_memory(&allocate_info, None)
+                .expect("Failed to allocate memory block")
+        };
+        let mapped_ptr = match property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            true => unsafe {
+                device
+                    .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                    .expect("Failed to map memory block")
+            },
+            false => std::ptr::null_mut(),
+        };
+        MemoryBlock {
+            memory,
+            memory_type_index,
+            mapped_ptr,
+            host_coherent: property_flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT),
+            free_ranges: FreeRanges::new(size),
+        }
+    }
+    // Frees every block, all resources using them must be destroyed first
+    pub fn cleanup(&mut self, device: &ash::Device) {
+        for block in self.blocks.drain(..) {
+            unsafe { device.free_memory(block.memory, None) };
+        }
+    }
+}
+
+// Free byte ranges of a block as (offset, size), sorted by offset with no two adjacent
+struct FreeRanges {
+    ranges: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+}
+
+impl FreeRanges {
+    fn new(size: vk::DeviceSize) -> FreeRanges {
+        FreeRanges {
+            ranges: vec![(0, size)],
+        }
+    }
+    // First fit, returns the aligned offset
+    fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<vk::DeviceSize> {
+        let (i, aligned_offset) =
+            self.ranges
+                .iter()
+                .enumerate()
+                .find_map(|(i, &(range_offset, range_size))| {
+                    let aligned_offset = range_offset.next_multiple_of(alignment);
+                    (aligned_offset + size <= range_offset + range_size)
+                        .then_some((i, aligned_offset))
+                })?;
+        let (range_offset, range_size) = self.ranges.remove(i);
+        let range_end = range_offset + range_size;
+        let allocation_end = aligned_offset + size;
+        if allocation_end < range_end {
+            self.ranges
+                .insert(i, (allocation_end, range_end - allocation_end));
+        }
+        if range_offset < aligned_offset {
+            self.ranges
+                .insert(i, (range_offset, aligned_offset - range_offset));
+        }
+        Some(aligned_offset)
+    }
+    fn free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        let i = self
+            .ranges
+            .partition_point(|&(range_offset, _)| range_offset < offset);
+        self.ranges.insert(i, (offset, size));
+        // merge with the following range first so i stays valid
+        if let Some(&(next_offset, next_size)) = self.ranges.get(i + 1) {
+            if offset + size == next_offset {
+                self.ranges[i].1 += next_size;
+                self.ranges.remove(i + 1);
+            }
+        }
+        if i > 0 {
+            let (previous_offset, previous_size) = self.ranges[i - 1];
+            if previous_offset + previous_size == offset {
+                self.ranges[i - 1].1 += self.ranges[i].1;
+                self.ranges.remove(i);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocations_are_aligned_and_reuse_freed_ranges() {
+        let mut free_ranges = FreeRanges::new(1024);
+        assert_eq!(free_ranges.allocate(100, 1), Some(0));
+        assert_eq!(free_ranges.allocate(100, 256), Some(256));
+        assert_eq!(free_ranges.ranges, vec![(100, 156), (356, 668)]);
+        assert_eq!(free_ranges.allocate(1024, 1), None);
+
+        free_ranges.free(0, 100);
+        assert_eq!(free_ranges.allocate(200, 1), Some(0));
+        free_ranges.free(0, 200);
+        free_ranges.free(256, 100);
+        assert_eq!(free_ranges.ranges, vec![(0, 1024)]);
+    }
+
+    #[test]
+    fn preferred_memory_properties_fall_back_in_order() {
+        let device_local = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+        let host = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        let mut memory_properties = vk::PhysicalDeviceMemoryProperties {
+            memory_type_count: 2,
+            ..Default::default()
+        };
+        memory_properties.memory_types[0].property_flags = device_local;
+        memory_properties.memory_types[1].property_flags = host;
+        let candidates = [device_local | host, host];
+        let any_memory_type = vk::MemoryRequirements::default().memory_type_bits(0b11);
+        let memory_allocator =
+            MemoryAllocator::new(memory_properties, &vk::PhysicalDeviceLimits::default());
+        assert_eq!(
+            memory_allocator.preferred_memory_properties(&any_memory_type, &candidates),
+            Some(host)
+        );
+
+        memory_properties.memory_types[1].property_flags = device_local | host;
+        memory_properties.memory_types[2].property_flags = host;
+        memory_properties.memory_type_count = 3;
+        let memory_allocator =
+            MemoryAllocator::new(memory_properties, &vk::PhysicalDeviceLimits::default());
+        assert_eq!(
+            memory_allocator.preferred_memory_properties(&any_memory_type, &candidates),
+            Some(device_local | host)
+        );
+        // the resource can't use the device local host visible type
+        let not_device_local = vk::MemoryRequirements::default().memory_type_bits(0b101);
+        assert_eq!(
+            memory_allocator.preferred_memory_properties(&not_device_local, &candidates),
+            Some(host)
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use nalgebra::Point3;
+
+use super::{
+    command_buffer_components::UploadCommands,
+    graphics_pipeline_components::DepthBias,
+    index_buffer_components::{smallest_index_type, Index, IndexBufferComponents},
+    memory_allocator::MemoryAllocator,
+    vertex_buffer_components::{Vertex, VertexBufferComponents},
+    DeviceLost,
+};
+
+// Handed out by Renderer::add_mesh, never reused after the mesh is removed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MeshId(pub(super) u64);
+
+// The mesh the renderer starts with, the loaded model or the built in triangles
+pub const DEFAULT_MESH_ID: MeshId = MeshId(0);
+
+// Axis aligned bounding box in the mesh's own space, before any model matrix or instance
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    // None without any vertices
+    pub fn from_vertices(vertices: &[Vertex]) -> Option<Aabb> {
+        let first = Point3::from(vertices.first()?.position);
+        Some(vertices.iter().fold(
+            Aabb {
+                min: first,
+                max: first,
+            },
+            |aabb, vertex| {
+                let position = Point3::from(vertex.position);
+                Aabb {
+                    min: aabb.min.inf(&position),
+                    max: aabb.max.sup(&position),
+                }
+            },
+        ))
+    }
+}
+
+// Interleaved vertices with their own index buffer, drawn with the interleaved pipeline.
+// A mesh without vertices or indices is kept, but skipped when drawing.
+pub struct Mesh {
+    pub vertex_buffer_components: VertexBufferComponents,
+    pub index_buffer_components: IndexBufferComponents,
+    // of the vertices last uploaded, None when there were none
+    pub bounds: Option<Aabb>,
+    // recorded instead of the pipeline config's depth bias when set
+    pub depth_bias: Option<DepthBias>,
+}
+
+impl Mesh {
+    pub fn new(
+        device: &ash::Device,
+        memory_allocator: &mut MemoryAllocator,
+        vertices: &[Vertex],
+        indices: &[Index],
+        upload_commands: &UploadCommands,
+    ) -> Result<Mesh, DeviceLost> {
+        // Vulkan doesn't allow buffers of size 0, so empty meshes get room for one element
+        let vertex_buffer_components = VertexBufferComponents::new_unintialized(
+            device,
+            memory_allocator,
+            vertices.len().max(1),
+        );
+        let index_buffer_components = IndexBufferComponents::new_unintiailized(
+            device,
+            memory_allocator,
+            indices.len().max(1),
+            smallest_index_type(vertices.len()),
+        );
+        let mut mesh = Mesh {
+            vertex_buffer_components,
+            index_buffer_components,
+            bounds: None,
+            depth_bias: None,
+        };
+        if let Err(device_lost) = mesh.upload(device, vertices, indices, upload_commands) {
+            mesh.cleanup(device, memory_allocator);
+            return Err(device_lost);
+        }
+        Ok(mesh)
+    }
+    pub fn index_count(&self) -> u32 {
+        self.index_buffer_components.index_count
+    }
+    // Nothing to draw without vertices or indices
+    pub fn is_empty(&self) -> bool {
+        self.index_count() == 0
+    }
+    // The buffers are only reallocated when the new geometry doesn't fit
+    pub fn update(
+        &mut self,
+        device: &ash::Device,
+        memory_allocator: &mut MemoryAllocator,
+        vertices: &[Vertex],
+        indices: &[Index],
+        upload_commands: &UploadCommands,
+    ) -> Result<(), DeviceLost> {
+        if vertices.len() > self.vertex_buffer_components.vertex_buffer.capacity() {
+            self.vertex_buffer_components
+                .resize(device, memory_allocator, vertices.len());
+        }
+        let index_type = smallest_index_type(vertices.len());
+        if indices.len() > self.index_buffer_components.capacity()
+            || index_type != self.index_buffer_components.index_type
+        {
+            self.index_buffer_components
+                .cleanup(device, memory_allocator);
+            self.index_buffer_components = IndexBufferComponents::new_unintiailized(
+                device,
+                memory_allocator,
+                indices.len().max(1),
+                index_type,
+            );
+        }
+        self.upload(device, vertices, indices, upload_commands)
+    }
+    fn upload(
+        &mut self,
+        device: &ash::Device,
+        vertices: &[Vertex],
+        indices: &[Index],
+        upload_commands: &UploadCommands,
+    ) -> Result<(), DeviceLost> {
+        self.bounds = Aabb::from_vertices(vertices);
+        if vertices.is_empty() || indices.is_empty() {
+            // a copy of 0 bytes isn't allowed either
+            self.index_buffer_components.index_count = 0;
+            return Ok(());
+        }
+        self.vertex_buffer_components
+            .update_vertices(device, vertices, upload_commands)?;
+        self.index_buffer_components
+            .update_indices(device, indices, upload_commands)
+    }
+    pub fn cleanup(&self, device: &ash::Device, memory_allocator: &mut MemoryAllocator) {
+        self.vertex_buffer_components
+            .cleanup(device, memory_allocator);
+        self.index_buffer_components
+            .cleanup(device, memory_allocator);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_cover_every_vertex() {
+        let vertex = |position| Vertex {
+            position,
+            color: [1.0; 4],
+            normal: [0.0; 3],
+        };
+        assert_eq!(Aabb::from_vertices(&[]), None);
+        let vertices = [
+            vertex([1.0, -2.0, 3.0]),
+            vertex([-1.0, 4.0, 0.5]),
+            vertex([0.0, 0.0, -6.0]),
+        ];
+        assert_eq!(
+            Aabb::from_vertices(&vertices),
+            Some(Aabb {
+                min: Point3::new(-1.0, -2.0, -6.0),
+                max: Point3::new(1.0, 4.0, 3.0),
+            })
+        );
+    }
+}
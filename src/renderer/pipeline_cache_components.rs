@@ -0,0 +1,67 @@
+use std::fs;
+
+use ash::vk;
+
+// Relative to the working directory
+pub const PIPELINE_CACHE_PATH: &str = "pipeline_cache.bin";
+
+pub struct PipelineCacheComponents {
+    pub pipeline_cache: vk::PipelineCache,
+}
+
+impl PipelineCacheComponents {
+    // initial_data is ignored unless it was written by the same device and driver
+    pub fn new(
+        device: &ash::Device,
+        physical_device_properties: &vk::PhysicalDeviceProperties,
+        initial_data: &[u8],
+    ) -> PipelineCacheComponents {
+        let initial_data = match is_compatible(initial_data, physical_device_properties) {
+            true => initial_data,
+            false => &[],
+        };
+        let pipeline_cache_create_info =
+            vk::PipelineCacheCreateInfo::default().initial_data(initial_data);
+        let pipeline_cache = unsafe {
+            device
+                .create_pipeline_cache(&pipeline_cache_create_info, None)
+                .or_else(|_| {
+                    device.create_pipeline_cache(&vk::PipelineCacheCreateInfo::default(), None)
+                })
+                .expect("Failed to create pipeline cache")
+        };
+        PipelineCacheComponents { pipeline_cache }
+    }
+    pub fn data(&self, device: &ash::Device) -> Vec<u8> {
+        unsafe { device.get_pipeline_cache_data(self.pipeline_cache) }.unwrap_or_default()
+    }
+    pub fn cleanup(&self, device: &ash::Device) {
+        unsafe { device.destroy_pipeline_cache(self.pipeline_cache, None) };
+    }
+}
+
+// Missing or unreadable files give an empty cache
+pub fn load_pipeline_cache_data() -> Vec<u8> {
+    fs::read(PIPELINE_CACHE_PATH).unwrap_or_default()
+}
+
+pub fn save_pipeline_cache_data(data: &[u8]) {
+    if let Err(error) = fs::write(PIPELINE_CACHE_PATH, data) {
+        eprintln!("Failed to write {PIPELINE_CACHE_PATH}: {error}");
+    }
+}
+
+// Checks the VkPipelineCacheHeaderVersionOne header so data from another device, driver or a
+// corrupt file is never handed to the driver.
+fn is_compatible(data: &[u8], physical_device_properties: &vk::PhysicalDeviceProperties) -> bool {
+    const HEADER_SIZE: usize = 32;
+    if data.len() < HEADER_SIZE {
+        return false;
+    }
+    let read_u32 = |offset: usize| u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap());
+    read_u32(0) as usize >= HEADER_SIZE
+        && read_u32(4) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && read_u32(8) == physical_device_properties.vendor_id
+        && read_u32(12) == physical_device_properties.device_id
+        && data[16..32] == physical_device_properties.pipeline_cache_uuid
+}
@@ -0,0 +1,359 @@
+use ash::vk;
+
+#[cfg(feature = "shaderc")]
+use super::command_buffer_components::MAX_FRAMES_IN_FLIGHT;
+use super::{
+    buffer::Buffer,
+    memory_allocator::MemoryAllocator,
+    resize_dependent_components::needs_srgb_encoding,
+    shaders::{SpecializationConstants, ENCODE_SRGB_CONSTANT_ID},
+};
+
+// Runtime parameters of the post effect, read by its fragment shader as
+//   layout (set = 0, binding = 1) uniform PostEffectParams { vec4 values; } params;
+// Each effect decides what the values mean, e.g. FxaaParams fills the first three.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct PostEffectParams {
+    pub values: [f32; 4],
+}
+
+// Tuning for FXAA_POST_EFFECT_SOURCE. Edges are only smoothed where the local contrast is above
+// both edge_threshold times the brightest luma and edge_threshold_min, subpixel scales the
+// smoothing of single pixel details, 0 disables it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FxaaParams {
+    pub edge_threshold: f32,
+    pub edge_threshold_min: f32,
+    pub subpixel: f32,
+}
+
+// the quality defaults of FXAA 3.11
+impl Default for FxaaParams {
+    fn default() -> Self {
+        Self {
+            edge_threshold: 0.166,
+            edge_threshold_min: 0.0833,
+            subpixel: 0.75,
+        }
+    }
+}
+
+impl From<FxaaParams> for PostEffectParams {
+    fn from(fxaa_params: FxaaParams) -> Self {
+        Self {
+            values: [
+                fxaa_params.edge_threshold,
+                fxaa_params.edge_threshold_min,
+                fxaa_params.subpixel,
+                0.0,
+            ],
+        }
+    }
+}
+
+// Fullscreen pass sampling the offscreen render target into the swapchain image with a user
+// supplied fragment shader. Owns its shader modules so the pipeline can be rebuilt when the
+// swapchain format changes.
+pub struct PostEffectComponents {
+    vertex_shader_module: vk::ShaderModule,
+    fragment_shader_module: vk::ShaderModule,
+    sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    // one per frame in flight, the render target at binding 0 is rewritten whenever the target
+    // is rebuilt and the frame's params buffer is bound at binding 1
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    // written each frame, so new params take effect without waiting for the device
+    params_buffers: Vec<Buffer<PostEffectParams>>,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl PostEffectComponents {
+    // the fragment shader is compiled from source, so there is nothing to build without shaderc
+    #[cfg(feature = "shaderc")]
+    pub fn new(
+        device: &ash::Device,
+        memory_allocator: &mut MemoryAllocator,
+        pipeline_cache: vk::PipelineCache,
+        color_format: vk::Format,
+        vertex_shader_code: &[u32],
+        fragment_shader_code: &[u32],
+    ) -> PostEffectComponents {
+        let create_shader_module = |code: &[u32]| unsafe {
+            device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::default().code(code), None)
+                .expect("Failed to create post effect shader module")
+        };
+        let vertex_shader_module = create_shader_module(vertex_shader_code);
+        let fragment_shader_module = create_shader_module(fragment_shader_code);
+
+        // one texel per pixel, so filtering only matters for effects that offset the uv
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        let sampler = unsafe { device.create_sampler(&sampler_info, None).unwrap() };
+
+        let layout_bindings = [
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+        ];
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::default().bindings(&layout_bindings),
+                    None,
+                )
+                .expect("Failed to create post effect descriptor set layout")
+        };
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize::default()
+                .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(MAX_FRAMES_IN_FLIGHT as u32),
+            vk::DescriptorPoolSize::default()
+                .ty(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_count(MAX_FRAMES_IN_FLIGHT as u32),
+        ];
+        let descriptor_pool = unsafe {
+            device
+                .create_descriptor_pool(
+                    &vk::DescriptorPoolCreateInfo::default()
+                        .pool_sizes(&pool_sizes)
+                        .max_sets(MAX_FRAMES_IN_FLIGHT as u32),
+                    None,
+                )
+                .expect("Failed to create post effect descriptor pool")
+        };
+        let descriptor_set_layouts = [descriptor_set_layout; MAX_FRAMES_IN_FLIGHT];
+        let descriptor_sets = unsafe {
+            device
+                .allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::default()
+                        .descriptor_pool(descriptor_pool)
+                        .set_layouts(&descriptor_set_layouts),
+                )
+                .expect("Failed to allocate post effect descriptor sets")
+        };
+
+        let params_buffers: Vec<_> = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| {
+                Buffer::<PostEffectParams>::new(
+                    device,
+                    memory_allocator,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::SharingMode::EXCLUSIVE,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    1,
+                )
+            })
+            .collect();
+        let params_buffer_infos: Vec<_> = params_buffers
+            .iter()
+            .map(|params_buffer| {
+                [vk::DescriptorBufferInfo::default()
+                    .buffer(params_buffer.buffer)
+                    .range(vk::WHOLE_SIZE)]
+            })
+            .collect();
+        let params_descriptor_writes: Vec<_> = descriptor_sets
+            .iter()
+            .zip(&params_buffer_infos)
+            .map(|(&descriptor_set, params_buffer_info)| {
+                vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .descriptor_count(1)
+                    .buffer_info(params_buffer_info)
+            })
+            .collect();
+        unsafe { device.update_descriptor_sets(&params_descriptor_writes, &[]) };
+
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::default()
+                        .set_layouts(&descriptor_set_layouts[..1]),
+                    None,
+                )
+                .expect("Failed to create post effect pipeline layout")
+        };
+
+        let mut post_effect_components = PostEffectComponents {
+            vertex_shader_module,
+            fragment_shader_module,
+            sampler,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_sets,
+            params_buffers,
+            pipeline_layout,
+            pipeline: vk::Pipeline::null(),
+        };
+        post_effect_components.pipeline =
+            post_effect_components.create_pipeline(device, pipeline_cache, color_format);
+        post_effect_components
+    }
+    fn create_pipeline(
+        &self,
+        device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        color_format: vk::Format,
+    ) -> vk::Pipeline {
+        // the sampled scene is linear, the fragment shader encodes it if color_format needs it
+        let specialization_constants = SpecializationConstants::default()
+            .with_reserved_bool(ENCODE_SRGB_CONSTANT_ID, needs_srgb_encoding(color_format));
+        let specialization_info = specialization_constants.info();
+        let shader_stage_infos = [
+            vk::PipelineShaderStageCreateInfo::default()
+                .module(self.vertex_shader_module)
+                .name(c"main")
+                .stage(vk::ShaderStageFlags::VERTEX),
+            vk::PipelineShaderStageCreateInfo::default()
+                .module(self.fragment_shader_module)
+                .name(c"main")
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .specialization_info(&specialization_info),
+        ];
+
+        // no vertex buffers, the vertex shader derives everything from gl_VertexIndex
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::default();
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        // viewport and scissor are dynamic, only their counts matter here
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
+            .cull_mode(vk::CullModeFlags::NONE)
+            .line_width(1.0)
+            .polygon_mode(vk::PolygonMode::FILL);
+        // the swapchain image is never multisampled, the scene was resolved into the target
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState::default()
+            .color_write_mask(vk::ColorComponentFlags::RGBA)];
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::default()
+            .attachments(&color_blend_attachment_states);
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+
+        // no depth attachment, the fullscreen triangle covers every pixel
+        let color_attachment_formats = [color_format];
+        let mut pipeline_rendering_create_info = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&color_attachment_formats);
+
+        let graphics_pipeline_create_info = vk::GraphicsPipelineCreateInfo::default()
+            .push_next(&mut pipeline_rendering_create_info)
+            .stages(&shader_stage_infos)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state_info)
+            .layout(self.pipeline_layout);
+
+        unsafe {
+            device
+                .create_graphics_pipelines(pipeline_cache, &[graphics_pipeline_create_info], None)
+                .expect("Failed to create post effect pipeline")[0]
+        }
+    }
+    // For a new swapchain format, no submitted frame may still use the pipeline
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        color_format: vk::Format,
+    ) {
+        let pipeline = self.create_pipeline(device, pipeline_cache, color_format);
+        unsafe { device.destroy_pipeline(std::mem::replace(&mut self.pipeline, pipeline), None) };
+    }
+    // Only while no submitted frame uses the sets
+    pub fn write_render_target(&self, device: &ash::Device, image_view: vk::ImageView) {
+        let descriptor_image_info = [vk::DescriptorImageInfo::default()
+            .sampler(self.sampler)
+            .image_view(image_view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let descriptor_writes: Vec<_> = self
+            .descriptor_sets
+            .iter()
+            .map(|&descriptor_set| {
+                vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .image_info(&descriptor_image_info)
+            })
+            .collect();
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+    }
+    // Only once frame's fence is signaled, like the other per frame uniforms
+    pub fn write_params(&mut self, device: &ash::Device, frame: usize, params: &PostEffectParams) {
+        self.params_buffers[frame].write_data_direct(device, std::slice::from_ref(params));
+    }
+    // Draws into the single color attachment of rendering_info, the render target must already
+    // be in SHADER_READ_ONLY_OPTIMAL
+    pub fn record(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        rendering_info: &vk::RenderingInfo,
+        viewports: &[vk::Viewport],
+        scissors: &[vk::Rect2D],
+    ) {
+        unsafe {
+            device.cmd_begin_rendering(command_buffer, rendering_info);
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_set_viewport(command_buffer, 0, viewports);
+            device.cmd_set_scissor(command_buffer, 0, scissors);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_sets[frame]],
+                &[],
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            device.cmd_end_rendering(command_buffer);
+        }
+    }
+    // No submitted frame may still use the pass, callers wait for the device to be idle
+    pub fn cleanup(&self, device: &ash::Device, memory_allocator: &mut MemoryAllocator) {
+        for params_buffer in &self.params_buffers {
+            params_buffer.cleanup(device, memory_allocator);
+        }
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_shader_module(self.vertex_shader_module, None);
+            device.destroy_shader_module(self.fragment_shader_module, None);
+        }
+    }
+}
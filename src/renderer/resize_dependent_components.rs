@@ -1,53 +1,202 @@
-use ash::{
-    khr::{self, surface},
-    vk,
-};
+use accumulation_image_components::AccumulationImageComponents;
+use ash::{khr, vk};
 use depth_image_components::DepthImageComponents;
+use msaa_color_image_components::MsaaColorImageComponents;
+use render_target_components::RenderTargetComponents;
+use std::fmt;
 use swapchain_components::SwapchainComponents;
 
+use super::{
+    command_buffer_components::SetupContext, frame_capture::FrameCaptureComponents,
+    memory_allocator::MemoryAllocator, DeviceLost,
+};
+
+pub use depth_image_components::select_depth_format;
+pub use swapchain_components::{needs_srgb_encoding, PresentModePreference, SwapchainSettings};
+
+mod accumulation_image_components;
 mod depth_image_components;
+mod msaa_color_image_components;
+mod render_target_components;
 mod swapchain_components;
 
+// Renderer state, independent of user settings, that resize dependent components are built from
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResizeDependentOptions {
+    pub surface_extent_override: Option<vk::Extent2D>,
+    // render into an owned image that is loaded rather than cleared each frame. Ignored, so
+    // frames are cleared, when the swapchain images can't be copied into.
+    pub persistent_color_target: bool,
+    // render the scene into an offscreen image for the post effect pass to sample
+    pub post_effect_target: bool,
+    // copy every frame into host visible memory for capture_frame
+    pub frame_capture: bool,
+}
+
+// Why the resize dependent components couldn't be created
+#[derive(Debug)]
+pub enum ResizeDependentError {
+    // e.g. the surface can't be presented to by the device
+    Swapchain(String),
+    DeviceLost,
+}
+
+impl fmt::Display for ResizeDependentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResizeDependentError::Swapchain(error) => write!(f, "{error}"),
+            ResizeDependentError::DeviceLost => write!(f, "{DeviceLost}"),
+        }
+    }
+}
+
 pub struct ResizeDependentComponents {
     pub swapchain_components: SwapchainComponents,
     pub depth_image_components: DepthImageComponents,
+    pub accumulation_image_components: Option<AccumulationImageComponents>,
+    // only when multisampling, rendered into and resolved into the swapchain or accumulation image
+    pub msaa_color_image_components: Option<MsaaColorImageComponents>,
+    // only with ResizeDependentOptions::post_effect_target, the scene is rendered or resolved
+    // into it instead
+    pub render_target_components: Option<RenderTargetComponents>,
+    // only with ResizeDependentOptions::frame_capture
+    pub frame_capture_components: Option<FrameCaptureComponents>,
     pub scissors: [vk::Rect2D; 1],
     pub viewports: [vk::Viewport; 1],
 }
 
-pub const DEPTH_IMAGE_FORMAT: vk::Format = vk::Format::D16_UNORM;
-
 impl ResizeDependentComponents {
     pub fn new(
-        device: &ash::Device,
-        window: &winit::window::Window,
-        surface: vk::SurfaceKHR,
-        surface_loader: &surface::Instance,
-        swapchain_loader: &khr::swapchain::Device,
-        physical_device: vk::PhysicalDevice,
-        setup_command_buffer: vk::CommandBuffer,
-        setup_commands_reuse_fence: vk::Fence,
-        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
-        graphics_queue: vk::Queue,
-    ) -> ResizeDependentComponents {
-        let swapchain_components = SwapchainComponents::new(
-            device,
-            window,
-            surface,
-            surface_loader,
-            swapchain_loader,
-            physical_device,
-        );
+        setup_context: &mut SetupContext,
+        swapchain_settings: &SwapchainSettings,
+        depth_format: vk::Format,
+        msaa_samples: vk::SampleCountFlags,
+        options: &ResizeDependentOptions,
+        old_swapchain: vk::SwapchainKHR,
+    ) -> Result<ResizeDependentComponents, ResizeDependentError> {
+        let device = setup_context.device;
+        let swapchain_components = match swapchain_settings.window_surface {
+            Some((window, surface)) => SwapchainComponents::new(
+                device,
+                window,
+                surface,
+                swapchain_settings,
+                options.surface_extent_override,
+                old_swapchain,
+            )
+            .map_err(ResizeDependentError::Swapchain)?,
+            // headless renderers always have an extent override
+            None => SwapchainComponents::new_offscreen(
+                device,
+                swapchain_settings.physical_device_memory_properties,
+                options
+                    .surface_extent_override
+                    .expect("Headless rendering requires an extent"),
+            ),
+        };
 
         let depth_image_components = DepthImageComponents::new(
-            device,
-            physical_device_memory_properties,
+            setup_context,
             &swapchain_components.surface_resolution,
-            setup_command_buffer,
-            setup_commands_reuse_fence,
-            graphics_queue,
+            depth_format,
+            msaa_samples,
         );
 
+        let scene_color_format =
+            scene_color_format(&swapchain_components, options.post_effect_target);
+
+        let msaa_color_image_components = if msaa_samples != vk::SampleCountFlags::TYPE_1 {
+            MsaaColorImageComponents::new(
+                setup_context,
+                scene_color_format,
+                &swapchain_components.surface_resolution,
+                msaa_samples,
+            )
+            .map(Some)
+        } else {
+            Ok(None)
+        };
+
+        let accumulation_image_components = if options.persistent_color_target
+            && swapchain_components
+                .image_usage
+                .contains(vk::ImageUsageFlags::TRANSFER_DST)
+        {
+            AccumulationImageComponents::new(
+                setup_context,
+                swapchain_settings.physical_device_memory_properties,
+                swapchain_components.surface_format.format,
+                &swapchain_components.surface_resolution,
+            )
+            .map(Some)
+        } else {
+            Ok(None)
+        };
+
+        let render_target_components = match options.post_effect_target {
+            true => {
+                RenderTargetComponents::new(setup_context, &swapchain_components.surface_resolution)
+                    .map(Some)
+            }
+            false => Ok(None),
+        };
+
+        let (
+            depth_image_components,
+            msaa_color_image_components,
+            accumulation_image_components,
+            render_target_components,
+        ) = match (
+            depth_image_components,
+            msaa_color_image_components,
+            accumulation_image_components,
+            render_target_components,
+        ) {
+            (
+                Ok(depth_image_components),
+                Ok(msaa_color_image_components),
+                Ok(accumulation_image_components),
+                Ok(render_target_components),
+            ) => (
+                depth_image_components,
+                msaa_color_image_components,
+                accumulation_image_components,
+                render_target_components,
+            ),
+            (
+                depth_image_components,
+                msaa_color_image_components,
+                accumulation_image_components,
+                render_target_components,
+            ) => {
+                // once the device is lost every later submission fails as well, so whatever
+                // was created before is destroyed again
+                let memory_allocator = &mut *setup_context.memory_allocator;
+                if let Ok(depth_image_components) = depth_image_components {
+                    depth_image_components.cleanup(device, memory_allocator);
+                }
+                if let Ok(Some(msaa_color_image_components)) = msaa_color_image_components {
+                    msaa_color_image_components.cleanup(device, memory_allocator);
+                }
+                if let Ok(Some(accumulation_image_components)) = accumulation_image_components {
+                    accumulation_image_components.cleanup(device);
+                }
+                if let Ok(Some(render_target_components)) = render_target_components {
+                    render_target_components.cleanup(device, memory_allocator);
+                }
+                swapchain_components.cleanup(device, swapchain_settings.swapchain_loader);
+                return Err(ResizeDependentError::DeviceLost);
+            }
+        };
+
+        let frame_capture_components = options.frame_capture.then(|| {
+            FrameCaptureComponents::new(
+                device,
+                setup_context.memory_allocator,
+                swapchain_components.surface_resolution,
+            )
+        });
+
         let scissors = [swapchain_components.surface_resolution.into()];
         let viewports = [vk::Viewport {
             x: 0.0,
@@ -58,15 +207,57 @@ impl ResizeDependentComponents {
             max_depth: 1.0,
         }];
 
-        ResizeDependentComponents {
+        Ok(ResizeDependentComponents {
             swapchain_components,
             depth_image_components,
+            accumulation_image_components,
+            msaa_color_image_components,
+            render_target_components,
+            frame_capture_components,
             scissors,
             viewports,
-        }
+        })
     }
-    pub fn cleanup(&self, device: &ash::Device, swapchain_loader: &khr::swapchain::Device) {
-        self.depth_image_components.cleanup(device);
+    // Format the scene is drawn in, which the pipelines are built for
+    pub fn scene_color_format(&self) -> vk::Format {
+        scene_color_format(
+            &self.swapchain_components,
+            self.render_target_components.is_some(),
+        )
+    }
+    // Nothing may still be using the components, callers wait for the device to be idle
+    pub fn cleanup(
+        &self,
+        device: &ash::Device,
+        swapchain_loader: &khr::swapchain::Device,
+        memory_allocator: &mut MemoryAllocator,
+    ) {
+        self.depth_image_components
+            .cleanup(device, memory_allocator);
+        if let Some(accumulation_image_components) = &self.accumulation_image_components {
+            accumulation_image_components.cleanup(device);
+        }
+        if let Some(msaa_color_image_components) = &self.msaa_color_image_components {
+            msaa_color_image_components.cleanup(device, memory_allocator);
+        }
+        if let Some(render_target_components) = &self.render_target_components {
+            render_target_components.cleanup(device, memory_allocator);
+        }
+        if let Some(frame_capture_components) = &self.frame_capture_components {
+            frame_capture_components.cleanup(device, memory_allocator);
+        }
         self.swapchain_components.cleanup(device, swapchain_loader);
     }
 }
+
+// The render target keeps the scene linear for the post effect, otherwise the scene is drawn in
+// the swapchain's format
+fn scene_color_format(
+    swapchain_components: &SwapchainComponents,
+    post_effect_target: bool,
+) -> vk::Format {
+    match post_effect_target {
+        true => RenderTargetComponents::FORMAT,
+        false => swapchain_components.surface_format.format,
+    }
+}
@@ -0,0 +1,222 @@
+use ash::vk;
+
+use crate::renderer::{command_buffer_components::SetupContext, find_memorytype_index, DeviceLost};
+
+// Color target owned by the renderer whose contents persist across frames.
+// Swapchain images rotate and are undefined after acquire, so content that should survive
+// between frames is rendered here with LOAD and copied into the acquired image.
+// Kept in COLOR_ATTACHMENT_OPTIMAL between frames.
+pub struct AccumulationImageComponents {
+    pub image: vk::Image,
+    pub image_view: vk::ImageView,
+    pub image_memory: vk::DeviceMemory,
+}
+
+impl AccumulationImageComponents {
+    pub fn new(
+        setup_context: &SetupContext,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        format: vk::Format,
+        surface_resolution: &vk::Extent2D,
+    ) -> Result<AccumulationImageComponents, DeviceLost> {
+        let device = setup_context.device;
+        let image_create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent((*surface_resolution).into())
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+            )
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let image = unsafe { device.create_image(&image_create_info, None).unwrap() };
+
+        let image_memory_reqs = unsafe { device.get_image_memory_requirements(image) };
+
+        let image_memory_index = find_memorytype_index(
+            &image_memory_reqs,
+            physical_device_memory_properties,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .expect("Cannot find suitable memory index for accumulation image");
+
+        let image_allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(image_memory_reqs.size)
+            .memory_type_index(image_memory_index);
+
+        let image_memory = unsafe { device.allocate_memory(&image_allocate_info, None).unwrap() };
+
+        unsafe {
+            device
+                .bind_image_memory(image, image_memory, 0)
+                .expect("Failed to bind accumulation image memory")
+        };
+
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1);
+
+        // start from a known cleared state since the first frame loads it
+        // on device loss the finished components are cleaned up again below
+        let layout_transition = setup_context.submit(|device, setup_command_buffer| unsafe {
+            let clear_barrier = vk::ImageMemoryBarrier::default()
+                .image(image)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .subresource_range(subresource_range);
+            device.cmd_pipeline_barrier(
+                setup_command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[clear_barrier],
+            );
+            device.cmd_clear_color_image(
+                setup_command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue::default(),
+                &[subresource_range],
+            );
+            let attachment_barrier = vk::ImageMemoryBarrier::default()
+                .image(image)
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_READ
+                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                )
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .subresource_range(subresource_range);
+            device.cmd_pipeline_barrier(
+                setup_command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[attachment_barrier],
+            );
+        });
+
+        let image_view_info = vk::ImageViewCreateInfo::default()
+            .subresource_range(subresource_range)
+            .image(image)
+            .format(format)
+            .view_type(vk::ImageViewType::TYPE_2D);
+
+        let image_view = unsafe { device.create_image_view(&image_view_info, None).unwrap() };
+
+        let components = AccumulationImageComponents {
+            image,
+            image_view,
+            image_memory,
+        };
+        if let Err(device_lost) = layout_transition {
+            components.cleanup(device);
+            return Err(device_lost);
+        }
+        Ok(components)
+    }
+    // Copies the rendered contents into the acquired swapchain image and leaves that image in
+    // present_layout. Must be recorded after rendering into the accumulation image.
+    pub fn record_copy_to_present_image(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        present_image: vk::Image,
+        present_layout: vk::ImageLayout,
+        surface_resolution: vk::Extent2D,
+    ) {
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1);
+        let subresource_layers = vk::ImageSubresourceLayers::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1);
+        unsafe {
+            let copy_barriers = [
+                vk::ImageMemoryBarrier::default()
+                    .image(self.image)
+                    .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                    .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                    .subresource_range(subresource_range),
+                vk::ImageMemoryBarrier::default()
+                    .image(present_image)
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .subresource_range(subresource_range),
+            ];
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &copy_barriers,
+            );
+            let image_copy = vk::ImageCopy::default()
+                .src_subresource(subresource_layers)
+                .dst_subresource(subresource_layers)
+                .extent(surface_resolution.into());
+            device.cmd_copy_image(
+                command_buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                present_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[image_copy],
+            );
+            let present_barriers = [
+                vk::ImageMemoryBarrier::default()
+                    .image(self.image)
+                    .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+                    .dst_access_mask(
+                        vk::AccessFlags::COLOR_ATTACHMENT_READ
+                            | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    )
+                    .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                    .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .subresource_range(subresource_range),
+                vk::ImageMemoryBarrier::default()
+                    .image(present_image)
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .new_layout(present_layout)
+                    .subresource_range(subresource_range),
+            ];
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &present_barriers,
+            );
+        }
+    }
+    pub fn cleanup(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_image_view(self.image_view, None);
+            device.destroy_image(self.image, None);
+            device.free_memory(self.image_memory, None);
+        }
+    }
+}
@@ -1,32 +1,55 @@
 use ash::vk;
 
-use crate::renderer::{command_buffer_components::record_submit_commandbuffer, find_memorytype_index};
+use crate::renderer::{
+    command_buffer_components::SetupContext,
+    memory_allocator::{Allocation, MemoryAllocator},
+    DeviceLost,
+};
 
-pub const DEPTH_IMAGE_FORMAT: vk::Format = vk::Format::D16_UNORM;
+// Most precise format the device supports as an optimally tiled depth attachment.
+// D16_UNORM support is required by the spec so the search can't fail.
+pub fn select_depth_format(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> vk::Format {
+    [
+        vk::Format::D32_SFLOAT,
+        vk::Format::D32_SFLOAT_S8_UINT,
+        vk::Format::D24_UNORM_S8_UINT,
+        vk::Format::D16_UNORM,
+    ]
+    .into_iter()
+    .find(|&format| {
+        let format_properties =
+            unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+        format_properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+    })
+    .expect("No supported depth format")
+}
 
 pub struct DepthImageComponents {
     pub depth_image: vk::Image,
     pub depth_image_view: vk::ImageView,
-    pub depth_image_memory: vk::DeviceMemory,
+    pub depth_image_allocation: Allocation,
 }
 
 impl DepthImageComponents {
     pub fn new(
-        device: &ash::Device,
-        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        setup_context: &mut SetupContext,
         surface_resolution: &vk::Extent2D,
-        setup_command_buffer: vk::CommandBuffer,
-        setup_commands_reuse_fence: vk::Fence,
-        present_queue: vk::Queue,
-    ) -> DepthImageComponents {
-        let sr = surface_resolution.clone();
+        depth_format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> Result<DepthImageComponents, DeviceLost> {
+        let device = setup_context.device;
         let depth_image_create_info = vk::ImageCreateInfo::default()
             .image_type(vk::ImageType::TYPE_2D)
-            .format(DEPTH_IMAGE_FORMAT)
-            .extent(sr.into())
+            .format(depth_format)
+            .extent((*surface_resolution).into())
             .mip_levels(1)
             .array_layers(1)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .samples(samples)
             .tiling(vk::ImageTiling::OPTIMAL)
             .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
@@ -35,65 +58,58 @@ impl DepthImageComponents {
 
         let depth_image_memory_reqs = unsafe { device.get_image_memory_requirements(depth_image) };
 
-        let depth_image_memory_index = find_memorytype_index(
+        let depth_image_allocation = setup_context.memory_allocator.allocate(
+            device,
             &depth_image_memory_reqs,
-            &physical_device_memory_properties,
             vk::MemoryPropertyFlags::DEVICE_LOCAL,
-        )
-        .expect("Cannot find suitable memory index for depth image");
-
-        let depth_image_allocate_info = vk::MemoryAllocateInfo::default()
-            .allocation_size(depth_image_memory_reqs.size)
-            .memory_type_index(depth_image_memory_index);
-
-        let depth_image_memory = unsafe {
-            device
-                .allocate_memory(&depth_image_allocate_info, None)
-                .unwrap()
-        };
+        );
 
         unsafe {
             device
-                .bind_image_memory(depth_image, depth_image_memory, 0)
+                .bind_image_memory(
+                    depth_image,
+                    depth_image_allocation.memory,
+                    depth_image_allocation.offset,
+                )
                 .expect("Faile to bind depth image memory")
         };
 
-        record_submit_commandbuffer(
-            &device,
-            present_queue,
-            setup_command_buffer,
-            setup_commands_reuse_fence,
-            &[],
-            &[],
-            &[],
-            |device, setup_command_buffer| {
-                let layout_transition_barrier = vk::ImageMemoryBarrier::default()
-                    .image(depth_image)
-                    .dst_access_mask(
-                        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
-                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
-                    )
-                    .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                    .old_layout(vk::ImageLayout::UNDEFINED)
-                    .subresource_range(
-                        vk::ImageSubresourceRange::default()
-                            .aspect_mask(vk::ImageAspectFlags::DEPTH)
-                            .layer_count(1)
-                            .level_count(1),
-                    );
-                unsafe {
-                    device.cmd_pipeline_barrier(
-                        setup_command_buffer,
-                        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                        vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
-                        vk::DependencyFlags::empty(),
-                        &[],
-                        &[],
-                        &[layout_transition_barrier],
-                    )
-                };
-            },
-        );
+        // layout transitions of combined formats have to cover the stencil aspect too
+        let layout_transition_aspect_mask = match depth_format {
+            vk::Format::D32_SFLOAT_S8_UINT | vk::Format::D24_UNORM_S8_UINT => {
+                vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+            }
+            _ => vk::ImageAspectFlags::DEPTH,
+        };
+
+        // on device loss the finished components are cleaned up again below
+        let layout_transition = setup_context.submit(|device, setup_command_buffer| {
+            let layout_transition_barrier = vk::ImageMemoryBarrier::default()
+                .image(depth_image)
+                .dst_access_mask(
+                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .subresource_range(
+                    vk::ImageSubresourceRange::default()
+                        .aspect_mask(layout_transition_aspect_mask)
+                        .layer_count(1)
+                        .level_count(1),
+                );
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    setup_command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[layout_transition_barrier],
+                )
+            };
+        });
 
         let depth_image_view_info = vk::ImageViewCreateInfo::default()
             .subresource_range(
@@ -112,18 +128,22 @@ impl DepthImageComponents {
                 .unwrap()
         };
 
-        DepthImageComponents {
+        let components = DepthImageComponents {
             depth_image,
-            depth_image_memory,
+            depth_image_allocation,
             depth_image_view,
+        };
+        if let Err(device_lost) = layout_transition {
+            components.cleanup(device, setup_context.memory_allocator);
+            return Err(device_lost);
         }
+        Ok(components)
     }
-    pub fn cleanup(&self, device: &ash::Device) {
+    pub fn cleanup(&self, device: &ash::Device, memory_allocator: &mut MemoryAllocator) {
         unsafe {
-            device.device_wait_idle().unwrap();
             device.destroy_image_view(self.depth_image_view, None);
             device.destroy_image(self.depth_image, None);
-            device.free_memory(self.depth_image_memory, None);
         }
+        memory_allocator.free(&self.depth_image_allocation);
     }
 }
@@ -0,0 +1,130 @@
+use ash::vk;
+
+use crate::renderer::{
+    command_buffer_components::SetupContext,
+    memory_allocator::{Allocation, MemoryAllocator},
+    DeviceLost,
+};
+
+// Multisampled color target, resolved into the single sampled target at the end of rendering.
+// Cleared once on creation so it can also be loaded when the color target persists.
+// Kept in COLOR_ATTACHMENT_OPTIMAL between frames.
+pub struct MsaaColorImageComponents {
+    pub image: vk::Image,
+    pub image_view: vk::ImageView,
+    pub allocation: Allocation,
+}
+
+impl MsaaColorImageComponents {
+    pub fn new(
+        setup_context: &mut SetupContext,
+        format: vk::Format,
+        surface_resolution: &vk::Extent2D,
+        samples: vk::SampleCountFlags,
+    ) -> Result<MsaaColorImageComponents, DeviceLost> {
+        let device = setup_context.device;
+        let image_create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent((*surface_resolution).into())
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let image = unsafe { device.create_image(&image_create_info, None).unwrap() };
+
+        let image_memory_reqs = unsafe { device.get_image_memory_requirements(image) };
+
+        let allocation = setup_context.memory_allocator.allocate(
+            device,
+            &image_memory_reqs,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+
+        unsafe {
+            device
+                .bind_image_memory(image, allocation.memory, allocation.offset)
+                .expect("Failed to bind multisampled color image memory")
+        };
+
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1);
+
+        // start from a known cleared state in case the first frame loads it
+        // on device loss the finished components are cleaned up again below
+        let layout_transition = setup_context.submit(|device, setup_command_buffer| unsafe {
+            let clear_barrier = vk::ImageMemoryBarrier::default()
+                .image(image)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .subresource_range(subresource_range);
+            device.cmd_pipeline_barrier(
+                setup_command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[clear_barrier],
+            );
+            device.cmd_clear_color_image(
+                setup_command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue::default(),
+                &[subresource_range],
+            );
+            let attachment_barrier = vk::ImageMemoryBarrier::default()
+                .image(image)
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_READ
+                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                )
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .subresource_range(subresource_range);
+            device.cmd_pipeline_barrier(
+                setup_command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[attachment_barrier],
+            );
+        });
+
+        let image_view_info = vk::ImageViewCreateInfo::default()
+            .subresource_range(subresource_range)
+            .image(image)
+            .format(format)
+            .view_type(vk::ImageViewType::TYPE_2D);
+
+        let image_view = unsafe { device.create_image_view(&image_view_info, None).unwrap() };
+
+        let components = MsaaColorImageComponents {
+            image,
+            image_view,
+            allocation,
+        };
+        if let Err(device_lost) = layout_transition {
+            components.cleanup(device, setup_context.memory_allocator);
+            return Err(device_lost);
+        }
+        Ok(components)
+    }
+    pub fn cleanup(&self, device: &ash::Device, memory_allocator: &mut MemoryAllocator) {
+        unsafe {
+            device.destroy_image_view(self.image_view, None);
+            device.destroy_image(self.image, None);
+        }
+        memory_allocator.free(&self.allocation);
+    }
+}
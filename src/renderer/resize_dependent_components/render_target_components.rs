@@ -0,0 +1,197 @@
+use ash::vk;
+
+use crate::renderer::{
+    command_buffer_components::SetupContext,
+    memory_allocator::{Allocation, MemoryAllocator},
+    DeviceLost,
+};
+
+// Offscreen color target the scene is rendered into while a post effect is set, then sampled by
+// the post effect pass that writes the swapchain image. It shares the depth image with the
+// swapchain, which has the same extent.
+// Kept in SHADER_READ_ONLY_OPTIMAL between frames, so its contents survive for a LOAD.
+pub struct RenderTargetComponents {
+    pub image: vk::Image,
+    pub image_view: vk::ImageView,
+    pub allocation: Allocation,
+}
+
+impl RenderTargetComponents {
+    // floating point, so the post effect samples linear color without banding in the darks.
+    // Required to support color attachment and sampled usage.
+    pub const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+    pub fn new(
+        setup_context: &mut SetupContext,
+        surface_resolution: &vk::Extent2D,
+    ) -> Result<RenderTargetComponents, DeviceLost> {
+        let device = setup_context.device;
+        let image_create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(Self::FORMAT)
+            .extent((*surface_resolution).into())
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+            )
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let image = unsafe { device.create_image(&image_create_info, None).unwrap() };
+
+        let image_memory_reqs = unsafe { device.get_image_memory_requirements(image) };
+
+        let allocation = setup_context.memory_allocator.allocate(
+            device,
+            &image_memory_reqs,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+
+        unsafe {
+            device
+                .bind_image_memory(image, allocation.memory, allocation.offset)
+                .expect("Failed to bind render target memory")
+        };
+
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1);
+
+        // cleared, since the first frame loads it when the clear color is disabled
+        // on device loss the finished components are cleaned up again below
+        let layout_transition = setup_context.submit(|device, setup_command_buffer| unsafe {
+            let clear_barrier = vk::ImageMemoryBarrier::default()
+                .image(image)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .subresource_range(subresource_range);
+            device.cmd_pipeline_barrier(
+                setup_command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[clear_barrier],
+            );
+            device.cmd_clear_color_image(
+                setup_command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue::default(),
+                &[subresource_range],
+            );
+            let sampled_barrier = vk::ImageMemoryBarrier::default()
+                .image(image)
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .subresource_range(subresource_range);
+            device.cmd_pipeline_barrier(
+                setup_command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[sampled_barrier],
+            );
+        });
+
+        let image_view_info = vk::ImageViewCreateInfo::default()
+            .subresource_range(subresource_range)
+            .image(image)
+            .format(Self::FORMAT)
+            .view_type(vk::ImageViewType::TYPE_2D);
+
+        let image_view = unsafe { device.create_image_view(&image_view_info, None).unwrap() };
+
+        let components = RenderTargetComponents {
+            image,
+            image_view,
+            allocation,
+        };
+        if let Err(device_lost) = layout_transition {
+            components.cleanup(device, setup_context.memory_allocator);
+            return Err(device_lost);
+        }
+        Ok(components)
+    }
+    // Before rendering the scene into the target, after the previous frame's post effect pass
+    // has sampled it
+    pub fn record_attachment_barrier(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        self.record_barrier(
+            device,
+            command_buffer,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::ImageMemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::SHADER_READ)
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_READ
+                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                )
+                .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+        );
+    }
+    // After rendering the scene, before the post effect pass samples it
+    pub fn record_sampled_barrier(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        self.record_barrier(
+            device,
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::ImageMemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        );
+    }
+    // barrier only needs the access masks and layouts, the image and its range are filled in
+    fn record_barrier(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        src_stage_mask: vk::PipelineStageFlags,
+        dst_stage_mask: vk::PipelineStageFlags,
+        barrier: vk::ImageMemoryBarrier,
+    ) {
+        let image_memory_barrier = barrier.image(self.image).subresource_range(
+            vk::ImageSubresourceRange::default()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .level_count(1)
+                .layer_count(1),
+        );
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                src_stage_mask,
+                dst_stage_mask,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[image_memory_barrier],
+            )
+        };
+    }
+    pub fn cleanup(&self, device: &ash::Device, memory_allocator: &mut MemoryAllocator) {
+        unsafe {
+            device.destroy_image_view(self.image_view, None);
+            device.destroy_image(self.image, None);
+        }
+        memory_allocator.free(&self.allocation);
+    }
+}
@@ -3,12 +3,65 @@ use ash::{
     khr
 };
 
+use crate::renderer::find_memorytype_index;
+
+// Color format of the offscreen image that stands in for the swapchain when rendering headless
+pub const OFFSCREEN_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+// Present mode to use when the surface supports it, FIFO otherwise.
+// Fifo and FifoRelaxed are vsynced, Immediate may tear.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PresentModePreference {
+    Fifo,
+    #[default]
+    Mailbox,
+    Immediate,
+    FifoRelaxed,
+}
+
+impl PresentModePreference {
+    pub fn present_mode(self) -> vk::PresentModeKHR {
+        match self {
+            PresentModePreference::Fifo => vk::PresentModeKHR::FIFO,
+            PresentModePreference::Mailbox => vk::PresentModeKHR::MAILBOX,
+            PresentModePreference::Immediate => vk::PresentModeKHR::IMMEDIATE,
+            PresentModePreference::FifoRelaxed => vk::PresentModeKHR::FIFO_RELAXED,
+        }
+    }
+}
+
+// What swapchains are created from, unchanged across resizes until the device is recreated
+#[derive(Clone, Copy)]
+pub struct SwapchainSettings<'a> {
+    // None when headless, an offscreen image stands in for the swapchain then
+    pub window_surface: Option<(&'a winit::window::Window, vk::SurfaceKHR)>,
+    pub surface_loader: &'a khr::surface::Instance,
+    pub swapchain_loader: &'a khr::swapchain::Device,
+    pub physical_device: vk::PhysicalDevice,
+    pub physical_device_memory_properties: &'a vk::PhysicalDeviceMemoryProperties,
+    // the family of the queue frames are rendered on
+    pub graphics_queue_family_index: u32,
+    // the family of the queue frames are presented from
+    pub present_queue_family_index: u32,
+    pub present_mode_preference: PresentModePreference,
+    // None requests one more image than the minimum
+    pub desired_image_count: Option<u32>,
+}
+
+// Either a real swapchain or, when headless, a single owned offscreen image in its place
 pub struct SwapchainComponents {
+    // null when headless
     pub swapchain: vk::SwapchainKHR,
     pub present_images: Vec<vk::Image>,
     pub present_image_views: Vec<vk::ImageView>,
     pub surface_format: vk::SurfaceFormatKHR,
     pub surface_resolution: vk::Extent2D,
+    pub image_usage: vk::ImageUsageFlags,
+    // layout the present images are left in at the end of a frame
+    pub present_layout: vk::ImageLayout,
+    // backing memory of the offscreen image, only when headless
+    pub offscreen_image_memory: Option<vk::DeviceMemory>,
 }
 
 impl SwapchainComponents {
@@ -16,15 +69,44 @@ impl SwapchainComponents {
         device: &ash::Device,
         window: &winit::window::Window,
         surface: vk::SurfaceKHR,
-        surface_loader: &khr::surface::Instance,
-        swapchain_loader: &khr::swapchain::Device,
-        physical_device: vk::PhysicalDevice,
-    ) -> SwapchainComponents {
-        let surface_format = unsafe {
+        settings: &SwapchainSettings,
+        surface_extent_override: Option<vk::Extent2D>,
+        // the swapchain being replaced, or null, still has to be destroyed afterwards
+        old_swapchain: vk::SwapchainKHR,
+    ) -> Result<SwapchainComponents, String> {
+        let SwapchainSettings {
+            surface_loader,
+            swapchain_loader,
+            physical_device,
+            graphics_queue_family_index,
+            present_queue_family_index,
+            present_mode_preference,
+            desired_image_count,
+            ..
+        } = *settings;
+        // checked again here since the surface may have changed since the device was selected
+        let surface_supported = unsafe {
+            surface_loader
+                .get_physical_device_surface_support(
+                    physical_device,
+                    present_queue_family_index,
+                    surface,
+                )
+                .map_err(|error| format!("Failed to query surface support: {error}"))?
+        };
+        if !surface_supported {
+            return Err(format!(
+                "Queue family {present_queue_family_index} can't present to the surface"
+            ));
+        }
+
+        let surface_formats = unsafe {
             surface_loader
                 .get_physical_device_surface_formats(physical_device, surface)
-                .unwrap()[0]
+                .map_err(|error| format!("Failed to query surface formats: {error}"))?
         };
+        let surface_format = select_surface_format(&surface_formats)
+            .ok_or_else(|| "The surface reports no supported formats".to_string())?;
 
         let surface_capabilities = unsafe {
             surface_loader
@@ -32,20 +114,31 @@ impl SwapchainComponents {
                 .unwrap()
         };
 
-        let mut desired_image_count = surface_capabilities.min_image_count + 1;
+        let desired_image_count = swapchain_image_count(desired_image_count, &surface_capabilities);
 
-        if surface_capabilities.max_image_count > 0
-            && desired_image_count > surface_capabilities.max_image_count
-        {
-            desired_image_count = surface_capabilities.max_image_count;
-        }
-
-        let surface_resolution = match surface_capabilities.current_extent.width {
-            u32::MAX => vk::Extent2D {
-                width: window.inner_size().width.max(1),
-                height: window.inner_size().height.max(1),
+        let surface_resolution = match surface_extent_override {
+            Some(extent) => {
+                let min = surface_capabilities.min_image_extent;
+                let max = surface_capabilities.max_image_extent;
+                let clamped_extent = vk::Extent2D {
+                    width: extent.width.clamp(min.width, max.width),
+                    height: extent.height.clamp(min.height, max.height),
+                };
+                if clamped_extent != extent {
+                    eprintln!(
+                        "Surface does not support extent override {:?}, using {:?}",
+                        extent, clamped_extent
+                    );
+                }
+                clamped_extent
+            }
+            None => match surface_capabilities.current_extent.width {
+                u32::MAX => vk::Extent2D {
+                    width: window.inner_size().width.max(1),
+                    height: window.inner_size().height.max(1),
+                },
+                _ => surface_capabilities.current_extent,
             },
-            _ => surface_capabilities.current_extent,
         };
 
         let pre_transform = if surface_capabilities
@@ -57,31 +150,51 @@ impl SwapchainComponents {
             surface_capabilities.current_transform
         };
 
+        // transfer destination allows copying an owned color target into the presented image,
+        // transfer source allows reading presented frames back
+        let image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | (surface_capabilities.supported_usage_flags
+                & (vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC));
+
         let present_modes = unsafe {
             surface_loader
                 .get_physical_device_surface_present_modes(physical_device, surface)
                 .unwrap()
         };
 
+        // FIFO is the only mode every surface is required to support
         let present_mode = present_modes
             .iter()
             .cloned()
-            .find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
+            .find(|&mode| mode == present_mode_preference.present_mode())
             .unwrap_or(vk::PresentModeKHR::FIFO);
 
-        let swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
+        // images shared by separate graphics and present families are concurrent, so no ownership
+        // transfer is needed and the render finished semaphore alone orders present after drawing
+        let queue_family_indices = [graphics_queue_family_index, present_queue_family_index];
+        let image_sharing_mode = match graphics_queue_family_index == present_queue_family_index {
+            true => vk::SharingMode::EXCLUSIVE,
+            false => vk::SharingMode::CONCURRENT,
+        };
+
+        let mut swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
             .surface(surface)
             .min_image_count(desired_image_count)
             .image_color_space(surface_format.color_space)
             .image_format(surface_format.format)
             .image_extent(surface_resolution)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
-            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .image_usage(image_usage)
+            .image_sharing_mode(image_sharing_mode)
             .pre_transform(pre_transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
             .present_mode(present_mode)
             .clipped(true)
-            .image_array_layers(1);
+            .image_array_layers(1)
+            .old_swapchain(old_swapchain);
+        if image_sharing_mode == vk::SharingMode::CONCURRENT {
+            swapchain_create_info =
+                swapchain_create_info.queue_family_indices(&queue_family_indices);
+        }
 
         let swapchain = unsafe {
             swapchain_loader
@@ -115,12 +228,85 @@ impl SwapchainComponents {
             })
             .collect();
 
-        SwapchainComponents {
+        Ok(SwapchainComponents {
             swapchain,
             present_image_views,
             present_images,
             surface_resolution,
             surface_format,
+            image_usage,
+            present_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            offscreen_image_memory: None,
+        })
+    }
+    pub fn new_offscreen(
+        device: &ash::Device,
+        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        extent: vk::Extent2D,
+    ) -> SwapchainComponents {
+        // transfer source for reading frames back, transfer destination for the accumulation copy
+        let image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | vk::ImageUsageFlags::TRANSFER_SRC
+            | vk::ImageUsageFlags::TRANSFER_DST;
+        let image_create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(OFFSCREEN_FORMAT)
+            .extent(extent.into())
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(image_usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let image = unsafe { device.create_image(&image_create_info, None).unwrap() };
+
+        let image_memory_reqs = unsafe { device.get_image_memory_requirements(image) };
+
+        let image_memory_index = find_memorytype_index(
+            &image_memory_reqs,
+            physical_device_memory_properties,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .expect("Cannot find suitable memory index for offscreen image");
+
+        let image_allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(image_memory_reqs.size)
+            .memory_type_index(image_memory_index);
+
+        let image_memory = unsafe { device.allocate_memory(&image_allocate_info, None).unwrap() };
+
+        unsafe {
+            device
+                .bind_image_memory(image, image_memory, 0)
+                .expect("Failed to bind offscreen image memory")
+        };
+
+        let image_view_info = vk::ImageViewCreateInfo::default()
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(OFFSCREEN_FORMAT)
+            .subresource_range(
+                vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .level_count(1)
+                    .layer_count(1),
+            )
+            .image(image);
+
+        let image_view = unsafe { device.create_image_view(&image_view_info, None).unwrap() };
+
+        SwapchainComponents {
+            swapchain: vk::SwapchainKHR::null(),
+            present_images: vec![image],
+            present_image_views: vec![image_view],
+            surface_format: vk::SurfaceFormatKHR {
+                format: OFFSCREEN_FORMAT,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            },
+            surface_resolution: extent,
+            image_usage,
+            present_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            offscreen_image_memory: Some(image_memory),
         }
     }
     pub fn get_aspect_ratio(&self) -> f32 {
@@ -129,11 +315,113 @@ impl SwapchainComponents {
     }
     pub fn cleanup(&self, device: &ash::Device, swapchain_loader: &khr::swapchain::Device) {
         unsafe {
-            device.device_wait_idle().unwrap();
             for &view in self.present_image_views.iter() {
                 device.destroy_image_view(view, None);
             }
-            swapchain_loader.destroy_swapchain(self.swapchain, None)
+            match self.offscreen_image_memory {
+                Some(offscreen_image_memory) => {
+                    device.destroy_image(self.present_images[0], None);
+                    device.free_memory(offscreen_image_memory, None);
+                }
+                None => swapchain_loader.destroy_swapchain(self.swapchain, None),
+            }
+        };
+    }
+}
+
+// Shaders write linear color. An sRGB format in the SRGB_NONLINEAR color space has the hardware
+// encode it on write, with any other format the fragment shaders encode it themselves, see
+// shaders::ENCODE_SRGB_CONSTANT_ID. None when the surface reports no formats at all.
+fn select_surface_format(surface_formats: &[vk::SurfaceFormatKHR]) -> Option<vk::SurfaceFormatKHR> {
+    surface_formats
+        .iter()
+        .cloned()
+        .find(|surface_format| {
+            is_srgb_format(surface_format.format)
+                && surface_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+        })
+        .or(surface_formats.first().cloned())
+}
+
+// Formats whose writes are sRGB encoded and reads decoded by the hardware
+pub fn is_srgb_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32
+            | vk::Format::B8G8R8_SRGB
+            | vk::Format::R8G8B8_SRGB
+    )
+}
+
+// Whether shaders writing linear color to format have to sRGB encode it themselves. sRGB formats
+// encode on write and floating point formats store linear color, other formats are presented
+// as if they held sRGB encoded color.
+pub fn needs_srgb_encoding(format: vk::Format) -> bool {
+    !is_srgb_format(format)
+        && !matches!(
+            format,
+            vk::Format::R16G16B16A16_SFLOAT
+                | vk::Format::R32G32B32A32_SFLOAT
+                | vk::Format::B10G11R11_UFLOAT_PACK32
+        )
+}
+
+// Clamped to what the surface supports, a max_image_count of 0 means there is no maximum
+fn swapchain_image_count(
+    desired_image_count: Option<u32>,
+    surface_capabilities: &vk::SurfaceCapabilitiesKHR,
+) -> u32 {
+    let image_count = desired_image_count
+        .unwrap_or(surface_capabilities.min_image_count + 1)
+        .max(surface_capabilities.min_image_count);
+    match surface_capabilities.max_image_count {
+        0 => image_count,
+        max_image_count => image_count.min(max_image_count),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_count_is_clamped_to_the_surface() {
+        let surface_capabilities = vk::SurfaceCapabilitiesKHR::default()
+            .min_image_count(2)
+            .max_image_count(3);
+        assert_eq!(swapchain_image_count(None, &surface_capabilities), 3);
+        assert_eq!(swapchain_image_count(Some(1), &surface_capabilities), 2);
+        assert_eq!(swapchain_image_count(Some(8), &surface_capabilities), 3);
+        let unbounded = surface_capabilities.max_image_count(0);
+        assert_eq!(swapchain_image_count(Some(8), &unbounded), 8);
+    }
+
+    #[test]
+    fn surface_format_prefers_srgb_and_needs_a_format() {
+        assert_eq!(select_surface_format(&[]), None);
+        let unorm = vk::SurfaceFormatKHR {
+            format: vk::Format::B8G8R8A8_UNORM,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        };
+        let srgb = vk::SurfaceFormatKHR {
+            format: vk::Format::B8G8R8A8_SRGB,
+            ..unorm
         };
+        assert_eq!(select_surface_format(&[unorm]), Some(unorm));
+        assert_eq!(select_surface_format(&[unorm, srgb]), Some(srgb));
+        let rgba_srgb = vk::SurfaceFormatKHR {
+            format: vk::Format::R8G8B8A8_SRGB,
+            ..unorm
+        };
+        assert_eq!(select_surface_format(&[unorm, rgba_srgb]), Some(rgba_srgb));
+    }
+
+    #[test]
+    fn only_unorm_formats_need_srgb_encoding() {
+        assert!(needs_srgb_encoding(vk::Format::B8G8R8A8_UNORM));
+        assert!(!needs_srgb_encoding(vk::Format::B8G8R8A8_SRGB));
+        assert!(!needs_srgb_encoding(vk::Format::R16G16B16A16_SFLOAT));
     }
 }
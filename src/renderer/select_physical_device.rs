@@ -1,2 +1,217 @@
-use ash::vk::{self, PhysicalDeviceType};
+use ash::{
+    khr::surface,
+    vk::{self, PhysicalDeviceType},
+};
 
+// Summary of a physical device for letting the user pick one,
+// device_id is what UserSettings::preferred_physical_device_id expects.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub device_id: u32,
+    pub device_name: String,
+    pub device_type: PhysicalDeviceType,
+    pub has_graphics_queue: bool,
+    // a queue family of the device can present to the surface, false when headless
+    pub has_surface_support: bool,
+    // devices without it are never selected
+    pub supports_dynamic_rendering: bool,
+}
+
+// Doesn't need a Renderer, so the device can be chosen before creating one. instance is any
+// instance created from entry, with VK_KHR_surface enabled when surface is passed.
+pub fn enumerate_devices(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    surface: Option<vk::SurfaceKHR>,
+) -> Vec<DeviceInfo> {
+    let surface_loader = surface::Instance::new(entry, instance);
+    let physical_devices = unsafe { instance.enumerate_physical_devices().unwrap() };
+    physical_devices
+        .iter()
+        .map(|&physical_device| {
+            let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+            let queue_family_properties =
+                unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+            let graphics_queue_family_indices: Vec<u32> = queue_family_properties
+                .iter()
+                .enumerate()
+                .filter(|(_, property)| property.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+                .map(|(i, _)| i as u32)
+                .collect();
+            let has_surface_support = surface.is_some_and(|surface| {
+                (0..queue_family_properties.len() as u32).any(|i| unsafe {
+                    surface_loader
+                        .get_physical_device_surface_support(physical_device, i, surface)
+                        .unwrap_or(false)
+                })
+            });
+            DeviceInfo {
+                device_id: properties.device_id,
+                device_name: properties
+                    .device_name_as_c_str()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                device_type: properties.device_type,
+                has_graphics_queue: !graphics_queue_family_indices.is_empty(),
+                has_surface_support,
+                supports_dynamic_rendering: supports_dynamic_rendering(instance, physical_device),
+            }
+        })
+        .collect()
+}
+
+// Rendering goes through the core Vulkan 1.3 cmd_begin_rendering, so a 1.2 driver exposing only
+// VK_KHR_dynamic_rendering is not enough.
+pub fn supports_dynamic_rendering(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> bool {
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    if !is_at_least_vulkan_1_3(properties.api_version) {
+        return false;
+    }
+    let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+    let mut features2 =
+        vk::PhysicalDeviceFeatures2::default().push_next(&mut dynamic_rendering_features);
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+    dynamic_rendering_features.dynamic_rendering == vk::TRUE
+}
+
+fn is_at_least_vulkan_1_3(api_version: u32) -> bool {
+    (
+        vk::api_version_major(api_version),
+        vk::api_version_minor(api_version),
+    ) >= (1, 3)
+}
+
+// Graphics, present and transfer queue family indices for a device. A family with GRAPHICS
+// that also supports the surface is preferred for both graphics and present, else the first
+// family with GRAPHICS and the first family supporting the surface are used. The transfer family
+// is preferably dedicated, having TRANSFER without GRAPHICS or COMPUTE, else any family with
+// TRANSFER but no GRAPHICS.
+pub fn select_queue_families(
+    queue_family_properties: &[vk::QueueFamilyProperties],
+    supports_surface: impl Fn(usize) -> bool,
+) -> (Option<usize>, Option<usize>, Option<usize>) {
+    let supports_graphics = |i: usize| {
+        queue_family_properties[i]
+            .queue_flags
+            .contains(vk::QueueFlags::GRAPHICS)
+    };
+    let family_indices = 0..queue_family_properties.len();
+    let (graphics_queue_family_index, present_queue_family_index) = match family_indices
+        .clone()
+        .find(|&i| supports_graphics(i) && supports_surface(i))
+    {
+        Some(i) => (Some(i), Some(i)),
+        None => (
+            family_indices.clone().find(|&i| supports_graphics(i)),
+            family_indices.clone().find(|&i| supports_surface(i)),
+        ),
+    };
+    let transfer_only = |property: &vk::QueueFamilyProperties| {
+        property.queue_flags.contains(vk::QueueFlags::TRANSFER)
+            && !property.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+    };
+    let transfer_queue_family_index = queue_family_properties
+        .iter()
+        .position(|property| {
+            transfer_only(property) && !property.queue_flags.contains(vk::QueueFlags::COMPUTE)
+        })
+        .or_else(|| queue_family_properties.iter().position(transfer_only));
+    (
+        graphics_queue_family_index,
+        present_queue_family_index,
+        transfer_queue_family_index,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn families(flags: &[vk::QueueFlags]) -> Vec<vk::QueueFamilyProperties> {
+        flags
+            .iter()
+            .map(|&queue_flags| vk::QueueFamilyProperties {
+                queue_flags,
+                queue_count: 1,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn prefers_dedicated_transfer_family() {
+        let all = vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+        let compute = vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+        let properties = families(&[all, compute, vk::QueueFlags::TRANSFER, all]);
+        assert_eq!(
+            select_queue_families(&properties, |_| true),
+            (Some(0), Some(0), Some(2))
+        );
+        // the graphics family doesn't affect which transfer family is picked
+        assert_eq!(
+            select_queue_families(&properties, |i| i == 3),
+            (Some(3), Some(3), Some(2))
+        );
+    }
+
+    #[test]
+    fn dynamic_rendering_needs_vulkan_1_3() {
+        assert!(!is_at_least_vulkan_1_3(vk::make_api_version(0, 1, 2, 198)));
+        assert!(is_at_least_vulkan_1_3(vk::make_api_version(0, 1, 3, 0)));
+        assert!(is_at_least_vulkan_1_3(vk::make_api_version(0, 1, 4, 0)));
+    }
+
+    #[test]
+    fn falls_back_to_non_graphics_transfer_family() {
+        let all = vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+        let compute = vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+        assert_eq!(
+            select_queue_families(&families(&[all, compute]), |_| true),
+            (Some(0), Some(0), Some(1))
+        );
+        assert_eq!(
+            select_queue_families(&families(&[all]), |_| true),
+            (Some(0), Some(0), None)
+        );
+        assert_eq!(
+            select_queue_families(&families(&[all]), |_| false),
+            (Some(0), None, None)
+        );
+    }
+
+    #[test]
+    fn presents_from_another_family_without_a_presenting_graphics_family() {
+        let all = vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+        let compute = vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+        let properties = families(&[all, compute, all]);
+        assert_eq!(
+            select_queue_families(&properties, |i| i == 1),
+            (Some(0), Some(1), Some(1))
+        );
+        // a graphics family that can present is preferred over splitting the queues
+        assert_eq!(
+            select_queue_families(&properties, |i| i != 0),
+            (Some(2), Some(2), Some(1))
+        );
+    }
+
+    #[test]
+    fn devices_are_enumerated_without_a_renderer() {
+        let Ok(entry) = (unsafe { ash::Entry::load() }) else {
+            return;
+        };
+        let Ok(instance) =
+            (unsafe { entry.create_instance(&vk::InstanceCreateInfo::default(), None) })
+        else {
+            return;
+        };
+        let devices = enumerate_devices(&entry, &instance, None);
+        unsafe { instance.destroy_instance(None) };
+        // nothing can present without a surface
+        assert!(devices.iter().all(|device| !device.has_surface_support));
+    }
+}
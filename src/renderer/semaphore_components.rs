@@ -1,35 +1,41 @@
 use ash::vk;
 
+use super::command_buffer_components::MAX_FRAMES_IN_FLIGHT;
+
+// One pair per frame in flight
 pub struct SemaphoreComponents {
-    pub present_complete_semaphore: vk::Semaphore,
-    pub rendering_complete_semaphore: vk::Semaphore,
+    pub present_complete_semaphores: Vec<vk::Semaphore>,
+    pub rendering_complete_semaphores: Vec<vk::Semaphore>,
 }
 
 impl SemaphoreComponents {
     pub fn new(device: &ash::Device) -> SemaphoreComponents {
         let semaphore_create_info = vk::SemaphoreCreateInfo::default();
 
-        let present_complete_semaphore = unsafe {
-            device
-                .create_semaphore(&semaphore_create_info, None)
-                .unwrap()
-        };
-
-        let rendering_complete_semaphore = unsafe {
-            device
-                .create_semaphore(&semaphore_create_info, None)
-                .unwrap()
+        let create_semaphores = || -> Vec<vk::Semaphore> {
+            (0..MAX_FRAMES_IN_FLIGHT)
+                .map(|_| unsafe {
+                    device
+                        .create_semaphore(&semaphore_create_info, None)
+                        .unwrap()
+                })
+                .collect()
         };
 
         SemaphoreComponents {
-            present_complete_semaphore,
-            rendering_complete_semaphore,
+            present_complete_semaphores: create_semaphores(),
+            rendering_complete_semaphores: create_semaphores(),
         }
     }
     pub fn cleanup(&self, device: &ash::Device) {
         unsafe {
-            device.destroy_semaphore(self.present_complete_semaphore, None);
-            device.destroy_semaphore(self.rendering_complete_semaphore, None);
+            for &semaphore in self
+                .present_complete_semaphores
+                .iter()
+                .chain(self.rendering_complete_semaphores.iter())
+            {
+                device.destroy_semaphore(semaphore, None);
+            }
         }
     }
 }
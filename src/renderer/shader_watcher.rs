@@ -0,0 +1,47 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use super::shaders::{
+    FRAGMENT_SHADER_FILE_NAME, FULLSCREEN_VERTEX_SHADER_FILE_NAME, GRID_VERTEX_SHADER_FILE_NAME,
+    SKYBOX_FRAGMENT_SHADER_FILE_NAME, SKYBOX_VERTEX_SHADER_FILE_NAME, VERTEX_SHADER_FILE_NAME,
+};
+
+// Polls the modification times of the shader sources, a few stat calls are cheap enough to make
+// every frame
+pub struct ShaderWatcher {
+    directory: PathBuf,
+    // newest modification time seen, None until the sources have been loaded once
+    last_modified: Option<SystemTime>,
+}
+
+impl ShaderWatcher {
+    pub fn new(directory: &Path) -> Self {
+        Self {
+            directory: directory.to_path_buf(),
+            last_modified: None,
+        }
+    }
+    // True on the first call and whenever a source has been written since the previous true
+    pub fn poll(&mut self) -> bool {
+        let last_modified = [
+            VERTEX_SHADER_FILE_NAME,
+            FULLSCREEN_VERTEX_SHADER_FILE_NAME,
+            FRAGMENT_SHADER_FILE_NAME,
+            SKYBOX_VERTEX_SHADER_FILE_NAME,
+            SKYBOX_FRAGMENT_SHADER_FILE_NAME,
+            GRID_VERTEX_SHADER_FILE_NAME,
+        ]
+        .iter()
+        .filter_map(|file_name| fs::metadata(self.directory.join(file_name)).ok())
+        .filter_map(|metadata| metadata.modified().ok())
+        .max();
+        if last_modified.is_none() || last_modified == self.last_modified {
+            return false;
+        }
+        self.last_modified = last_modified;
+        true
+    }
+}
@@ -1,21 +1,282 @@
+use std::{ffi::CString, fs, path::Path};
+
 use ash::vk;
 
+use super::graphics_pipeline_components::PipelineShaderStages;
+
+// Default UserSettings::shader_directory, the GLSL sources in the crate
+pub const SHADER_DIRECTORY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/shaders");
+pub const VERTEX_SHADER_FILE_NAME: &str = "vertex_shader.glsl";
+pub const FULLSCREEN_VERTEX_SHADER_FILE_NAME: &str = "fullscreen_vertex_shader.glsl";
+pub const FRAGMENT_SHADER_FILE_NAME: &str = "fragment_shader.glsl";
+pub const SKYBOX_VERTEX_SHADER_FILE_NAME: &str = "skybox_vertex_shader.glsl";
+pub const SKYBOX_FRAGMENT_SHADER_FILE_NAME: &str = "skybox_fragment_shader.glsl";
+pub const GRID_VERTEX_SHADER_FILE_NAME: &str = "grid_vertex_shader.glsl";
+#[cfg(feature = "shaderc")]
+const POST_EFFECT_VERTEX_SHADER_FILE_NAME: &str = "post_effect_vertex_shader.glsl";
+// names the user's post effect in shaderc's diagnostics
+#[cfg(feature = "shaderc")]
+const POST_EFFECT_FRAGMENT_SHADER_NAME: &str = "post_effect_fragment_shader.glsl";
+// built in post effect for Renderer::set_post_effect, tuned with Renderer::set_fxaa_params
+#[cfg(feature = "shaderc")]
+pub const FXAA_POST_EFFECT_SOURCE: &str = include_str!("../../shaders/fxaa_fragment_shader.glsl");
+
+#[cfg(feature = "shaderc")]
+struct ShaderSources {
+    vertex: String,
+    fullscreen_vertex: String,
+    fragment: String,
+    skybox_vertex: String,
+    skybox_fragment: String,
+    grid_vertex: String,
+}
+
+// Constant ids declared with layout (constant_id = N) in the shaders
+pub const LIGHTING_ENABLED_CONSTANT_ID: u32 = 0;
+// set by the skybox pipeline itself rather than the user's SpecializationConstants
+pub const SKYBOX_DEPTH_CONSTANT_ID: u32 = 1;
+// also set by the pipelines and the post effect pass, true when the color attachment format is
+// neither sRGB nor floating point so the fragment shaders encode their linear output themselves
+pub const ENCODE_SRGB_CONSTANT_ID: u32 = 2;
+// ids the user's SpecializationConstants can't set
+const RESERVED_CONSTANT_IDS: [u32; 2] = [SKYBOX_DEPTH_CONSTANT_ID, ENCODE_SRGB_CONSTANT_ID];
+
+// Only SPIR-V built with other entry point names needs these, GLSL always compiles to main
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ShaderEntryPoints {
+    pub vertex: CString,
+    pub fullscreen_vertex: CString,
+    pub fragment: CString,
+    pub skybox_vertex: CString,
+    pub skybox_fragment: CString,
+    pub grid_vertex: CString,
+}
+
+impl Default for ShaderEntryPoints {
+    fn default() -> Self {
+        Self {
+            vertex: c"main".to_owned(),
+            fullscreen_vertex: c"main".to_owned(),
+            fragment: c"main".to_owned(),
+            skybox_vertex: c"main".to_owned(),
+            skybox_fragment: c"main".to_owned(),
+            grid_vertex: c"main".to_owned(),
+        }
+    }
+}
+
+// Specialization constants applied to every stage when the pipelines are created, constants
+// that are not set keep the default value from the shader. SKYBOX_DEPTH_CONSTANT_ID and
+// ENCODE_SRGB_CONSTANT_ID are reserved for the renderer. Saved as (constant_id, bytes) pairs,
+// which go through the same checks as the with_ methods when loaded.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(try_from = "Vec<(u32, Vec<u8>)>", into = "Vec<(u32, Vec<u8>)>")
+)]
+pub struct SpecializationConstants {
+    map_entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u8>,
+}
+
+impl SpecializationConstants {
+    pub fn with_bool(self, constant_id: u32, value: bool) -> Self {
+        self.with_bytes(constant_id, &vk::Bool32::from(value).to_ne_bytes())
+    }
+    pub fn with_u32(self, constant_id: u32, value: u32) -> Self {
+        self.with_bytes(constant_id, &value.to_ne_bytes())
+    }
+    pub fn with_f32(self, constant_id: u32, value: f32) -> Self {
+        self.with_bytes(constant_id, &value.to_ne_bytes())
+    }
+    // Only for the renderer's own reserved constants
+    pub(super) fn with_reserved_bool(self, constant_id: u32, value: bool) -> Self {
+        self.try_with_any_bytes(constant_id, &vk::Bool32::from(value).to_ne_bytes())
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+    pub(super) fn with_reserved_f32(self, constant_id: u32, value: f32) -> Self {
+        self.try_with_any_bytes(constant_id, &value.to_ne_bytes())
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+    fn with_bytes(self, constant_id: u32, bytes: &[u8]) -> Self {
+        self.try_with_bytes(constant_id, bytes)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+    fn try_with_bytes(self, constant_id: u32, bytes: &[u8]) -> Result<Self, String> {
+        if RESERVED_CONSTANT_IDS.contains(&constant_id) {
+            return Err(format!(
+                "Specialization constant {constant_id} is reserved for the renderer"
+            ));
+        }
+        self.try_with_any_bytes(constant_id, bytes)
+    }
+    fn try_with_any_bytes(mut self, constant_id: u32, bytes: &[u8]) -> Result<Self, String> {
+        if self
+            .map_entries
+            .iter()
+            .any(|map_entry| map_entry.constant_id == constant_id)
+        {
+            return Err(format!(
+                "Specialization constant {constant_id} is set twice"
+            ));
+        }
+        self.map_entries.push(
+            vk::SpecializationMapEntry::default()
+                .constant_id(constant_id)
+                .offset(self.data.len() as u32)
+                .size(bytes.len()),
+        );
+        self.data.extend_from_slice(bytes);
+        Ok(self)
+    }
+    pub fn info(&self) -> vk::SpecializationInfo<'_> {
+        vk::SpecializationInfo::default()
+            .map_entries(&self.map_entries)
+            .data(&self.data)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<SpecializationConstants> for Vec<(u32, Vec<u8>)> {
+    fn from(specialization_constants: SpecializationConstants) -> Self {
+        specialization_constants
+            .map_entries
+            .iter()
+            .map(|map_entry| {
+                let offset = map_entry.offset as usize;
+                (
+                    map_entry.constant_id,
+                    specialization_constants.data[offset..offset + map_entry.size].to_vec(),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<Vec<(u32, Vec<u8>)>> for SpecializationConstants {
+    type Error = String;
+
+    fn try_from(constants: Vec<(u32, Vec<u8>)>) -> Result<Self, String> {
+        constants.into_iter().try_fold(
+            SpecializationConstants::default(),
+            |specialization_constants, (constant_id, bytes)| {
+                specialization_constants.try_with_bytes(constant_id, &bytes)
+            },
+        )
+    }
+}
+
 pub struct Shaders {
     vertex_shader_module: vk::ShaderModule,
+    fullscreen_vertex_shader_module: vk::ShaderModule,
     fragment_shader_module: vk::ShaderModule,
+    skybox_vertex_shader_module: vk::ShaderModule,
+    skybox_fragment_shader_module: vk::ShaderModule,
+    grid_vertex_shader_module: vk::ShaderModule,
+    entry_points: ShaderEntryPoints,
 }
 
 impl Shaders {
-    pub fn new(device: &ash::Device) -> Self {
+    #[cfg(feature = "shaderc")]
+    // Compiles the GLSL embedded at build time, errors carry shaderc's diagnostics
+    pub fn new(device: &ash::Device) -> Result<Self, String> {
+        Self::from_sources(
+            device,
+            &ShaderSources {
+                vertex: include_str!("../../shaders/vertex_shader.glsl").to_string(),
+                fullscreen_vertex: include_str!("../../shaders/fullscreen_vertex_shader.glsl")
+                    .to_string(),
+                fragment: include_str!("../../shaders/fragment_shader.glsl").to_string(),
+                skybox_vertex: include_str!("../../shaders/skybox_vertex_shader.glsl").to_string(),
+                skybox_fragment: include_str!("../../shaders/skybox_fragment_shader.glsl")
+                    .to_string(),
+                grid_vertex: include_str!("../../shaders/grid_vertex_shader.glsl").to_string(),
+            },
+        )
+    }
+    #[cfg(feature = "shaderc")]
+    // Reads the GLSL at runtime instead of the copy embedded at build time
+    pub fn from_directory(device: &ash::Device, directory: &Path) -> Result<Self, String> {
+        let read = |file_name: &str| {
+            let path = directory.join(file_name);
+            fs::read_to_string(&path)
+                .map_err(|error| format!("Failed to read {}: {error}", path.display()))
+        };
+        Self::from_sources(
+            device,
+            &ShaderSources {
+                vertex: read(VERTEX_SHADER_FILE_NAME)?,
+                fullscreen_vertex: read(FULLSCREEN_VERTEX_SHADER_FILE_NAME)?,
+                fragment: read(FRAGMENT_SHADER_FILE_NAME)?,
+                skybox_vertex: read(SKYBOX_VERTEX_SHADER_FILE_NAME)?,
+                skybox_fragment: read(SKYBOX_FRAGMENT_SHADER_FILE_NAME)?,
+                grid_vertex: read(GRID_VERTEX_SHADER_FILE_NAME)?,
+            },
+        )
+    }
+    #[cfg(feature = "shaderc")]
+    fn from_sources(device: &ash::Device, sources: &ShaderSources) -> Result<Self, String> {
+        // everything is compiled before any module is created, so errors leak nothing
         let vertex_shader_code = compile_shader(
-            &include_str!("../../shaders/vertex_shader.glsl"),
+            &sources.vertex,
             shaderc::ShaderKind::Vertex,
-            "vertex_shader.glsl",
+            VERTEX_SHADER_FILE_NAME,
             "main",
-        );
-
-        let vertex_shader_info =
-            vk::ShaderModuleCreateInfo::default().code(&vertex_shader_code.as_binary());
+        )?;
+        let fullscreen_vertex_shader_code = compile_shader(
+            &sources.fullscreen_vertex,
+            shaderc::ShaderKind::Vertex,
+            FULLSCREEN_VERTEX_SHADER_FILE_NAME,
+            "main",
+        )?;
+        let fragment_shader_code = compile_shader(
+            &sources.fragment,
+            shaderc::ShaderKind::Fragment,
+            FRAGMENT_SHADER_FILE_NAME,
+            "main",
+        )?;
+        let skybox_vertex_shader_code = compile_shader(
+            &sources.skybox_vertex,
+            shaderc::ShaderKind::Vertex,
+            SKYBOX_VERTEX_SHADER_FILE_NAME,
+            "main",
+        )?;
+        let skybox_fragment_shader_code = compile_shader(
+            &sources.skybox_fragment,
+            shaderc::ShaderKind::Fragment,
+            SKYBOX_FRAGMENT_SHADER_FILE_NAME,
+            "main",
+        )?;
+        let grid_vertex_shader_code = compile_shader(
+            &sources.grid_vertex,
+            shaderc::ShaderKind::Vertex,
+            GRID_VERTEX_SHADER_FILE_NAME,
+            "main",
+        )?;
+        Ok(Self::from_spirv(
+            device,
+            vertex_shader_code.as_binary(),
+            fullscreen_vertex_shader_code.as_binary(),
+            fragment_shader_code.as_binary(),
+            skybox_vertex_shader_code.as_binary(),
+            skybox_fragment_shader_code.as_binary(),
+            grid_vertex_shader_code.as_binary(),
+        ))
+    }
+    // Skips shaderc entirely, the code has to target the same interface as the GLSL shaders
+    pub fn from_spirv(
+        device: &ash::Device,
+        vertex_shader_code: &[u32],
+        fullscreen_vertex_shader_code: &[u32],
+        fragment_shader_code: &[u32],
+        skybox_vertex_shader_code: &[u32],
+        skybox_fragment_shader_code: &[u32],
+        grid_vertex_shader_code: &[u32],
+    ) -> Self {
+        let vertex_shader_info = vk::ShaderModuleCreateInfo::default().code(vertex_shader_code);
 
         let vertex_shader_module = unsafe {
             device
@@ -23,15 +284,16 @@ impl Shaders {
                 .expect("Failed to create vertex shader module")
         };
 
-        let fragment_shader_code = compile_shader(
-            &include_str!("../../shaders/fragment_shader.glsl"),
-            shaderc::ShaderKind::Fragment,
-            "fragment_shader.glsl",
-            "main",
-        );
+        let fullscreen_vertex_shader_info =
+            vk::ShaderModuleCreateInfo::default().code(fullscreen_vertex_shader_code);
+
+        let fullscreen_vertex_shader_module = unsafe {
+            device
+                .create_shader_module(&fullscreen_vertex_shader_info, None)
+                .expect("Failed to create fullscreen vertex shader module")
+        };
 
-        let fragment_shader_info =
-            vk::ShaderModuleCreateInfo::default().code(&fragment_shader_code.as_binary());
+        let fragment_shader_info = vk::ShaderModuleCreateInfo::default().code(fragment_shader_code);
 
         let fragment_shader_module = unsafe {
             device
@@ -39,43 +301,254 @@ impl Shaders {
                 .expect("Failed to create fragment shader module")
         };
 
+        let skybox_vertex_shader_info =
+            vk::ShaderModuleCreateInfo::default().code(skybox_vertex_shader_code);
+
+        let skybox_vertex_shader_module = unsafe {
+            device
+                .create_shader_module(&skybox_vertex_shader_info, None)
+                .expect("Failed to create skybox vertex shader module")
+        };
+
+        let skybox_fragment_shader_info =
+            vk::ShaderModuleCreateInfo::default().code(skybox_fragment_shader_code);
+
+        let skybox_fragment_shader_module = unsafe {
+            device
+                .create_shader_module(&skybox_fragment_shader_info, None)
+                .expect("Failed to create skybox fragment shader module")
+        };
+
+        let grid_vertex_shader_info =
+            vk::ShaderModuleCreateInfo::default().code(grid_vertex_shader_code);
+
+        let grid_vertex_shader_module = unsafe {
+            device
+                .create_shader_module(&grid_vertex_shader_info, None)
+                .expect("Failed to create grid vertex shader module")
+        };
+
         Self {
             vertex_shader_module,
+            fullscreen_vertex_shader_module,
             fragment_shader_module,
+            skybox_vertex_shader_module,
+            skybox_fragment_shader_module,
+            grid_vertex_shader_module,
+            entry_points: ShaderEntryPoints::default(),
+        }
+    }
+    // Reads <shader name>.spv for each GLSL file name, e.g. compiled with
+    // glslc vertex_shader.glsl -o vertex_shader.spv
+    pub fn from_spirv_directory(device: &ash::Device, directory: &Path) -> Result<Self, String> {
+        let read = |file_name: &str| {
+            let path = directory.join(file_name).with_extension("spv");
+            let bytes = fs::read(&path)
+                .map_err(|error| format!("Failed to read {}: {error}", path.display()))?;
+            spirv_words(&bytes).map_err(|error| format!("{}: {error}", path.display()))
+        };
+        Ok(Self::from_spirv(
+            device,
+            &read(VERTEX_SHADER_FILE_NAME)?,
+            &read(FULLSCREEN_VERTEX_SHADER_FILE_NAME)?,
+            &read(FRAGMENT_SHADER_FILE_NAME)?,
+            &read(SKYBOX_VERTEX_SHADER_FILE_NAME)?,
+            &read(SKYBOX_FRAGMENT_SHADER_FILE_NAME)?,
+            &read(GRID_VERTEX_SHADER_FILE_NAME)?,
+        ))
+    }
+    pub fn with_entry_points(mut self, entry_points: ShaderEntryPoints) -> Self {
+        self.entry_points = entry_points;
+        self
+    }
+    #[cfg(feature = "shaderc")]
+    pub fn entry_points(&self) -> &ShaderEntryPoints {
+        &self.entry_points
+    }
+    pub fn pipeline_shader_stages(&self) -> PipelineShaderStages<'_> {
+        PipelineShaderStages {
+            interleaved: self.shader_stage_infos(),
+            procedural: self.procedural_shader_stage_infos(),
+            skybox: self.skybox_shader_stage_infos(),
+            grid: self.grid_shader_stage_infos(),
         }
     }
-    pub fn shader_stage_infos(&self) -> Vec<vk::PipelineShaderStageCreateInfo> {
+    pub fn shader_stage_infos(&self) -> Vec<vk::PipelineShaderStageCreateInfo<'_>> {
         vec![
-            vk::PipelineShaderStageCreateInfo {
-                module: self.vertex_shader_module,
-                p_name: c"main".as_ptr(),
-                stage: vk::ShaderStageFlags::VERTEX,
-                ..Default::default()
-            },
-            vk::PipelineShaderStageCreateInfo {
-                module: self.fragment_shader_module,
-                p_name: c"main".as_ptr(),
-                stage: vk::ShaderStageFlags::FRAGMENT,
-                ..Default::default()
-            },
+            vk::PipelineShaderStageCreateInfo::default()
+                .module(self.vertex_shader_module)
+                .name(&self.entry_points.vertex)
+                .stage(vk::ShaderStageFlags::VERTEX),
+            vk::PipelineShaderStageCreateInfo::default()
+                .module(self.fragment_shader_module)
+                .name(&self.entry_points.fragment)
+                .stage(vk::ShaderStageFlags::FRAGMENT),
+        ]
+    }
+    // stages for geometry generated from gl_VertexIndex, used with an empty vertex input state
+    pub fn procedural_shader_stage_infos(&self) -> Vec<vk::PipelineShaderStageCreateInfo<'_>> {
+        vec![
+            vk::PipelineShaderStageCreateInfo::default()
+                .module(self.fullscreen_vertex_shader_module)
+                .name(&self.entry_points.fullscreen_vertex)
+                .stage(vk::ShaderStageFlags::VERTEX),
+            vk::PipelineShaderStageCreateInfo::default()
+                .module(self.fragment_shader_module)
+                .name(&self.entry_points.fragment)
+                .stage(vk::ShaderStageFlags::FRAGMENT),
+        ]
+    }
+    // fullscreen triangle sampling the skybox cubemap, also without vertex input
+    pub fn skybox_shader_stage_infos(&self) -> Vec<vk::PipelineShaderStageCreateInfo<'_>> {
+        vec![
+            vk::PipelineShaderStageCreateInfo::default()
+                .module(self.skybox_vertex_shader_module)
+                .name(&self.entry_points.skybox_vertex)
+                .stage(vk::ShaderStageFlags::VERTEX),
+            vk::PipelineShaderStageCreateInfo::default()
+                .module(self.skybox_fragment_shader_module)
+                .name(&self.entry_points.skybox_fragment)
+                .stage(vk::ShaderStageFlags::FRAGMENT),
+        ]
+    }
+    // world space lines with positions and colors only, lit by nothing
+    pub fn grid_shader_stage_infos(&self) -> Vec<vk::PipelineShaderStageCreateInfo<'_>> {
+        vec![
+            vk::PipelineShaderStageCreateInfo::default()
+                .module(self.grid_vertex_shader_module)
+                .name(&self.entry_points.grid_vertex)
+                .stage(vk::ShaderStageFlags::VERTEX),
+            vk::PipelineShaderStageCreateInfo::default()
+                .module(self.fragment_shader_module)
+                .name(&self.entry_points.fragment)
+                .stage(vk::ShaderStageFlags::FRAGMENT),
         ]
     }
     pub fn cleanup(&self, device: &ash::Device) {
         unsafe {
             device.destroy_shader_module(self.vertex_shader_module, None);
+            device.destroy_shader_module(self.fullscreen_vertex_shader_module, None);
             device.destroy_shader_module(self.fragment_shader_module, None);
+            device.destroy_shader_module(self.skybox_vertex_shader_module, None);
+            device.destroy_shader_module(self.skybox_fragment_shader_module, None);
+            device.destroy_shader_module(self.grid_vertex_shader_module, None);
         }
     }
 }
+// SPIR-V for the post effect pass, the built in fullscreen vertex shader and the user's fragment
+// shader
+#[cfg(feature = "shaderc")]
+pub fn compile_post_effect(fragment_shader_source: &str) -> Result<(Vec<u32>, Vec<u32>), String> {
+    let vertex_shader_code = compile_shader(
+        include_str!("../../shaders/post_effect_vertex_shader.glsl"),
+        shaderc::ShaderKind::Vertex,
+        POST_EFFECT_VERTEX_SHADER_FILE_NAME,
+        "main",
+    )?;
+    let fragment_shader_code = compile_shader(
+        fragment_shader_source,
+        shaderc::ShaderKind::Fragment,
+        POST_EFFECT_FRAGMENT_SHADER_NAME,
+        "main",
+    )?;
+    Ok((
+        vertex_shader_code.as_binary().to_vec(),
+        fragment_shader_code.as_binary().to_vec(),
+    ))
+}
+
+#[cfg(feature = "shaderc")]
 fn compile_shader(
     source_text: &str,
     shader_kind: shaderc::ShaderKind,
     name: &str,
     entry: &str,
-) -> shaderc::CompilationArtifact {
+) -> Result<shaderc::CompilationArtifact, String> {
     let compiler = shaderc::Compiler::new().expect("Failed to create shaderc compiler");
     let options = shaderc::CompileOptions::new().expect("Failed to create shaderc options");
     compiler
         .compile_into_spirv(source_text, shader_kind, name, entry, Some(&options))
-        .expect("Failed to compile shader source")
+        .map_err(|error| error.to_string())
+}
+
+// SPIR-V is a stream of little endian words starting with the magic number
+const SPIRV_MAGIC_NUMBER: u32 = 0x0723_0203;
+
+pub fn spirv_words(bytes: &[u8]) -> Result<Vec<u32>, String> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(format!(
+            "SPIR-V length {} is not a multiple of 4 bytes",
+            bytes.len()
+        ));
+    }
+    let words: Vec<u32> = bytes
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+    match words.first() {
+        Some(&SPIRV_MAGIC_NUMBER) => Ok(words),
+        _ => Err("Missing SPIR-V magic number".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spirv_words_are_validated() {
+        let mut bytes = SPIRV_MAGIC_NUMBER.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&[1, 0, 0, 0]);
+        assert_eq!(spirv_words(&bytes), Ok(vec![SPIRV_MAGIC_NUMBER, 1]));
+        assert!(spirv_words(&bytes[..7]).is_err());
+        assert!(spirv_words(&[1, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn specialization_constants_are_packed_in_order() {
+        let specialization_constants = SpecializationConstants::default()
+            .with_bool(LIGHTING_ENABLED_CONSTANT_ID, false)
+            .with_f32(3, 0.5);
+        let info = specialization_constants.info();
+        assert_eq!(info.map_entry_count, 2);
+        assert_eq!(info.data_size, 8);
+        assert_eq!(specialization_constants.map_entries[1].offset, 4);
+        assert_eq!(&specialization_constants.data[..4], &0u32.to_ne_bytes());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn specialization_constants_round_trip_through_json() {
+        let specialization_constants = SpecializationConstants::default()
+            .with_bool(LIGHTING_ENABLED_CONSTANT_ID, false)
+            .with_f32(3, 0.5);
+        let json = serde_json::to_string(&specialization_constants).unwrap();
+        let loaded: SpecializationConstants = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            Vec::<(u32, Vec<u8>)>::from(loaded),
+            Vec::from(specialization_constants)
+        );
+    }
+
+    #[cfg(feature = "shaderc")]
+    #[test]
+    fn fxaa_post_effect_compiles() {
+        compile_post_effect(FXAA_POST_EFFECT_SOURCE).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "reserved")]
+    fn reserved_specialization_constants_are_rejected() {
+        let _ = SpecializationConstants::default().with_bool(ENCODE_SRGB_CONSTANT_ID, false);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn duplicate_specialization_constants_are_rejected() {
+        assert!(
+            serde_json::from_str::<SpecializationConstants>("[[3,[0,0,0,63]],[3,[1]]]").is_err()
+        );
+        // the renderer sets these itself
+        assert!(serde_json::from_str::<SpecializationConstants>("[[2,[0,0,0,0]]]").is_err());
+    }
 }
@@ -0,0 +1,93 @@
+use super::{
+    command_buffer_components::UploadCommands,
+    index_buffer_components::{smallest_index_type, Index, IndexBufferComponents},
+    memory_allocator::MemoryAllocator,
+    vertex_buffer_components::{VertexAttribs, VertexBufferComponents},
+    DeviceLost,
+};
+
+// Geometry with positions and the remaining attributes in separate vertex buffers.
+// Positions are bound at binding 0 and attributes at binding 1.
+// Geometry without vertices or indices is kept, but skipped when drawing.
+pub struct SplitGeometryComponents {
+    pub position_buffer_components: VertexBufferComponents<[f32; 3]>,
+    pub attribute_buffer_components: VertexBufferComponents<VertexAttribs>,
+    pub index_buffer_components: IndexBufferComponents,
+}
+
+impl SplitGeometryComponents {
+    pub fn new(
+        device: &ash::Device,
+        memory_allocator: &mut MemoryAllocator,
+        positions: &[[f32; 3]],
+        attributes: &[VertexAttribs],
+        indices: &[Index],
+        upload_commands: &UploadCommands,
+    ) -> Result<SplitGeometryComponents, DeviceLost> {
+        // checked by Renderer::set_geometry_split
+        debug_assert_eq!(positions.len(), attributes.len());
+
+        // Vulkan doesn't allow buffers of size 0, so empty geometry gets room for one element
+        let mut split_geometry_components = SplitGeometryComponents {
+            position_buffer_components: VertexBufferComponents::new_unintialized(
+                device,
+                memory_allocator,
+                positions.len().max(1),
+            ),
+            attribute_buffer_components: VertexBufferComponents::new_unintialized(
+                device,
+                memory_allocator,
+                attributes.len().max(1),
+            ),
+            index_buffer_components: IndexBufferComponents::new_unintiailized(
+                device,
+                memory_allocator,
+                indices.len().max(1),
+                smallest_index_type(positions.len()),
+            ),
+        };
+        if let Err(device_lost) = split_geometry_components.upload(
+            device,
+            positions,
+            attributes,
+            indices,
+            upload_commands,
+        ) {
+            split_geometry_components.cleanup(device, memory_allocator);
+            return Err(device_lost);
+        }
+        Ok(split_geometry_components)
+    }
+    // Nothing to draw without vertices or indices
+    pub fn is_empty(&self) -> bool {
+        self.index_buffer_components.index_count == 0
+    }
+    fn upload(
+        &mut self,
+        device: &ash::Device,
+        positions: &[[f32; 3]],
+        attributes: &[VertexAttribs],
+        indices: &[Index],
+        upload_commands: &UploadCommands,
+    ) -> Result<(), DeviceLost> {
+        if positions.is_empty() || indices.is_empty() {
+            // a copy of 0 bytes isn't allowed either
+            self.index_buffer_components.index_count = 0;
+            return Ok(());
+        }
+        self.position_buffer_components
+            .update_vertices(device, positions, upload_commands)?;
+        self.attribute_buffer_components
+            .update_vertices(device, attributes, upload_commands)?;
+        self.index_buffer_components
+            .update_indices(device, indices, upload_commands)
+    }
+    pub fn cleanup(&self, device: &ash::Device, memory_allocator: &mut MemoryAllocator) {
+        self.position_buffer_components
+            .cleanup(device, memory_allocator);
+        self.attribute_buffer_components
+            .cleanup(device, memory_allocator);
+        self.index_buffer_components
+            .cleanup(device, memory_allocator);
+    }
+}
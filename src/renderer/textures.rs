@@ -1,49 +1,404 @@
+use anyhow::{anyhow, Context, Result};
 use ash::vk;
 use image::{GenericImageView, ImageReader};
 
-use super::find_memorytype_index;
+use super::{
+    buffer::Buffer,
+    command_buffer_components::SetupContext,
+    device_lost_or_panic,
+    memory_allocator::{Allocation, MemoryAllocator},
+    DeviceLost,
+};
 
-pub fn create_texture(
-    device: &ash::Device,
-    physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
-) {
-    let img = ImageReader::open("../../static/textures/texture.jpg")
-        .unwrap()
+pub const DEFAULT_TEXTURE_PATH: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/static/textures/texture.jpg");
+
+// How textures are filtered and addressed, trilinear and repeating by default. Pixel art
+// wants NEAREST filters and mipmaps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SamplerConfig {
+    #[cfg_attr(feature = "serde", serde(with = "super::vk_serde::filter"))]
+    pub mag_filter: vk::Filter,
+    #[cfg_attr(feature = "serde", serde(with = "super::vk_serde::filter"))]
+    pub min_filter: vk::Filter,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "super::vk_serde::sampler_mipmap_mode")
+    )]
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    // u, v and w
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "super::vk_serde::sampler_address_modes")
+    )]
+    pub address_modes: [vk::SamplerAddressMode; 3],
+    // only used by CLAMP_TO_BORDER, has to be a FLOAT_ color since textures are sampled as floats
+    #[cfg_attr(feature = "serde", serde(with = "super::vk_serde::border_color"))]
+    pub border_color: vk::BorderColor,
+}
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_modes: [vk::SamplerAddressMode::REPEAT; 3],
+            border_color: vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+        }
+    }
+}
+
+impl SamplerConfig {
+    // Replaces what the renderer's textures and device can't sample with
+    pub fn validated(&self) -> SamplerConfig {
+        let mut config = *self;
+        for address_mode in config.address_modes.iter_mut() {
+            // samplerMirrorClampToEdge is never enabled
+            if *address_mode == vk::SamplerAddressMode::MIRROR_CLAMP_TO_EDGE {
+                eprintln!("MIRROR_CLAMP_TO_EDGE is not enabled, using CLAMP_TO_EDGE instead");
+                *address_mode = vk::SamplerAddressMode::CLAMP_TO_EDGE;
+            }
+        }
+        let clamps_to_border = config
+            .address_modes
+            .contains(&vk::SamplerAddressMode::CLAMP_TO_BORDER);
+        let float_border_color = match config.border_color {
+            vk::BorderColor::INT_TRANSPARENT_BLACK => vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+            vk::BorderColor::INT_OPAQUE_BLACK => vk::BorderColor::FLOAT_OPAQUE_BLACK,
+            vk::BorderColor::INT_OPAQUE_WHITE => vk::BorderColor::FLOAT_OPAQUE_WHITE,
+            // custom border colors need VK_EXT_custom_border_color
+            vk::BorderColor::FLOAT_CUSTOM_EXT | vk::BorderColor::INT_CUSTOM_EXT => {
+                vk::BorderColor::FLOAT_TRANSPARENT_BLACK
+            }
+            border_color => border_color,
+        };
+        if clamps_to_border && float_border_color != config.border_color {
+            eprintln!(
+                "Border color {:?} can't be used with float textures, using {float_border_color:?}",
+                config.border_color
+            );
+        }
+        config.border_color = float_border_color;
+        config
+    }
+}
+
+// How a texture's layers are created, viewed and sampled
+struct TextureKind {
+    layer_count: u32,
+    create_flags: vk::ImageCreateFlags,
+    view_type: vk::ImageViewType,
+    // overrides the sampler config's address modes
+    address_mode: Option<vk::SamplerAddressMode>,
+}
+
+const TEXTURE_2D: TextureKind = TextureKind {
+    layer_count: 1,
+    create_flags: vk::ImageCreateFlags::empty(),
+    view_type: vk::ImageViewType::TYPE_2D,
+    address_mode: None,
+};
+
+// clamped so sampling near an edge doesn't blend in the opposite side of the face
+const CUBEMAP: TextureKind = TextureKind {
+    layer_count: 6,
+    create_flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
+    view_type: vk::ImageViewType::CUBE,
+    address_mode: Some(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+};
+
+// Sampled RGBA8 texture, left in SHADER_READ_ONLY_OPTIMAL
+pub struct Texture {
+    pub image: vk::Image,
+    pub allocation: Allocation,
+    pub image_view: vk::ImageView,
+    pub sampler: vk::Sampler,
+}
+
+impl Texture {
+    pub fn new_from_path(
+        setup_context: &mut SetupContext,
+        path: &str,
+        sampler_config: &SamplerConfig,
+        max_anisotropy: Option<f32>,
+    ) -> Result<Texture> {
+        let (dimensions, pixels) = load_rgba8(path)?;
+        Ok(Self::new_from_pixels(
+            setup_context,
+            dimensions,
+            &pixels,
+            &TEXTURE_2D,
+            sampler_config,
+            max_anisotropy,
+        )?)
+    }
+    // Faces in Vulkan's layer order +x, -x, +y, -y, +z, -z, all square and of the same size
+    pub fn new_cubemap_from_paths(
+        setup_context: &mut SetupContext,
+        paths: [&str; 6],
+        sampler_config: &SamplerConfig,
+        max_anisotropy: Option<f32>,
+    ) -> Result<Texture> {
+        let faces = paths
+            .iter()
+            .map(|path| load_rgba8(path))
+            .collect::<Result<Vec<_>>>()?;
+        let face_dimensions: [(u32, u32); 6] = std::array::from_fn(|face| faces[face].0);
+        let dimensions = cube_face_dimensions(&face_dimensions)
+            .ok_or_else(|| anyhow!("Cubemap faces have to be square and all the same size"))?;
+        // layers are copied from consecutive face sized ranges of the staging buffer
+        let pixels = faces
+            .into_iter()
+            .flat_map(|(_, pixels)| pixels)
+            .collect::<Vec<u8>>();
+        Ok(Self::new_from_pixels(
+            setup_context,
+            dimensions,
+            &pixels,
+            &CUBEMAP,
+            sampler_config,
+            max_anisotropy,
+        )?)
+    }
+    fn new_from_pixels(
+        setup_context: &mut SetupContext,
+        dimensions: (u32, u32),
+        pixels: &[u8],
+        kind: &TextureKind,
+        sampler_config: &SamplerConfig,
+        max_anisotropy: Option<f32>,
+    ) -> Result<Texture, DeviceLost> {
+        let device = setup_context.device;
+        let extent = vk::Extent3D {
+            width: dimensions.0,
+            height: dimensions.1,
+            depth: 1,
+        };
+        let image_create_info = vk::ImageCreateInfo::default()
+            .flags(kind.create_flags)
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(extent)
+            .mip_levels(1)
+            .format(vk::Format::R8G8B8A8_SRGB)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .array_layers(kind.layer_count);
+
+        let image = unsafe { device.create_image(&image_create_info, None).unwrap() };
+
+        let memory_reqs = unsafe { device.get_image_memory_requirements(image) };
+
+        let allocation = setup_context.memory_allocator.allocate(
+            device,
+            &memory_reqs,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+
+        unsafe {
+            device
+                .bind_image_memory(image, allocation.memory, allocation.offset)
+                .unwrap()
+        };
+
+        let mut staging_buffer = Buffer::<u8>::new(
+            device,
+            setup_context.memory_allocator,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::SharingMode::EXCLUSIVE,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            pixels.len(),
+        );
+        staging_buffer.write_data_direct(device, pixels);
+
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(kind.layer_count);
+
+        let upload = setup_context.submit(|device, command_buffer| unsafe {
+            let transfer_barrier = vk::ImageMemoryBarrier::default()
+                .image(image)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .subresource_range(subresource_range);
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[transfer_barrier],
+            );
+            let copy_region = vk::BufferImageCopy::default()
+                .image_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .layer_count(kind.layer_count),
+                )
+                .image_extent(extent);
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_buffer.buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[copy_region],
+            );
+            let shader_read_barrier = vk::ImageMemoryBarrier::default()
+                .image(image)
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .subresource_range(subresource_range);
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[shader_read_barrier],
+            );
+        });
+
+        // the staging buffer has to outlive the copy
+        let upload = upload.and_then(|()| unsafe {
+            device
+                .wait_for_fences(&[setup_context.command_buffer_reuse_fence], true, u64::MAX)
+                .map_err(|e| device_lost_or_panic(e, "Failed to wait for texture upload"))
+        });
+        staging_buffer.cleanup(device, setup_context.memory_allocator);
+        if let Err(device_lost) = upload {
+            unsafe { device.destroy_image(image, None) };
+            setup_context.memory_allocator.free(&allocation);
+            return Err(device_lost);
+        }
+
+        let image_view_info = vk::ImageViewCreateInfo::default()
+            .subresource_range(subresource_range)
+            .image(image)
+            .format(image_create_info.format)
+            .view_type(kind.view_type);
+
+        let image_view = unsafe { device.create_image_view(&image_view_info, None).unwrap() };
+
+        let [address_mode_u, address_mode_v, address_mode_w] = kind
+            .address_mode
+            .map_or(sampler_config.address_modes, |address_mode| {
+                [address_mode; 3]
+            });
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(sampler_config.mag_filter)
+            .min_filter(sampler_config.min_filter)
+            .mipmap_mode(sampler_config.mipmap_mode)
+            .address_mode_u(address_mode_u)
+            .address_mode_v(address_mode_v)
+            .address_mode_w(address_mode_w)
+            .border_color(sampler_config.border_color)
+            .anisotropy_enable(max_anisotropy.is_some())
+            .max_anisotropy(max_anisotropy.unwrap_or(1.0))
+            .max_lod(vk::LOD_CLAMP_NONE);
+
+        let sampler = unsafe { device.create_sampler(&sampler_info, None).unwrap() };
+
+        Ok(Texture {
+            image,
+            allocation,
+            image_view,
+            sampler,
+        })
+    }
+    pub fn cleanup(&self, device: &ash::Device, memory_allocator: &mut MemoryAllocator) {
+        unsafe {
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_image_view(self.image_view, None);
+            device.destroy_image(self.image, None);
+        }
+        memory_allocator.free(&self.allocation);
+    }
+}
+
+// None unless anisotropy was requested and the samplerAnisotropy feature is enabled, otherwise
+// the requested anisotropy clamped to 1.0..=maxSamplerAnisotropy
+pub fn clamped_anisotropy(
+    requested: Option<f32>,
+    sampler_anisotropy_enabled: bool,
+    max_sampler_anisotropy: f32,
+) -> Option<f32> {
+    requested
+        .filter(|_| sampler_anisotropy_enabled)
+        .map(|requested| requested.clamp(1.0, max_sampler_anisotropy))
+}
+
+// Returns (width, height) and the RGBA8 pixels
+fn load_rgba8(path: &str) -> Result<((u32, u32), Vec<u8>)> {
+    let img = ImageReader::open(path)
+        .with_context(|| format!("Failed to open texture {path}"))?
         .decode()
-        .unwrap();
-    let dimensions = img.dimensions();
-    let extent = vk::Extent3D {
-        width: dimensions.0,
-        height: dimensions.1,
-        depth: 1,
-    };
-    let image_create_info = vk::ImageCreateInfo::default()
-        .image_type(vk::ImageType::TYPE_2D)
-        .extent(extent)
-        .mip_levels(1)
-        .format(vk::Format::R8G8B8A8_SRGB)
-        .tiling(vk::ImageTiling::OPTIMAL)
-        .sharing_mode(vk::SharingMode::EXCLUSIVE)
-        .samples(vk::SampleCountFlags::TYPE_1)
-        .usage(vk::ImageUsageFlags::SAMPLED)
-        .array_layers(1);
-
-    let image = unsafe { device.create_image(&image_create_info, None).unwrap() };
-
-    let memory_reqs = unsafe { device.get_image_memory_requirements(image) };
-
-    let memtype_index = find_memorytype_index(
-        &memory_reqs,
-        physical_device_memory_properties,
-        vk::MemoryPropertyFlags::DEVICE_LOCAL,
-    )
-    .expect("failed to find memtype index");
-
-    let allocate_info = vk::MemoryAllocateInfo::default()
-        .allocation_size(memory_reqs.size)
-        .memory_type_index(memtype_index);
-
-    let memory = unsafe { device.allocate_memory(&allocate_info, None).unwrap() };
-
-    unsafe { device.bind_image_memory(image, memory, 0).unwrap() };
+        .with_context(|| format!("Failed to decode texture {path}"))?;
+    Ok((img.dimensions(), img.to_rgba8().into_raw()))
+}
+
+// Cube compatible images need square layers of one size
+fn cube_face_dimensions(face_dimensions: &[(u32, u32); 6]) -> Option<(u32, u32)> {
+    let dimensions = face_dimensions[0];
+    (dimensions.0 == dimensions.1 && face_dimensions.iter().all(|&face| face == dimensions))
+        .then_some(dimensions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cube_faces_have_to_be_square_and_equal() {
+        assert_eq!(cube_face_dimensions(&[(64, 64); 6]), Some((64, 64)));
+        assert_eq!(cube_face_dimensions(&[(64, 32); 6]), None);
+        let mut face_dimensions = [(64, 64); 6];
+        face_dimensions[5] = (32, 32);
+        assert_eq!(cube_face_dimensions(&face_dimensions), None);
+    }
+
+    #[test]
+    fn missing_texture_is_an_error() {
+        let error = load_rgba8("does/not/exist.png").unwrap_err();
+        assert!(error.to_string().contains("does/not/exist.png"));
+    }
+
+    #[test]
+    fn anisotropy_is_clamped_to_the_device_limit() {
+        assert_eq!(clamped_anisotropy(Some(32.0), true, 16.0), Some(16.0));
+        assert_eq!(clamped_anisotropy(Some(0.0), true, 16.0), Some(1.0));
+        assert_eq!(clamped_anisotropy(Some(8.0), false, 16.0), None);
+        assert_eq!(clamped_anisotropy(None, true, 16.0), None);
+    }
+
+    #[test]
+    fn border_colors_are_made_float() {
+        assert_eq!(
+            SamplerConfig::default().validated(),
+            SamplerConfig::default()
+        );
+        let config = SamplerConfig {
+            address_modes: [
+                vk::SamplerAddressMode::CLAMP_TO_BORDER,
+                vk::SamplerAddressMode::MIRROR_CLAMP_TO_EDGE,
+                vk::SamplerAddressMode::REPEAT,
+            ],
+            border_color: vk::BorderColor::INT_OPAQUE_WHITE,
+            ..Default::default()
+        };
+        let validated = config.validated();
+        assert_eq!(validated.border_color, vk::BorderColor::FLOAT_OPAQUE_WHITE);
+        assert_eq!(
+            validated.address_modes[1],
+            vk::SamplerAddressMode::CLAMP_TO_EDGE
+        );
+    }
 }
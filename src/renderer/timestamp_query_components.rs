@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use ash::vk;
+
+use super::command_buffer_components::MAX_FRAMES_IN_FLIGHT;
+
+// A start and end timestamp per frame in flight. Results are read once the frame's draw fence
+// has been waited on, the next time the frame index comes around, so reading never stalls.
+pub struct TimestampQueryComponents {
+    pub query_pool: vk::QueryPool,
+    // nanoseconds per timestamp tick
+    timestamp_period: f32,
+    timestamp_valid_bits: u32,
+    // the frame's queries have been written since they were last read
+    pending: [bool; MAX_FRAMES_IN_FLIGHT],
+    last_gpu_time: Duration,
+}
+
+impl TimestampQueryComponents {
+    // None when the graphics queue family can't write timestamps
+    pub fn new(
+        device: &ash::Device,
+        limits: &vk::PhysicalDeviceLimits,
+        graphics_queue_family_properties: &vk::QueueFamilyProperties,
+    ) -> Option<TimestampQueryComponents> {
+        // with timestampComputeAndGraphics every graphics queue supports them, otherwise the
+        // family reports zero valid bits when it doesn't
+        if limits.timestamp_compute_and_graphics == vk::FALSE
+            && graphics_queue_family_properties.timestamp_valid_bits == 0
+        {
+            eprintln!("The graphics queue does not support timestamps, GPU time is unavailable");
+            return None;
+        }
+        let query_pool_create_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(2 * MAX_FRAMES_IN_FLIGHT as u32);
+        let query_pool = unsafe {
+            device
+                .create_query_pool(&query_pool_create_info, None)
+                .expect("Failed to create timestamp query pool")
+        };
+        Some(TimestampQueryComponents {
+            query_pool,
+            timestamp_period: limits.timestamp_period,
+            timestamp_valid_bits: graphics_queue_family_properties.timestamp_valid_bits,
+            pending: [false; MAX_FRAMES_IN_FLIGHT],
+            last_gpu_time: Duration::ZERO,
+        })
+    }
+    fn first_query(frame: usize) -> u32 {
+        2 * frame as u32
+    }
+    // Call after waiting on the frame's draw fence and before recording it again
+    pub fn read_results(&mut self, device: &ash::Device, frame: usize) {
+        if !self.pending[frame] {
+            return;
+        }
+        self.pending[frame] = false;
+        let mut timestamps = [0u64; 2];
+        let result = unsafe {
+            device.get_query_pool_results(
+                self.query_pool,
+                Self::first_query(frame),
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        // NOT_READY can't happen after the fence, but keep the previous time if it does
+        if result.is_ok() {
+            self.last_gpu_time = timestamp_duration(
+                timestamps[0],
+                timestamps[1],
+                self.timestamp_valid_bits,
+                self.timestamp_period,
+            );
+        }
+    }
+    // Outside of any rendering, before the frame's first command
+    pub fn record_start(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) {
+        unsafe {
+            device.cmd_reset_query_pool(
+                command_buffer,
+                self.query_pool,
+                Self::first_query(frame),
+                2,
+            );
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                Self::first_query(frame),
+            );
+        }
+    }
+    // After the frame's last command
+    pub fn record_end(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) {
+        unsafe {
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                Self::first_query(frame) + 1,
+            )
+        };
+    }
+    // Once the frame's command buffer with both timestamps has been submitted
+    pub fn mark_pending(&mut self, frame: usize) {
+        self.pending[frame] = true;
+    }
+    pub fn last_gpu_time(&self) -> Duration {
+        self.last_gpu_time
+    }
+    pub fn cleanup(&self, device: &ash::Device) {
+        unsafe { device.destroy_query_pool(self.query_pool, None) };
+    }
+}
+
+// Only the low valid_bits of a timestamp are meaningful, the subtraction wraps within them
+fn timestamp_duration(start: u64, end: u64, valid_bits: u32, timestamp_period: f32) -> Duration {
+    let mask = match valid_bits {
+        0 | 64.. => u64::MAX,
+        _ => (1 << valid_bits) - 1,
+    };
+    let ticks = end.wrapping_sub(start) & mask;
+    Duration::from_nanos((ticks as f64 * timestamp_period as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_durations_wrap_within_the_valid_bits() {
+        assert_eq!(
+            timestamp_duration(100, 1100, 64, 1.5),
+            Duration::from_nanos(1500)
+        );
+        // the counter wrapped past 2^36 between the two timestamps
+        let start = (1 << 36) - 10;
+        assert_eq!(
+            timestamp_duration(start, 30, 36, 1.0),
+            Duration::from_nanos(40)
+        );
+    }
+}
@@ -1,93 +1,185 @@
 use ash::vk;
+use nalgebra::{Matrix3, Matrix4};
 
-use super::buffer::Buffer;
+use super::{
+    buffer::Buffer, command_buffer_components::UploadCommands, memory_allocator::MemoryAllocator,
+    DeviceLost,
+};
 
 #[derive(Clone, Copy)]
 #[repr(C)]
 pub struct Vertex {
     pub position: [f32; 3],
+    // linear, encoded to sRGB on output
     pub color: [f32; 4],
+    pub normal: [f32; 3],
+}
+
+// Everything but the position, for geometry uploaded as separate position and attribute streams
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct VertexAttribs {
+    pub color: [f32; 4],
+    pub normal: [f32; 3],
+}
+
+// Per instance data, bound with an INSTANCE input rate. The model matrix is applied before the
+// per object model matrix, as a column major array of columns like nalgebra's Matrix4.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct InstanceData {
+    pub model_matrix: [[f32; 4]; 4],
+}
+
+impl Default for InstanceData {
+    fn default() -> Self {
+        Self {
+            model_matrix: Matrix4::identity().into(),
+        }
+    }
+}
+
+// What the instance buffer holds for each InstanceData. The normal matrix is computed here once
+// per instance rather than by the vertex shader for every vertex.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct InstanceVertex {
+    pub model_matrix: [[f32; 4]; 4],
+    // inverse transpose of the model matrix's upper 3x3, column major like model_matrix
+    pub normal_matrix: [[f32; 3]; 3],
+}
+
+impl From<&InstanceData> for InstanceVertex {
+    fn from(instance: &InstanceData) -> Self {
+        let model_matrix =
+            Matrix3::from(Matrix4::from(instance.model_matrix).fixed_view::<3, 3>(0, 0));
+        Self {
+            model_matrix: instance.model_matrix,
+            // a singular matrix, e.g. a zero scale, flattens the instance, so its normals only
+            // have to stay finite
+            normal_matrix: model_matrix
+                .try_inverse()
+                .map_or(model_matrix, |inverse| inverse.transpose())
+                .into(),
+        }
+    }
 }
 
 pub const VERTICES: [Vertex; 6] = [
     Vertex {
         position: [-1.0, 1.0, 2.0],
         color: [1.0, 1.0, 0.0, 1.0],
+        normal: [0.0, 0.0, -1.0],
     },
     Vertex {
         position: [1.0, 1.0, 2.0],
         color: [1.0, 0.0, 1.0, 1.0],
+        normal: [0.0, 0.0, -1.0],
     },
     Vertex {
         position: [0.0, -1.0, 2.0],
         color: [1.0, 1.0, 0.0, 1.0],
+        normal: [0.0, 0.0, -1.0],
     },
     Vertex {
         position: [-1.0, -1.0, 3.0],
         color: [0.0, 1.0, 0.5, 1.0],
+        normal: [0.0, 0.0, -1.0],
     },
     Vertex {
         position: [1.0, -1.0, 3.0],
         color: [0.5, 0.0, 1.0, 1.0],
+        normal: [0.0, 0.0, -1.0],
     },
     Vertex {
         position: [0.0, 1.0, 3.0],
         color: [1.0, 0.5, 0.0, 1.0],
+        normal: [0.0, 0.0, -1.0],
     },
 ];
 
-pub struct VertexBufferComponents {
-    pub vertex_buffer: Buffer<Vertex>,
-    pub vertex_staging_buffer: Buffer<Vertex>,
+pub struct VertexBufferComponents<V = Vertex> {
+    pub vertex_buffer: Buffer<V>,
+    pub vertex_staging_buffer: Buffer<V>,
 }
-impl VertexBufferComponents {
+impl<V: Copy> VertexBufferComponents<V> {
     pub fn new_unintialized(
         device: &ash::Device,
-        physical_device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
-    ) -> VertexBufferComponents {
-        let vertex_buffer = Buffer::<Vertex>::new(
+        memory_allocator: &mut MemoryAllocator,
+        vertex_count: usize,
+    ) -> VertexBufferComponents<V> {
+        let vertex_buffer = Buffer::<V>::new(
             device,
-            physical_device_memory_properties,
+            memory_allocator,
             vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
             vk::SharingMode::EXCLUSIVE,
             vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            VERTICES.len(),
-            false,
+            vertex_count,
         );
-        let vertex_staging_buffer = Buffer::<Vertex>::new(
+        let vertex_staging_buffer = Buffer::<V>::new(
             device,
-            physical_device_memory_properties,
+            memory_allocator,
             vk::BufferUsageFlags::TRANSFER_SRC,
             vk::SharingMode::EXCLUSIVE,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            VERTICES.len(),
-            false,
+            vertex_count,
         );
         VertexBufferComponents {
             vertex_buffer,
             vertex_staging_buffer,
         }
     }
-    pub fn update_vertices(
+    // Room for vertex_count vertices, the current vertices are not kept
+    pub fn resize(
         &mut self,
         device: &ash::Device,
-        vertices: &[Vertex],
-        command_buffer: vk::CommandBuffer,
-        command_buffer_reuse_fence: vk::Fence,
-        queue: vk::Queue,
+        memory_allocator: &mut MemoryAllocator,
+        vertex_count: usize,
     ) {
+        self.vertex_buffer
+            .resize(device, memory_allocator, vertex_count);
+        self.vertex_staging_buffer
+            .resize(device, memory_allocator, vertex_count);
+    }
+    pub fn update_vertices(
+        &mut self,
+        device: &ash::Device,
+        vertices: &[V],
+        upload_commands: &UploadCommands,
+    ) -> Result<(), DeviceLost> {
         self.vertex_staging_buffer.write_data_direct(device, vertices);
-        self.vertex_buffer.write_from_staging(
-            &self.vertex_staging_buffer,
-            device,
-            command_buffer,
-            command_buffer_reuse_fence,
-            queue,
-        );
+        self.vertex_buffer
+            .write_from_staging(&self.vertex_staging_buffer, device, upload_commands)
     }
-    pub fn cleanup(&self, device: &ash::Device) {
-        self.vertex_buffer.cleanup(device);
-        self.vertex_staging_buffer.cleanup(device);
+    pub fn cleanup(&self, device: &ash::Device, memory_allocator: &mut MemoryAllocator) {
+        self.vertex_buffer.cleanup(device, memory_allocator);
+        self.vertex_staging_buffer.cleanup(device, memory_allocator);
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_normal_matrix_undoes_non_uniform_scale() {
+        let instance = InstanceData {
+            model_matrix: Matrix4::new_nonuniform_scaling(&[2.0, 4.0, 1.0].into()).into(),
+        };
+        let normal_matrix = Matrix3::from(InstanceVertex::from(&instance).normal_matrix);
+        assert_eq!(
+            normal_matrix,
+            Matrix3::from_diagonal(&[0.5, 0.25, 1.0].into())
+        );
+        // singular matrices keep finite normals
+        let flattened = InstanceData {
+            model_matrix: Matrix4::new_nonuniform_scaling(&[1.0, 0.0, 1.0].into()).into(),
+        };
+        let normal_matrix = InstanceVertex::from(&flattened).normal_matrix;
+        assert!(normal_matrix
+            .iter()
+            .flatten()
+            .all(|value| value.is_finite()));
+    }
+}
@@ -0,0 +1,170 @@
+// serde(with) modules for the ash types in UserSettings, which have no serde support of their
+// own. Values are stored as their raw Vulkan integers, only the values listed for each type are
+// accepted when loading.
+use ash::vk;
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+macro_rules! raw_value {
+    ($($module:ident: $vk_type:ty => $raw_type:ty [$($value:ident),* $(,)?]),* $(,)?) => {$(
+        pub mod $module {
+            use super::*;
+
+            pub const KNOWN_VALUES: &[$vk_type] = &[$(<$vk_type>::$value),*];
+
+            pub fn serialize<S: Serializer>(
+                value: &$vk_type,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                value.as_raw().serialize(serializer)
+            }
+            pub fn deserialize<'de, D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<$vk_type, D::Error> {
+                let raw = <$raw_type>::deserialize(deserializer)?;
+                let value = <$vk_type>::from_raw(raw);
+                if !KNOWN_VALUES.contains(&value) {
+                    return Err(D::Error::custom(format!(
+                        "{raw} is not a valid {}",
+                        stringify!($vk_type)
+                    )));
+                }
+                Ok(value)
+            }
+        }
+    )*};
+}
+
+raw_value!(
+    compare_op: vk::CompareOp => i32 [
+        NEVER,
+        LESS,
+        EQUAL,
+        LESS_OR_EQUAL,
+        GREATER,
+        NOT_EQUAL,
+        GREATER_OR_EQUAL,
+        ALWAYS,
+    ],
+    polygon_mode: vk::PolygonMode => i32 [FILL, LINE, POINT],
+    cull_mode_flags: vk::CullModeFlags => u32 [NONE, FRONT, BACK, FRONT_AND_BACK],
+    front_face: vk::FrontFace => i32 [COUNTER_CLOCKWISE, CLOCKWISE],
+    // patch lists need tessellation shaders, which the renderer doesn't have
+    primitive_topology: vk::PrimitiveTopology => i32 [
+        POINT_LIST,
+        LINE_LIST,
+        LINE_STRIP,
+        TRIANGLE_LIST,
+        TRIANGLE_STRIP,
+        TRIANGLE_FAN,
+        LINE_LIST_WITH_ADJACENCY,
+        LINE_STRIP_WITH_ADJACENCY,
+        TRIANGLE_LIST_WITH_ADJACENCY,
+        TRIANGLE_STRIP_WITH_ADJACENCY,
+    ],
+    line_rasterization_mode: vk::LineRasterizationModeEXT => i32 [
+        DEFAULT,
+        RECTANGULAR,
+        BRESENHAM,
+        RECTANGULAR_SMOOTH,
+    ],
+    // a single power of two sample count
+    sample_count_flags: vk::SampleCountFlags => u32 [
+        TYPE_1,
+        TYPE_2,
+        TYPE_4,
+        TYPE_8,
+        TYPE_16,
+        TYPE_32,
+        TYPE_64,
+    ],
+    filter: vk::Filter => i32 [NEAREST, LINEAR],
+    sampler_mipmap_mode: vk::SamplerMipmapMode => i32 [NEAREST, LINEAR],
+    border_color: vk::BorderColor => i32 [
+        FLOAT_TRANSPARENT_BLACK,
+        INT_TRANSPARENT_BLACK,
+        FLOAT_OPAQUE_BLACK,
+        INT_OPAQUE_BLACK,
+        FLOAT_OPAQUE_WHITE,
+        INT_OPAQUE_WHITE,
+    ],
+);
+
+// MIRROR_CLAMP_TO_EDGE is accepted, SamplerConfig::validated replaces it
+const KNOWN_ADDRESS_MODES: [vk::SamplerAddressMode; 5] = [
+    vk::SamplerAddressMode::REPEAT,
+    vk::SamplerAddressMode::MIRRORED_REPEAT,
+    vk::SamplerAddressMode::CLAMP_TO_EDGE,
+    vk::SamplerAddressMode::CLAMP_TO_BORDER,
+    vk::SamplerAddressMode::MIRROR_CLAMP_TO_EDGE,
+];
+
+pub mod sampler_address_modes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        address_modes: &[vk::SamplerAddressMode; 3],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        address_modes
+            .map(|address_mode| address_mode.as_raw())
+            .serialize(serializer)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[vk::SamplerAddressMode; 3], D::Error> {
+        let raw_address_modes = <[i32; 3]>::deserialize(deserializer)?;
+        let address_modes = raw_address_modes.map(vk::SamplerAddressMode::from_raw);
+        if let Some(raw) = raw_address_modes
+            .iter()
+            .zip(&address_modes)
+            .find(|(_, address_mode)| !KNOWN_ADDRESS_MODES.contains(address_mode))
+            .map(|(raw, _)| raw)
+        {
+            return Err(D::Error::custom(format!(
+                "{raw} is not a valid vk::SamplerAddressMode"
+            )));
+        }
+        Ok(address_modes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::SamplerConfig;
+
+    #[test]
+    fn sampler_config_round_trips_through_json() {
+        let sampler_config = SamplerConfig {
+            mag_filter: vk::Filter::NEAREST,
+            address_modes: [
+                vk::SamplerAddressMode::CLAMP_TO_BORDER,
+                vk::SamplerAddressMode::REPEAT,
+                vk::SamplerAddressMode::MIRRORED_REPEAT,
+            ],
+            ..SamplerConfig::default()
+        };
+        let json = serde_json::to_string(&sampler_config).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SamplerConfig>(&json).unwrap(),
+            sampler_config
+        );
+    }
+
+    #[test]
+    fn unknown_raw_values_are_rejected() {
+        #[derive(serde::Deserialize)]
+        struct MsaaSamples(#[serde(with = "sample_count_flags")] vk::SampleCountFlags);
+        assert_eq!(
+            serde_json::from_str::<MsaaSamples>("4").unwrap().0,
+            vk::SampleCountFlags::TYPE_4
+        );
+        // not a power of two
+        assert!(serde_json::from_str::<MsaaSamples>("3").is_err());
+        assert!(serde_json::from_str::<MsaaSamples>("128").is_err());
+
+        let mut json = serde_json::to_value(SamplerConfig::default()).unwrap();
+        json["address_modes"][1] = 5.into();
+        assert!(serde_json::from_value::<SamplerConfig>(json).is_err());
+    }
+}
@@ -1,141 +1,74 @@
-use std::{
-    borrow::Cow,
-    ffi::{c_char, CStr},
-    sync::Arc,
-};
+use std::ops::{Deref, DerefMut};
 
-use ash::{
-    ext::debug_utils,
-    khr::{surface, swapchain},
-    vk::{self, PhysicalDeviceType},
-};
-use winit::{
-    raw_window_handle::{HasDisplayHandle, HasWindowHandle},
-    window::WindowAttributes,
-};
+use ash::vk;
 
+use crate::renderer::{Renderer, UserSettings};
+
+// Extent of the headless renderer's color image, fixture tests don't draw into it
+const EXTENT: u32 = 16;
+
+// A headless Renderer kept alive for device backed unit tests, e.g. memory type selection and
+// staging copies. Derefs to the renderer for its device, queue family indices, memory
+// properties and run_setup. Without the shaderc feature the default shader directory needs the
+// compiled SPIR-V, the same as for the app.
 pub struct TestRenderer {
-    window: Arc<winit::window::Window>,
-    pub instance: ash::Instance,
-    pub device: ash::Device,
-    entry: ash::Entry,
+    renderer: Renderer,
 }
 
 impl TestRenderer {
-    pub fn new(event_loop: &winit::event_loop::ActiveEventLoop) -> Self {
-        let window = Arc::new(
-            event_loop
-                .create_window(WindowAttributes::default())
-                .expect("Failed to create winit window"),
-        );
-
-        let entry = unsafe { ash::Entry::load().unwrap() };
-
-        let mut extension_names =
-            ash_window::enumerate_required_extensions(window.display_handle().unwrap().as_raw())
-                .unwrap()
-                .to_vec();
-        extension_names.push(ash::ext::debug_utils::NAME.as_ptr());
-
-        let application_info = vk::ApplicationInfo::default().api_version(vk::API_VERSION_1_3);
-
-        let instance_create_info = vk::InstanceCreateInfo::default()
-            .application_info(&application_info)
-            .enabled_extension_names(&extension_names);
-
-        let instance = unsafe { entry.create_instance(&instance_create_info, None).unwrap() };
-
-        let surface = unsafe {
-            ash_window::create_surface(
-                &entry,
-                &instance,
-                window.display_handle().unwrap().as_raw(),
-                window.window_handle().unwrap().as_raw(),
-                None,
-            )
-            .unwrap()
-        };
-
-        let physical_devices = unsafe { instance.enumerate_physical_devices().unwrap() };
-
-        let surface_loader = surface::Instance::new(&entry, &instance);
-
-        let (queue_family_index, physical_device) = physical_devices
-            .iter()
-            .filter_map(|physical_device| unsafe {
-                instance
-                    .get_physical_device_queue_family_properties(*physical_device)
-                    .iter()
-                    .enumerate()
-                    .find_map(|(index, info)| {
-                        let supports_graphics_and_surface =
-                            info.queue_flags.contains(vk::QueueFlags::GRAPHICS)
-                                && surface_loader
-                                    .get_physical_device_surface_support(
-                                        *physical_device,
-                                        index as u32,
-                                        surface,
-                                    )
-                                    .unwrap();
-                        if supports_graphics_and_surface {
-                            Some((index as u32, *physical_device))
-                        } else {
-                            None
-                        }
-                    })
-            })
-            .max_by_key(|(_index, physical_device)| {
-                let device_properties =
-                    unsafe { instance.get_physical_device_properties(*physical_device) };
-                let mut score = 0;
-                match device_properties.device_type {
-                    PhysicalDeviceType::DISCRETE_GPU => score += 1000,
-                    PhysicalDeviceType::INTEGRATED_GPU => score += 100,
-                    PhysicalDeviceType::VIRTUAL_GPU => score += 10,
-                    PhysicalDeviceType::CPU => score += 1,
-                    _ => (),
-                }
-                score += device_properties.limits.max_image_dimension2_d;
-                score
-            })
-            .expect("No supported physical device found");
-
-        let device_extension_names_raw = [swapchain::NAME.as_ptr()];
-
-        let features = vk::PhysicalDeviceFeatures {
-            shader_clip_distance: 1,
-            ..Default::default()
-        };
-
-        let mut dynamic_rendering_features =
-            vk::PhysicalDeviceDynamicRenderingFeatures::default().dynamic_rendering(true);
-
-        let priorities = [1.0];
-
-        let queue_info = vk::DeviceQueueCreateInfo::default()
-            .queue_family_index(queue_family_index)
-            .queue_priorities(&priorities);
-
-        let device_create_info = vk::DeviceCreateInfo::default()
-            .queue_create_infos(std::slice::from_ref(&queue_info))
-            .enabled_extension_names(&device_extension_names_raw)
-            .push_next(&mut dynamic_rendering_features)
-            .enabled_features(&features);
+    // None when there is no Vulkan 1.3 device, so tests can skip instead of failing on machines
+    // without a GPU or software rasterizer
+    pub fn new() -> Option<TestRenderer> {
+        if !vulkan_1_3_device_present() {
+            eprintln!("No Vulkan 1.3 device, skipping device backed test");
+            return None;
+        }
+        Some(TestRenderer {
+            renderer: Renderer::new_headless(EXTENT, EXTENT, &UserSettings::default()),
+        })
+    }
+    pub fn limits(&self) -> vk::PhysicalDeviceLimits {
+        unsafe {
+            self.renderer
+                .instance()
+                .get_physical_device_properties(self.renderer.physical_device())
+        }
+        .limits
+    }
+}
 
-        let device = unsafe {
-            instance
-                .create_device(physical_device, &device_create_info, None)
-                .unwrap()
-        };
+impl Deref for TestRenderer {
+    type Target = Renderer;
 
-        unsafe { device.destroy_device(None) };
-        eprintln!("DESTROYED");
+    fn deref(&self) -> &Renderer {
+        &self.renderer
+    }
+}
 
-        Self {
-            window,
-            instance,
-            device,
-            entry,
-        }
+impl DerefMut for TestRenderer {
+    fn deref_mut(&mut self) -> &mut Renderer {
+        &mut self.renderer
     }
 }
+
+fn vulkan_1_3_device_present() -> bool {
+    let Ok(entry) = (unsafe { ash::Entry::load() }) else {
+        return false;
+    };
+    let application_info = vk::ApplicationInfo::default().api_version(vk::API_VERSION_1_3);
+    let instance_create_info =
+        vk::InstanceCreateInfo::default().application_info(&application_info);
+    let Ok(instance) = (unsafe { entry.create_instance(&instance_create_info, None) }) else {
+        return false;
+    };
+    let present = unsafe { instance.enumerate_physical_devices() }
+        .unwrap_or_default()
+        .iter()
+        .any(|&physical_device| {
+            let api_version =
+                unsafe { instance.get_physical_device_properties(physical_device) }.api_version;
+            vk::api_version_minor(api_version) >= 3 || vk::api_version_major(api_version) > 1
+        });
+    unsafe { instance.destroy_instance(None) };
+    present
+}